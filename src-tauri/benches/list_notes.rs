@@ -0,0 +1,52 @@
+// Streaming notes out of a 50k-note synthetic vault, to check that list_notes_stream stays
+// fast and only ever holds one batch in memory however large the vault is.
+//
+// Run with `cargo bench --bench list_notes`.
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use minimal_notes_lib::commands::stream_notes;
+use std::path::Path;
+
+const VAULT_NOTES: usize = 50_000;
+
+// Write `count` JSON notes of a few paragraphs each into `dir`
+fn synthetic_vault(dir: &Path, count: usize) {
+    let paragraph = "Lorem ipsum dolor sit amet, consectetur adipiscing elit, sed do eiusmod tempor. ";
+    for i in 0..count {
+        let id = format!("note-{:05}", i);
+        let note = serde_json::json!({
+            "id": id,
+            "title": format!("Synthetic note {}", i),
+            "content": paragraph.repeat(1 + i % 8),
+            "created_at": "2024-01-01T00:00:00+00:00",
+            "updated_at": "2024-01-01T00:00:00+00:00",
+        });
+        std::fs::write(dir.join(format!("{}.json", id)), note.to_string()).unwrap();
+    }
+}
+
+fn bench_stream_notes(c: &mut Criterion) {
+    let vault = tempfile::tempdir().unwrap();
+    synthetic_vault(vault.path(), VAULT_NOTES);
+
+    let mut group = c.benchmark_group("stream_notes_50k");
+    group.sample_size(10);
+    for batch_size in [200, 1000] {
+        group.bench_function(format!("batch_{}", batch_size), |b| {
+            b.iter(|| {
+                let mut largest = 0;
+                let sent = stream_notes(vault.path(), batch_size, None, |batch| {
+                    largest = largest.max(batch.notes.len());
+                    Ok(())
+                })
+                .unwrap();
+                assert_eq!(sent, VAULT_NOTES);
+                assert!(largest <= batch_size);
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_stream_notes);
+criterion_main!(benches);
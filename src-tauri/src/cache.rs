@@ -0,0 +1,248 @@
+use crate::{Note, NoteState};
+use lru::LruCache;
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::num::NonZeroUsize;
+use std::sync::Mutex;
+
+// Upper bounds for the parsed-note cache
+const MAX_CACHED_NOTES: usize = 256;
+const MAX_CACHED_BYTES: usize = 16 * 1024 * 1024;
+
+// Global cache of parsed notes keyed by id, shared across commands
+pub static NOTE_CACHE: Lazy<Mutex<NoteCache>> = Lazy::new(|| Mutex::new(NoteCache::new(MAX_CACHED_NOTES, MAX_CACHED_BYTES)));
+
+// LRU cache of parsed notes bounded by both entry count and approximate total size
+pub struct NoteCache {
+    entries: LruCache<String, Note>,
+    total_bytes: usize,
+    max_bytes: usize,
+}
+
+impl NoteCache {
+    pub fn new(max_entries: usize, max_bytes: usize) -> Self {
+        NoteCache {
+            entries: LruCache::new(NonZeroUsize::new(max_entries).unwrap_or(NonZeroUsize::MIN)),
+            total_bytes: 0,
+            max_bytes,
+        }
+    }
+
+    pub fn get(&mut self, id: &str) -> Option<Note> {
+        self.entries.get(id).cloned()
+    }
+
+    pub fn insert(&mut self, note: Note) {
+        let size = note_size(&note);
+        // Don't let a single huge note flush everything else
+        if size > self.max_bytes {
+            self.invalidate(&note.id);
+            return;
+        }
+        if let Some((_, evicted)) = self.entries.push(note.id.clone(), note) {
+            self.total_bytes -= note_size(&evicted);
+        }
+        self.total_bytes += size;
+        while self.total_bytes > self.max_bytes {
+            match self.entries.pop_lru() {
+                Some((_, evicted)) => self.total_bytes -= note_size(&evicted),
+                None => break,
+            }
+        }
+    }
+
+    pub fn invalidate(&mut self, id: &str) {
+        if let Some(removed) = self.entries.pop(id) {
+            self.total_bytes -= note_size(&removed);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.total_bytes = 0;
+    }
+}
+
+// The note `id` from `cache`, or else from `load`, caching what it returns. The cache isn't
+// locked while `load` reads the note, so other lookups aren't held up by the disk.
+pub fn cached_or_load(cache: &Mutex<NoteCache>, id: &str, load: impl FnOnce() -> Result<Note, String>) -> Result<Note, String> {
+    if let Some(note) = cache.lock().ok().and_then(|mut cache| cache.get(id)) {
+        return Ok(note);
+    }
+    let note = load()?;
+    if let Ok(mut cache) = cache.lock() {
+        cache.insert(note.clone());
+    }
+    Ok(note)
+}
+
+fn note_size(note: &Note) -> usize {
+    note.id.len()
+        + note.title.len()
+        + note.content.len()
+        + note.created_at.len()
+        + note.updated_at.len()
+        + note.metadata.iter().map(|(k, v)| k.len() + v.len()).sum::<usize>()
+}
+
+// Title and last update of a note, as kept in the title index
+#[derive(Clone, Debug, PartialEq)]
+pub struct TitleEntry {
+    pub id: String,
+    pub title: String,
+    pub updated_at: String,
+}
+
+// Titles of the notes that aren't trashed, so title lookups don't parse every file
+#[derive(Default)]
+pub struct TitleIndex {
+    entries: HashMap<String, TitleEntry>,
+}
+
+impl TitleIndex {
+    pub fn from_notes<'a>(notes: impl IntoIterator<Item = &'a Note>) -> Self {
+        let mut index = TitleIndex::default();
+        notes.into_iter().for_each(|note| index.update(note));
+        index
+    }
+
+    pub fn update(&mut self, note: &Note) {
+        if note.state == NoteState::Trashed {
+            self.entries.remove(&note.id);
+            return;
+        }
+        let entry = TitleEntry { id: note.id.clone(), title: note.title.clone(), updated_at: note.updated_at.clone() };
+        self.entries.insert(note.id.clone(), entry);
+    }
+
+    pub fn remove(&mut self, id: &str) {
+        self.entries.remove(id);
+    }
+
+    // Every entry, most recently updated first
+    pub fn recent_first(&self) -> Vec<TitleEntry> {
+        let mut entries: Vec<TitleEntry> = self.entries.values().cloned().collect();
+        entries.sort_by(|a, b| b.updated_at.cmp(&a.updated_at));
+        entries
+    }
+}
+
+// Built from the vault on first use (see titles), then kept current as notes are saved and
+// deleted. None until then, and again after a vault switch.
+static TITLE_INDEX: Lazy<Mutex<Option<TitleIndex>>> = Lazy::new(|| Mutex::new(None));
+
+// Titles of the notes that aren't trashed, most recently updated first. `load` lists the
+// vault's notes if the index hasn't been built yet.
+pub fn titles(load: impl FnOnce() -> Vec<Note>) -> Vec<TitleEntry> {
+    let mut index = TITLE_INDEX.lock().unwrap_or_else(|e| e.into_inner());
+    index.get_or_insert_with(|| TitleIndex::from_notes(&load())).recent_first()
+}
+
+// Bring a saved note's entry up to date, if the index has been built
+pub fn track_title(note: &Note) {
+    if let Some(index) = TITLE_INDEX.lock().unwrap_or_else(|e| e.into_inner()).as_mut() {
+        index.update(note);
+    }
+}
+
+// Drop a deleted note's entry
+pub fn forget_title(id: &str) {
+    if let Some(index) = TITLE_INDEX.lock().unwrap_or_else(|e| e.into_inner()).as_mut() {
+        index.remove(id);
+    }
+}
+
+// Forget the title index so it's rebuilt from the notes on next use
+pub fn reset_titles() {
+    *TITLE_INDEX.lock().unwrap_or_else(|e| e.into_inner()) = None;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn note(id: &str, title: &str, updated_at: &str) -> Note {
+        Note { id: id.to_string(), title: title.to_string(), updated_at: updated_at.to_string(), ..Default::default() }
+    }
+
+    fn sized(id: &str, content_bytes: usize) -> Note {
+        Note { id: id.to_string(), content: "x".repeat(content_bytes), ..Default::default() }
+    }
+
+    #[test]
+    fn second_lookup_is_served_from_the_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("a.json");
+        std::fs::write(&path, "On disk").unwrap();
+        let cache = Mutex::new(NoteCache::new(8, 1024));
+        let reads = std::cell::Cell::new(0);
+        let load = || {
+            reads.set(reads.get() + 1);
+            let content = std::fs::read_to_string(&path).map_err(|e| e.to_string())?;
+            Ok(Note { id: "a".to_string(), content, ..Default::default() })
+        };
+
+        assert_eq!(cached_or_load(&cache, "a", load).unwrap().content, "On disk");
+        // With the file changed and then gone, the cached copy is still what's returned
+        std::fs::write(&path, "Changed").unwrap();
+        assert_eq!(cached_or_load(&cache, "a", load).unwrap().content, "On disk");
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(cached_or_load(&cache, "a", load).unwrap().content, "On disk");
+        assert_eq!(reads.get(), 1);
+
+        // Once invalidated the note is read again, and now it's missing
+        cache.lock().unwrap().invalidate("a");
+        assert!(cached_or_load(&cache, "a", load).is_err());
+        assert_eq!(reads.get(), 2);
+    }
+
+    #[test]
+    fn inserts_evict_least_recently_used_notes_past_the_byte_limit() {
+        let mut cache = NoteCache::new(8, 250);
+        cache.insert(sized("a", 100));
+        cache.insert(sized("b", 100));
+        // Using "a" leaves "b" as the least recently used
+        assert!(cache.get("a").is_some());
+        cache.insert(sized("c", 100));
+        assert!(cache.get("b").is_none());
+        assert!(cache.get("a").is_some() && cache.get("c").is_some());
+        assert_eq!(cache.total_bytes, 2 * note_size(&sized("a", 100)));
+    }
+
+    #[test]
+    fn notes_larger_than_the_cache_are_not_kept() {
+        let mut cache = NoteCache::new(8, 250);
+        cache.insert(sized("a", 100));
+        cache.insert(sized("big", 300));
+        assert!(cache.get("big").is_none());
+        assert!(cache.get("a").is_some());
+        // A note that grows too big drops its old cached copy too
+        cache.insert(sized("a", 300));
+        assert!(cache.get("a").is_none());
+        assert_eq!(cache.total_bytes, 0);
+    }
+
+    fn titles(index: &TitleIndex) -> Vec<String> {
+        index.recent_first().into_iter().map(|entry| entry.title).collect()
+    }
+
+    #[test]
+    fn title_index_lists_recent_first_and_follows_edits() {
+        let mut index = TitleIndex::from_notes(&[note("a", "Alpha", "2024-01-01"), note("b", "Beta", "2024-02-01")]);
+        assert_eq!(titles(&index), ["Beta", "Alpha"]);
+        index.update(&note("a", "Alpha renamed", "2024-03-01"));
+        assert_eq!(titles(&index), ["Alpha renamed", "Beta"]);
+        index.remove("b");
+        assert_eq!(titles(&index), ["Alpha renamed"]);
+    }
+
+    #[test]
+    fn title_index_leaves_out_trashed_notes() {
+        let trashed = Note { state: NoteState::Trashed, ..note("a", "Alpha", "2024-01-01") };
+        let mut index = TitleIndex::from_notes(&[trashed.clone(), note("b", "Beta", "2024-02-01")]);
+        assert_eq!(titles(&index), ["Beta"]);
+        index.update(&note("a", "Alpha", "2024-03-01"));
+        index.update(&Note { state: NoteState::Trashed, ..note("b", "Beta", "2024-02-01") });
+        assert_eq!(titles(&index), ["Alpha"]);
+    }
+}
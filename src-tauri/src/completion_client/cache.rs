@@ -0,0 +1,198 @@
+use super::gemini_client::GenerationConfig;
+use lru::LruCache;
+use serde::{Deserialize, Serialize};
+use std::num::NonZeroUsize;
+use std::time::{Duration, Instant};
+
+// User-tunable settings for the completion cache
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+#[serde(default)]
+pub struct CompletionCacheSettings {
+    pub enabled: bool,
+    pub max_entries: usize,
+    // How long a deterministic (temperature 0) completion is reused
+    pub ttl_secs: u64,
+    // How long a sampled (temperature above 0) completion is reused. Kept short so that
+    // retyping the same text soon after gets the same suggestion, but it still varies later.
+    pub sampled_ttl_secs: u64,
+    // Only cache deterministic requests, so sampling always varies
+    pub deterministic_only: bool,
+}
+
+impl Default for CompletionCacheSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            max_entries: 128,
+            ttl_secs: 60,
+            sampled_ttl_secs: 10,
+            deterministic_only: false,
+        }
+    }
+}
+
+impl CompletionCacheSettings {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.max_entries == 0 {
+            return Err("max_entries must be positive".to_string());
+        }
+        Ok(())
+    }
+
+    // How long a completion made at this temperature may be served from the cache, or None
+    // if it shouldn't be cached at all
+    pub fn ttl_for(&self, temperature: f32) -> Option<Duration> {
+        if !self.enabled {
+            return None;
+        }
+        if temperature == 0.0 {
+            Some(Duration::from_secs(self.ttl_secs))
+        } else if self.deterministic_only {
+            None
+        } else {
+            Some(Duration::from_secs(self.sampled_ttl_secs))
+        }
+    }
+}
+
+// Everything that affects the raw model output for a completion request
+#[derive(Hash, PartialEq, Eq, Clone, Debug)]
+pub struct CompletionKey {
+    model: String,
+    // The system instruction as sent, global prompt prefix and suffix included
+    instruction: String,
+    prompt: String,
+    context: Option<String>,
+    max_tokens: Option<i32>,
+    // Stored as bits so the key can be hashed
+    temperature: Option<u32>,
+    stop_sequences: Option<Vec<String>>,
+    thinking_budget: Option<i32>,
+}
+
+impl CompletionKey {
+    pub fn new(model: &str, instruction: &str, prompt: &str, context: Option<&str>, generation_config: &GenerationConfig) -> Self {
+        CompletionKey {
+            model: model.to_string(),
+            instruction: instruction.to_string(),
+            prompt: prompt.to_string(),
+            context: context.map(str::to_string),
+            max_tokens: generation_config.max_output_tokens,
+            temperature: generation_config.temperature.map(f32::to_bits),
+            stop_sequences: generation_config.stop_sequences.clone(),
+            thinking_budget: generation_config.thinking_config.as_ref().map(|thinking| thinking.thinking_budget),
+        }
+    }
+}
+
+// LRU cache of raw completions, each expiring after the TTL it was stored with
+pub struct CompletionCache {
+    entries: LruCache<CompletionKey, (Instant, String)>,
+}
+
+impl CompletionCache {
+    pub fn new(settings: &CompletionCacheSettings) -> Self {
+        CompletionCache {
+            entries: LruCache::new(NonZeroUsize::new(settings.max_entries).unwrap_or(NonZeroUsize::MIN)),
+        }
+    }
+
+    // Apply new settings, evicting the oldest entries if the cache shrank. New TTLs apply to
+    // entries stored from now on.
+    pub fn configure(&mut self, settings: &CompletionCacheSettings) {
+        self.entries.resize(NonZeroUsize::new(settings.max_entries).unwrap_or(NonZeroUsize::MIN));
+    }
+
+    pub fn get(&mut self, key: &CompletionKey) -> Option<String> {
+        match self.entries.get(key) {
+            Some((expires_at, text)) if Instant::now() < *expires_at => Some(text.clone()),
+            Some(_) => {
+                self.entries.pop(key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    pub fn insert(&mut self, key: CompletionKey, text: String, ttl: Duration) {
+        self.entries.put(key, (Instant::now() + ttl, text));
+    }
+
+    // Drop every entry, returning how many there were
+    pub fn clear(&mut self) -> usize {
+        let count = self.entries.len();
+        self.entries.clear();
+        count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::completion_client::gemini_client::ThinkingConfig;
+
+    fn config() -> GenerationConfig {
+        GenerationConfig {
+            max_output_tokens: Some(16),
+            temperature: Some(0.0),
+            thinking_config: None,
+            stop_sequences: None,
+            response_mime_type: None,
+            response_schema: None,
+        }
+    }
+
+    fn key(model: &str, instruction: &str) -> CompletionKey {
+        CompletionKey::new(model, instruction, "prompt", None, &config())
+    }
+
+    #[test]
+    fn keys_differ_by_model_and_instruction() {
+        assert_eq!(key("m", "i"), key("m", "i"));
+        assert_ne!(key("m", "i"), key("other", "i"));
+        // e.g. a changed global prompt prefix
+        assert_ne!(key("m", "i"), key("m", "Be terse.\n\ni"));
+    }
+
+    #[test]
+    fn keys_differ_by_stop_sequences_and_thinking_budget() {
+        let stopped = GenerationConfig { stop_sequences: Some(vec!["\n".to_string()]), ..config() };
+        assert_ne!(key("m", "i"), CompletionKey::new("m", "i", "prompt", None, &stopped));
+        let thinking = GenerationConfig { thinking_config: Some(ThinkingConfig { thinking_budget: 1024 }), ..config() };
+        assert_ne!(key("m", "i"), CompletionKey::new("m", "i", "prompt", None, &thinking));
+    }
+
+    #[test]
+    fn hits_until_the_entry_expires() {
+        let mut cache = CompletionCache::new(&CompletionCacheSettings::default());
+        assert_eq!(cache.get(&key("m", "i")), None);
+        cache.insert(key("m", "i"), "done".to_string(), Duration::from_secs(60));
+        assert_eq!(cache.get(&key("m", "i")).as_deref(), Some("done"));
+        assert_eq!(cache.get(&key("m", "other")), None);
+
+        cache.insert(key("m", "i"), "stale".to_string(), Duration::ZERO);
+        assert_eq!(cache.get(&key("m", "i")), None);
+        assert_eq!(cache.clear(), 0);
+    }
+
+    #[test]
+    fn evicts_the_least_recently_used() {
+        let settings = CompletionCacheSettings { max_entries: 1, ..CompletionCacheSettings::default() };
+        let mut cache = CompletionCache::new(&settings);
+        cache.insert(key("m", "a"), "a".to_string(), Duration::from_secs(60));
+        cache.insert(key("m", "b"), "b".to_string(), Duration::from_secs(60));
+        assert_eq!(cache.get(&key("m", "a")), None);
+        assert_eq!(cache.get(&key("m", "b")).as_deref(), Some("b"));
+    }
+
+    #[test]
+    fn sampled_completions_are_cached_briefly_by_default() {
+        let settings = CompletionCacheSettings::default();
+        assert_eq!(settings.ttl_for(0.0), Some(Duration::from_secs(settings.ttl_secs)));
+        assert_eq!(settings.ttl_for(0.7), Some(Duration::from_secs(settings.sampled_ttl_secs)));
+        let strict = CompletionCacheSettings { deterministic_only: true, ..settings };
+        assert_eq!(strict.ttl_for(0.7), None);
+        let off = CompletionCacheSettings { enabled: false, ..settings };
+        assert_eq!(off.ttl_for(0.0), None);
+    }
+}
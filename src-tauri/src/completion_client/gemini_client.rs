@@ -35,6 +35,16 @@ pub struct GenerationConfig {
     #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(rename = "thinkingConfig")]
     pub thinking_config: Option<ThinkingConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stop_sequences: Option<Vec<String>>,
+    // Set to "application/json" to have the model answer in JSON
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "responseMimeType")]
+    pub response_mime_type: Option<String>,
+    // OpenAPI-style schema the JSON answer must follow
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "responseSchema")]
+    pub response_schema: Option<serde_json::Value>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -45,22 +55,184 @@ pub struct GeminiResponse {
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Candidate {
     pub content: Content,
-    #[serde(rename = "finishReason")]
-    #[allow(dead_code)]
+    #[serde(rename = "finishReason", default)]
     pub finish_reason: String,
     #[allow(dead_code)]
     pub index: u32,
 }
 
+// Retries after a 429 before giving up with RateLimited
+const MAX_RATE_LIMIT_RETRIES: u32 = 2;
+
+// Longest Retry-After we'll wait out inside a request; longer ones go straight to the caller
+const MAX_RETRY_WAIT_SECS: u64 = 30;
+
+// Returned (inside anyhow::Error) when the API is still throttling after all retries
+#[derive(Debug, Clone)]
+pub struct RateLimited {
+    pub retry_after_secs: u64,
+}
+
+impl std::fmt::Display for RateLimited {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Gemini API rate limit hit, retry after {} seconds", self.retry_after_secs)
+    }
+}
+
+impl std::error::Error for RateLimited {}
+
+// Parse a Retry-After header, given either as seconds or as an HTTP date
+pub fn parse_retry_after(value: &str) -> Option<u64> {
+    let value = value.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(secs);
+    }
+    let at = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    Some((at.timestamp() - chrono::Utc::now().timestamp()).max(0) as u64)
+}
+
+// An autocomplete instruction extended with the text after the cursor, which the
+// continuation has to lead into
+pub fn with_suffix_hint(instruction: &str, suffix: &str) -> String {
+    format!("{}\n\nThe text right after the cursor is:\n{}\nYour continuation must lead naturally into it and must not repeat it.", instruction, suffix)
+}
+
+// Finish reason of a reply that was cut off by max_output_tokens
+pub const MAX_TOKENS_FINISH_REASON: &str = "MAX_TOKENS";
+
+// Returned in place of a reply that came back without any text
+const EMPTY_REPLY: &str = "...";
+
+// Sent after a truncated reply to have the model pick up where it stopped
+const CONTINUE_INSTRUCTION: &str = "Continue exactly where your previous reply stopped. Do not repeat any of it or add any preamble.";
+
+// The text of one reply and why the model stopped generating it
+#[derive(Debug, Clone)]
+pub struct Generation {
+    pub text: String,
+    pub finish_reason: String,
+}
+
+impl Generation {
+    pub fn was_truncated(&self) -> bool {
+        self.finish_reason == MAX_TOKENS_FINISH_REASON
+    }
+}
+
+// Keep requesting while replies are cut off by the token limit, up to `max_continuations`
+// extra requests, and return the replies joined together. `request` gets the output so far
+// for every request after the first. A truncated reply with no text (e.g. the budget went
+// on thinking) ends it, since there's nothing for the model to continue from.
+pub fn continue_until_done<F>(max_continuations: usize, mut request: F) -> Result<String>
+where
+    F: FnMut(Option<&str>) -> Result<Generation>,
+{
+    let mut output = String::new();
+    for attempt in 0..=max_continuations {
+        let generation = request((attempt > 0).then_some(output.as_str()))?;
+        output.push_str(&generation.text);
+        if !generation.was_truncated() || generation.text.is_empty() {
+            return Ok(output);
+        }
+        debug!("Reply truncated at {} characters, continuing", output.len());
+    }
+    warn!("Reply still truncated after {} continuations", max_continuations);
+    Ok(output)
+}
+
+// Model metadata from the Gemini models endpoint
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ModelInfo {
+    pub name: String,
+    #[serde(rename(deserialize = "displayName"), default)]
+    pub display_name: String,
+    #[serde(rename(deserialize = "inputTokenLimit"), default)]
+    pub input_token_limit: u32,
+    #[serde(rename(deserialize = "outputTokenLimit"), default)]
+    pub output_token_limit: u32,
+    #[serde(rename(deserialize = "supportedGenerationMethods"), default)]
+    pub supported_methods: Vec<String>,
+}
+
+// Outcome of one step of a connectivity check
+#[derive(Serialize, Debug, Clone)]
+pub struct ConnectivityPhase {
+    pub phase: String,
+    pub ok: bool,
+    pub duration_ms: u64,
+    pub detail: String,
+}
+
+// Step-by-step connectivity check against the Gemini host. Stops at the first failing phase.
+#[derive(Serialize, Debug, Clone)]
+pub struct ConnectivityReport {
+    pub host: String,
+    pub phases: Vec<ConnectivityPhase>,
+    pub failed_phase: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ListModelsResponse {
+    #[serde(default)]
+    models: Vec<ModelInfo>,
+    #[serde(rename = "nextPageToken")]
+    next_page_token: Option<String>,
+}
+
+// System instruction for inline autocomplete
+pub const AUTOCOMPLETE_INSTRUCTION: &str = "You are an autocomplete assistant. Only return 2-5 words to continue the user's sentence. If the user's sentence does not end with a space or punctuation, start your completion with a space to ensure proper word separation.";
+
+// Autocomplete instruction asking for a continuation in the given language (an ISO 639-3
+// code). English, unknown codes and None get the plain instruction.
+pub fn autocomplete_instruction(language: Option<&str>) -> String {
+    match language.filter(|&code| code != "eng").and_then(|code| crate::language::name(code).map(|name| (code, name))) {
+        Some((code, name)) => format!(
+            "{} The user is writing in {} ({}); continue in {}, never switching to English.",
+            AUTOCOMPLETE_INSTRUCTION, name, code, name
+        ),
+        None => AUTOCOMPLETE_INSTRUCTION.to_string(),
+    }
+}
+
+// System instruction for free-form chat with the assistant
+pub const CHAT_INSTRUCTION: &str = "You are a helpful assistant inside a note-taking app. Answer the user's message directly and completely, using markdown where it helps.";
+
+// System instruction for structured (JSON) extraction
+pub const STRUCTURED_INSTRUCTION: &str = "You extract structured data from the user's text. Answer only with JSON that matches the provided schema.";
+
+// System instruction for naming a cluster of notes with a tag
+pub const TAG_INSTRUCTION: &str = "You name groups of notes. Reply with one short lowercase tag of 1-3 words joined by hyphens that describes what the notes have in common. Reply with the tag only, no '#', quotes, or explanation.";
+
+// System instruction for answering questions about the user's notes
+pub const ASK_INSTRUCTION: &str = "You answer questions about the user's notes. Use the notes provided as reference material; they are ranked with the most relevant first and may be cut short.";
+
+// Added to ASK_INSTRUCTION when chat grounding is on, to keep answers to what the notes say
+pub const GROUNDING_INSTRUCTION: &str = "Answer only from the notes provided, never from general knowledge. If they don't contain the answer, reply exactly \"not found in your notes\". Each note's heading ends with its id in square brackets; cite the ids of the notes you used, like [id], after the statements they support.";
+
+// System instruction for rewriting a selected passage
+pub const REWRITE_INSTRUCTION: &str = "You rewrite passages from the user's notes. Apply the user's instruction to the passage and return only the rewritten passage, with no preamble, quotes, or explanation. Keep the passage's markdown formatting (headings, lists, emphasis, links, code) unless the instruction asks to change it.";
+
+const GEMINI_HOST: &str = "generativelanguage.googleapis.com";
+const GEMINI_API_BASE: &str = "https://generativelanguage.googleapis.com/v1beta";
+
+// Model used for generation requests unless the vault config names another
+pub const GEMINI_MODEL: &str = "gemini-2.5-flash-lite-preview-06-17";
+
+// Per-request HTTP timeout
+pub const REQUEST_TIMEOUT_SECS: u64 = 10;
+
+#[derive(Clone)]
 pub struct GeminiClient {
     pub api_key: String,
     pub http: reqwest::blocking::Client,
+    // Root of the API, GEMINI_API_BASE outside tests
+    api_base: String,
 }
 
 impl GeminiClient {
     pub fn new(api_key: impl Into<String>) -> Self {
         let client = reqwest::blocking::ClientBuilder::new()
-            .timeout(std::time::Duration::from_secs(10))
+            .timeout(std::time::Duration::from_secs(REQUEST_TIMEOUT_SECS))
             .build()
             .unwrap_or_else(|_| {
                 error!("Failed to build HTTP client with custom timeout, using default");
@@ -69,57 +241,162 @@ impl GeminiClient {
         Self {
             api_key: api_key.into(),
             http: client,
+            api_base: GEMINI_API_BASE.to_string(),
         }
     }
 
+    // Point the client at another server, e.g. a local mock
+    #[cfg(test)]
+    pub fn with_api_base(mut self, api_base: impl Into<String>) -> Self {
+        self.api_base = api_base.into();
+        self
+    }
+
     pub fn api_key(&self) -> &str {
         &self.api_key
     }
 
     pub fn get_completion(&self, prompt: String, max_tokens: i32, temperature: f32) -> Result<String> {
+        let generation_config = GenerationConfig {
+            max_output_tokens: Some(max_tokens),
+            temperature: Some(temperature),
+            thinking_config: Some(ThinkingConfig { thinking_budget: 0 }),
+            stop_sequences: None,
+            response_mime_type: None,
+            response_schema: None,
+        };
+        self.get_completion_with_config(prompt, generation_config)
+    }
+
+    pub fn get_completion_with_config(&self, prompt: String, generation_config: GenerationConfig) -> Result<String> {
+        self.get_completion_with_context(prompt, None, generation_config)
+    }
+
+    // Like get_completion_with_config, with optional reference text sent ahead of the prompt
+    pub fn get_completion_with_context(&self, prompt: String, context: Option<&str>, generation_config: GenerationConfig) -> Result<String> {
+        self.generate(AUTOCOMPLETE_INSTRUCTION, prompt, context, generation_config)
+    }
+
+    // Send `prompt` under the given system instruction, with optional reference text ahead of it
+    pub fn generate(&self, instruction: &str, prompt: String, context: Option<&str>, generation_config: GenerationConfig) -> Result<String> {
         println!("[GEMINI_DEBUG] Starting get_completion with prompt: '{}'", prompt);
         info!("Getting completion for prompt: '{}'", prompt);
 
-        let contents = vec![
+        let body = Self::build_request(instruction, prompt, context, generation_config);
+        self.send(&body)
+    }
+
+    // Like generate, but when the reply hits the token limit, send the partial reply back and
+    // ask the model to continue, up to `max_continuations` times
+    pub fn generate_continued(&self, instruction: &str, prompt: String, context: Option<&str>, generation_config: GenerationConfig, max_continuations: usize) -> Result<String> {
+        let mut body = Self::build_request(instruction, prompt, context, generation_config);
+        let base_len = body.contents.len();
+        let output = continue_until_done(max_continuations, |so_far| {
+            body.contents.truncate(base_len);
+            if let Some(so_far) = so_far {
+                body.contents.push(Content {
+                    role: Some("model".to_string()),
+                    parts: Some(vec![Part { text: Some(so_far.to_string()) }]),
+                });
+                body.contents.push(Content {
+                    role: Some("user".to_string()),
+                    parts: Some(vec![Part { text: Some(CONTINUE_INSTRUCTION.to_string()) }]),
+                });
+            }
+            self.send_generation(&body)
+        })?;
+        Ok(if output.is_empty() { EMPTY_REPLY.to_string() } else { output })
+    }
+
+    // The system instruction as sent for a command's own `instruction`, with the configured
+    // global prompt prefix and suffix applied
+    pub fn full_instruction(instruction: &str) -> String {
+        crate::config::GlobalPrompt::from_app_config(&crate::config::load_config()).wrap(instruction)
+    }
+
+    // Assemble the request body without sending it. The global prompt is applied to the
+    // instruction here (see full_instruction), so every completion command gets it.
+    pub fn build_request(instruction: &str, prompt: String, context: Option<&str>, generation_config: GenerationConfig) -> GeminiRequest {
+        Self::build_request_with_instruction(Self::full_instruction(instruction), prompt, context, generation_config)
+    }
+
+    fn build_request_with_instruction(instruction: String, prompt: String, context: Option<&str>, generation_config: GenerationConfig) -> GeminiRequest {
+        let mut contents = vec![
             Content {
                 role: Some("user".to_string()),
-                parts: Some(vec![Part { text: Some("You are an autocomplete assistant. Only return 2-5 words to continue the user's sentence. If the user's sentence does not end with a space or punctuation, start your completion with a space to ensure proper word separation.".to_string()) }]),
+                parts: Some(vec![Part { text: Some(instruction) }]),
             },
-            Content {
-                role: Some("user".to_string()),
-                parts: Some(vec![Part { text: Some(prompt.clone()) }]),
-            }
         ];
+        if let Some(context) = context {
+            contents.push(Content {
+                role: Some("user".to_string()),
+                parts: Some(vec![Part { text: Some(format!("Use this reference material from the note for style and terminology. Do not continue it:\n{}", context)) }]),
+            });
+        }
+        contents.push(Content {
+            role: Some("user".to_string()),
+            parts: Some(vec![Part { text: Some(prompt) }]),
+        });
 
-        let generation_config = GenerationConfig {
-            max_output_tokens: Some(max_tokens),
-            temperature: Some(temperature),
-            thinking_config: Some(ThinkingConfig { thinking_budget: 0 }),
-        };
-
-        let body = GeminiRequest {
+        GeminiRequest {
             contents,
             generation_config: Some(generation_config),
-        };
+        }
+    }
+
+    // Endpoint completions are sent to
+    pub fn generate_url(&self) -> String {
+        format!("{}/models/{}:generateContent", self.api_base, crate::config::load_config().model())
+    }
+
+    fn send(&self, body: &GeminiRequest) -> Result<String> {
+        let generation = self.send_generation(body)?;
+        Ok(if generation.text.is_empty() { EMPTY_REPLY.to_string() } else { generation.text })
+    }
 
-        let url = "https://generativelanguage.googleapis.com/v1beta/models/gemini-2.5-flash-lite-preview-06-17:generateContent";
+    // One request and its reply. A candidate without any parts gives empty text rather than
+    // a placeholder, so a continued reply isn't padded with one.
+
+    fn send_generation(&self, body: &GeminiRequest) -> Result<Generation> {
+        let url = self.generate_url();
 
         println!("[GEMINI_DEBUG] Sending request to Gemini API at {}", url);
         info!("Sending request to Gemini API at {}", url);
 
-        let response_result = self.http
-            .post(url)
-            .header("x-goog-api-key", &self.api_key)
-            .json(&body)
-            .send();
+        let mut attempt = 0;
+        let response = loop {
+            let response_result = self.http
+                .post(&url)
+                .header("x-goog-api-key", &self.api_key)
+                .json(body)
+                .send();
 
-        if response_result.is_err() {
-            let err = response_result.unwrap_err();
-            println!("[GEMINI_DEBUG] API request failed: {}", err);
-            return Err(anyhow!("Gemini API request failed: {}", err));
-        }
+            if response_result.is_err() {
+                let err = response_result.unwrap_err();
+                println!("[GEMINI_DEBUG] API request failed: {}", err);
+                return Err(anyhow!("Gemini API request failed: {}", err));
+            }
+
+            let response = response_result.unwrap();
+            if response.status() != reqwest::StatusCode::TOO_MANY_REQUESTS {
+                break response;
+            }
 
-        let response = response_result.unwrap();
+            // Wait as long as the server asks, falling back to exponential backoff
+            let retry_after = response
+                .headers()
+                .get(reqwest::header::RETRY_AFTER)
+                .and_then(|value| value.to_str().ok())
+                .and_then(parse_retry_after)
+                .unwrap_or(1 << attempt);
+            if attempt == MAX_RATE_LIMIT_RETRIES || retry_after > MAX_RETRY_WAIT_SECS {
+                warn!("Gemini API rate limited, giving up after {} retries", attempt);
+                return Err(RateLimited { retry_after_secs: retry_after }.into());
+            }
+            println!("[GEMINI_DEBUG] Rate limited, retrying in {}s", retry_after);
+            std::thread::sleep(std::time::Duration::from_secs(retry_after));
+            attempt += 1;
+        };
         if !response.status().is_success() {
             let error_text = response.text().unwrap_or_else(|_| "Unknown error".to_string());
             println!("[GEMINI_DEBUG] API error: {}", error_text);
@@ -148,20 +425,326 @@ impl GeminiClient {
         println!("[GEMINI_DEBUG] Gemini response: {:#?}", gemini_response);
 
         if let Some(candidate) = gemini_response.candidates.first() {
+            let finish_reason = candidate.finish_reason.clone();
             if let Some(parts) = &candidate.content.parts {
                 println!("[GEMINI_DEBUG] First candidate has {} parts", parts.len());
                 if let Some(part) = parts.first() {
                     if let Some(text) = &part.text {
-                        return Ok(text.clone());
+                        return Ok(Generation { text: text.clone(), finish_reason });
                     }
                 } else {
                     println!("[GEMINI_DEBUG] Parts array is empty");
                 }
             } else {
-                return Ok("...".to_string());
+                return Ok(Generation { text: String::new(), finish_reason });
             }
         }
         println!("[GEMINI_DEBUG] No text found in Gemini API response");
         Err(anyhow!("No text found in Gemini API response"))
     }
+
+    // Resolve the API host, open a TCP connection to it, then make one HTTPS request,
+    // timing each phase. Any HTTP response counts as reachable; the status is reported.
+    pub fn diagnose_connectivity(&self) -> ConnectivityReport {
+        use std::net::{TcpStream, ToSocketAddrs};
+        use std::time::{Duration, Instant};
+
+        let mut report = ConnectivityReport { host: GEMINI_HOST.to_string(), phases: Vec::new(), failed_phase: None };
+        let record = |report: &mut ConnectivityReport, phase: &str, start: Instant, result: std::result::Result<String, String>| {
+            let ok = result.is_ok();
+            report.phases.push(ConnectivityPhase {
+                phase: phase.to_string(),
+                ok,
+                duration_ms: start.elapsed().as_millis() as u64,
+                detail: result.unwrap_or_else(|e| e),
+            });
+            if !ok {
+                report.failed_phase = Some(phase.to_string());
+            }
+            ok
+        };
+
+        let start = Instant::now();
+        let addrs: Vec<_> = match (GEMINI_HOST, 443).to_socket_addrs() {
+            Ok(addrs) => addrs.collect(),
+            Err(e) => {
+                record(&mut report, "dns", start, Err(e.to_string()));
+                return report;
+            }
+        };
+        let resolved = match addrs.first() {
+            Some(addr) => Ok(format!("resolved to {}", addr.ip())),
+            None => Err("no addresses returned".to_string()),
+        };
+        if !record(&mut report, "dns", start, resolved) {
+            return report;
+        }
+
+        let start = Instant::now();
+        let connected = TcpStream::connect_timeout(&addrs[0], Duration::from_secs(REQUEST_TIMEOUT_SECS))
+            .map(|_| format!("connected to {}", addrs[0]))
+            .map_err(|e| e.to_string());
+        if !record(&mut report, "tcp", start, connected) {
+            return report;
+        }
+
+        let start = Instant::now();
+        let response = self
+            .http
+            .get(format!("{}/models", self.api_base))
+            .header("x-goog-api-key", &self.api_key)
+            .query(&[("pageSize", "1")])
+            .send()
+            .map(|response| format!("HTTP {}", response.status()))
+            .map_err(|e| e.to_string());
+        record(&mut report, "https", start, response);
+        report
+    }
+
+    // Make the cheapest authenticated request there is (the current model's metadata) so the
+    // pooled HTTPS connection is open before the next completion needs it
+    pub fn warm(&self) -> Result<()> {
+        let url = format!("{}/models/{}", self.api_base, crate::config::load_config().model());
+        let response = self
+            .http
+            .get(&url)
+            .header("x-goog-api-key", &self.api_key)
+            .send()
+            .map_err(|e| anyhow!("Gemini warm-up request failed: {}", e))?;
+        if !response.status().is_success() {
+            return Err(anyhow!("Gemini API error: HTTP {}", response.status()));
+        }
+        Ok(())
+    }
+
+    pub fn list_models(&self) -> Result<Vec<ModelInfo>> {
+        let url = format!("{}/models", self.api_base);
+        info!("Listing Gemini models from {}", url);
+
+        let mut models = Vec::new();
+        let mut page_token: Option<String> = None;
+        loop {
+            let mut request = self.http
+                .get(&url)
+                .header("x-goog-api-key", &self.api_key)
+                .query(&[("pageSize", "1000")]);
+            if let Some(token) = &page_token {
+                request = request.query(&[("pageToken", token)]);
+            }
+
+            let response = request
+                .send()
+                .map_err(|e| anyhow!("Gemini models request failed: {}", e))?;
+            if !response.status().is_success() {
+                let error_text = response.text().unwrap_or_else(|_| "Unknown error".to_string());
+                return Err(anyhow!("Gemini API error: {}", error_text));
+            }
+
+            let page: ListModelsResponse = response
+                .json()
+                .map_err(|e| anyhow!("Failed to parse Gemini models response: {}", e))?;
+            models.extend(page.models);
+
+            match page.next_page_token {
+                Some(token) if !token.is_empty() => page_token = Some(token),
+                _ => break,
+            }
+        }
+        Ok(models)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    const REPLY: &str = r#"{"candidates":[{"content":{"parts":[{"text":"hello there"}]},"finishReason":"STOP","index":0}]}"#;
+
+    fn response(status: &str, extra_headers: &str, body: &str) -> String {
+        format!(
+            "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n{}\r\n{}",
+            status,
+            body.len(),
+            extra_headers,
+            body
+        )
+    }
+
+    fn rate_limited(retry_after: u64) -> String {
+        response("429 Too Many Requests", &format!("Retry-After: {}\r\n", retry_after), "{}")
+    }
+
+    // Read one request's headers and body so the client sees a clean reply
+    fn read_request(stream: &mut TcpStream) {
+        let mut request = Vec::new();
+        let mut buf = [0; 4096];
+        loop {
+            let n = stream.read(&mut buf).unwrap();
+            request.extend_from_slice(&buf[..n]);
+            let text = String::from_utf8_lossy(&request);
+            if let Some(header_end) = text.find("\r\n\r\n") {
+                let length = text[..header_end]
+                    .lines()
+                    .find_map(|line| line.to_lowercase().strip_prefix("content-length:").map(|v| v.trim().parse::<usize>().unwrap()))
+                    .unwrap_or(0);
+                if request.len() >= header_end + 4 + length {
+                    return;
+                }
+            }
+            if n == 0 {
+                return;
+            }
+        }
+    }
+
+    // Answer one request per response, in order, on a local port. Returns a client pointed at
+    // it and the number of requests answered so far.
+    fn mock_server(responses: Vec<String>) -> (GeminiClient, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        let served = Arc::new(AtomicUsize::new(0));
+        let counter = served.clone();
+        std::thread::spawn(move || {
+            for response in responses {
+                let (mut stream, _) = listener.accept().unwrap();
+                read_request(&mut stream);
+                stream.write_all(response.as_bytes()).unwrap();
+                counter.fetch_add(1, Ordering::SeqCst);
+            }
+        });
+        let mut client = GeminiClient::new("test-key").with_api_base(base);
+        client.http = reqwest::blocking::Client::builder().no_proxy().build().unwrap();
+        (client, served)
+    }
+
+    fn config() -> GenerationConfig {
+        GenerationConfig {
+            max_output_tokens: Some(16),
+            temperature: Some(0.0),
+            thinking_config: None,
+            stop_sequences: None,
+            response_mime_type: None,
+            response_schema: None,
+        }
+    }
+
+    #[test]
+    fn instruction_names_the_detected_language() {
+        let instruction = autocomplete_instruction(Some("fra"));
+        assert!(instruction.starts_with(AUTOCOMPLETE_INSTRUCTION));
+        assert!(instruction.contains("French (fra)"));
+    }
+
+    #[test]
+    fn english_and_unknown_languages_get_the_plain_instruction() {
+        assert_eq!(autocomplete_instruction(None), AUTOCOMPLETE_INSTRUCTION);
+        assert_eq!(autocomplete_instruction(Some("eng")), AUTOCOMPLETE_INSTRUCTION);
+        assert_eq!(autocomplete_instruction(Some("zzz")), AUTOCOMPLETE_INSTRUCTION);
+    }
+
+    #[test]
+    fn retries_after_a_429() {
+        let (client, served) = mock_server(vec![rate_limited(0), response("200 OK", "", REPLY)]);
+        let text = client.generate("instruction", "prompt".to_string(), None, config()).unwrap();
+        assert_eq!(text, "hello there");
+        assert_eq!(served.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn gives_up_with_rate_limited_after_the_retries() {
+        let responses = (0..=MAX_RATE_LIMIT_RETRIES).map(|_| rate_limited(0)).collect();
+        let (client, served) = mock_server(responses);
+        let error = client.generate("instruction", "prompt".to_string(), None, config()).unwrap_err();
+        assert_eq!(error.downcast_ref::<RateLimited>().map(|e| e.retry_after_secs), Some(0));
+        assert_eq!(served.load(Ordering::SeqCst), MAX_RATE_LIMIT_RETRIES as usize + 1);
+    }
+
+    #[test]
+    fn long_retry_after_is_returned_without_waiting() {
+        let (client, served) = mock_server(vec![rate_limited(MAX_RETRY_WAIT_SECS + 90)]);
+        let error = client.generate("instruction", "prompt".to_string(), None, config()).unwrap_err();
+        assert!(matches!(
+            crate::NoteError::from(error),
+            crate::NoteError::RateLimited { retry_after_secs } if retry_after_secs == MAX_RETRY_WAIT_SECS + 90
+        ));
+        assert_eq!(served.load(Ordering::SeqCst), 1);
+    }
+
+    fn text_of(content: &Content) -> &str {
+        content.parts.as_ref().and_then(|parts| parts[0].text.as_deref()).unwrap_or_default()
+    }
+
+    #[test]
+    fn global_prompt_surrounds_the_instruction() {
+        let global_prompt = crate::config::GlobalPrompt {
+            prefix: Some("Write in British English.".to_string()),
+            suffix: Some("Never use emoji.".to_string()),
+        };
+        let request = GeminiClient::build_request_with_instruction(
+            global_prompt.wrap(AUTOCOMPLETE_INSTRUCTION),
+            "The colour of".to_string(),
+            Some("reference"),
+            config(),
+        );
+        let instruction = text_of(&request.contents[0]);
+        assert!(instruction.starts_with("Write in British English.\n\n"));
+        assert!(instruction.contains(AUTOCOMPLETE_INSTRUCTION));
+        assert!(instruction.ends_with("\n\nNever use emoji."));
+        assert!(text_of(&request.contents[1]).ends_with("reference"));
+        assert_eq!(text_of(&request.contents[2]), "The colour of");
+    }
+
+    #[test]
+    fn blank_global_prompt_parts_are_left_out() {
+        let global_prompt = crate::config::GlobalPrompt { prefix: Some("  ".to_string()), suffix: None };
+        assert_eq!(global_prompt.wrap(CHAT_INSTRUCTION), CHAT_INSTRUCTION);
+    }
+
+    fn reply(text: Option<&str>, finish_reason: &str) -> String {
+        let content = match text {
+            Some(text) => serde_json::json!({ "parts": [{ "text": text }] }),
+            None => serde_json::json!({}),
+        };
+        let body = serde_json::json!({ "candidates": [{ "content": content, "finishReason": finish_reason, "index": 0 }] });
+        response("200 OK", "", &body.to_string())
+    }
+
+    #[test]
+    fn truncated_replies_are_continued_and_joined() {
+        let (client, served) = mock_server(vec![
+            reply(Some("hello "), MAX_TOKENS_FINISH_REASON),
+            reply(Some("there"), "STOP"),
+        ]);
+        let text = client.generate_continued("instruction", "prompt".to_string(), None, config(), 3).unwrap();
+        assert_eq!(text, "hello there");
+        assert_eq!(served.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn continuation_without_parts_adds_no_placeholder() {
+        let (client, served) = mock_server(vec![
+            reply(Some("hello"), MAX_TOKENS_FINISH_REASON),
+            reply(None, MAX_TOKENS_FINISH_REASON),
+        ]);
+        let text = client.generate_continued("instruction", "prompt".to_string(), None, config(), 3).unwrap();
+        assert_eq!(text, "hello");
+        assert_eq!(served.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn reply_without_parts_is_a_placeholder() {
+        let (client, _) = mock_server(vec![reply(None, "STOP")]);
+        assert_eq!(client.generate("instruction", "prompt".to_string(), None, config()).unwrap(), EMPTY_REPLY);
+    }
+
+    #[test]
+    fn parses_retry_after_seconds_and_dates() {
+        assert_eq!(parse_retry_after(" 7 "), Some(7));
+        assert_eq!(parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"), Some(0));
+        assert_eq!(parse_retry_after("soon"), None);
+    }
 }
@@ -1,4 +1,7 @@
+pub mod cache;
 pub mod common;
 pub mod gemini_client;
+pub mod postprocess;
+pub mod provider;
 
-pub use gemini_client::{GeminiClient, GeminiRequest, Content, Part, ThinkingConfig, GenerationConfig, GeminiResponse, Candidate};
+pub use gemini_client::{GeminiClient, GeminiRequest, Content, Part, ThinkingConfig, GenerationConfig, GeminiResponse, Candidate, ModelInfo, ConnectivityReport, RateLimited};
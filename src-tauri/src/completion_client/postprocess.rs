@@ -0,0 +1,276 @@
+use serde::{Deserialize, Serialize};
+
+// Flags controlling how raw model output is cleaned up before it is inserted
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PostProcessOptions {
+    pub trim: bool,
+    pub strip_quotes: bool,
+    pub remove_prompt_echo: bool,
+    pub collapse_whitespace: bool,
+}
+
+impl Default for PostProcessOptions {
+    fn default() -> Self {
+        Self {
+            trim: true,
+            strip_quotes: true,
+            remove_prompt_echo: true,
+            collapse_whitespace: true,
+        }
+    }
+}
+
+const QUOTE_PAIRS: [(char, char); 5] = [('"', '"'), ('\'', '\''), ('`', '`'), ('\u{201C}', '\u{201D}'), ('\u{2018}', '\u{2019}')];
+
+// Minimum number of words that must repeat before we treat it as an echo of the prompt
+const MIN_ECHO_WORDS: usize = 2;
+
+// Clean up a raw completion so it can be appended directly after `prompt`
+pub fn postprocess_completion(prompt: &str, raw: &str, options: &PostProcessOptions) -> String {
+    // The autocomplete instruction asks for a leading space when the prompt ends mid-sentence,
+    // so remember whether the model gave us one before trimming it away
+    let mut needs_leading_space = raw.starts_with(char::is_whitespace);
+    let mut text = raw.to_string();
+
+    if options.trim {
+        text = text.trim().to_string();
+    }
+
+    if options.strip_quotes {
+        text = strip_surrounding_quotes(&text, options.trim);
+    }
+
+    if options.remove_prompt_echo {
+        if let Some(echo) = strip_prompt_echo(prompt, &text) {
+            text = echo.rest;
+            // After dropping the echo, the remainder follows the prompt's last word directly,
+            // unless it finishes that word
+            needs_leading_space = !echo.continues_word
+                && !prompt.ends_with(char::is_whitespace)
+                && text.starts_with(char::is_alphanumeric);
+        }
+    }
+
+    if options.collapse_whitespace {
+        text = collapse_whitespace(&text);
+    }
+
+    if needs_leading_space && !text.is_empty() && !text.starts_with(char::is_whitespace) {
+        text.insert(0, ' ');
+    }
+
+    text
+}
+
+// Rough upper bound on tokens per English word, used to size max_output_tokens from a word budget
+pub const TOKENS_PER_WORD: f32 = 2.0;
+
+// Token budget for a completion of at most `words` words
+pub fn tokens_for_words(words: usize) -> i32 {
+    (words as f32 * TOKENS_PER_WORD).ceil() as i32 + 2
+}
+
+// Cut `text` after its `max_words`th word, keeping any leading whitespace
+pub fn truncate_to_words(text: &str, max_words: usize) -> String {
+    let words = word_spans(text);
+    if words.len() <= max_words {
+        return text.to_string();
+    }
+    if max_words == 0 {
+        return String::new();
+    }
+    let (start, word) = words[max_words - 1];
+    text[..start + word.len()].to_string()
+}
+
+// Every run of whitespace as a single space. Runs at either end are kept (as one space) so
+// the leading space survives when trimming is off.
+fn collapse_whitespace(text: &str) -> String {
+    let mut collapsed = String::with_capacity(text.len());
+    let mut in_whitespace = false;
+    for c in text.chars() {
+        if c.is_whitespace() {
+            if !in_whitespace {
+                collapsed.push(' ');
+            }
+            in_whitespace = true;
+        } else {
+            collapsed.push(c);
+            in_whitespace = false;
+        }
+    }
+    collapsed
+}
+
+fn strip_surrounding_quotes(text: &str, trim: bool) -> String {
+    let mut current = text;
+    loop {
+        let mut chars = current.chars();
+        let (Some(first), Some(last)) = (chars.next(), chars.next_back()) else {
+            break;
+        };
+        if !QUOTE_PAIRS.contains(&(first, last)) {
+            break;
+        }
+        current = &current[first.len_utf8()..current.len() - last.len_utf8()];
+        if trim {
+            current = current.trim();
+        }
+    }
+    current.to_string()
+}
+
+// What's left of a completion after dropping an echo of the prompt
+struct Echo {
+    rest: String,
+    // The prompt ended mid-word and `rest` starts with the remainder of that word
+    continues_word: bool,
+}
+
+// Remove a leading run of words in `text` that repeats the tail of `prompt`. Words are
+// compared ignoring case and surrounding punctuation. When the prompt ends mid-word, the
+// echo may finish that word ("...the qui" + "the quick fox" leaves "ck fox").
+fn strip_prompt_echo(prompt: &str, text: &str) -> Option<Echo> {
+    let prompt_words: Vec<&str> = prompt.split_whitespace().collect();
+    let text_words: Vec<(usize, &str)> = word_spans(text);
+    let prompt_mid_word = !prompt.is_empty() && !prompt.ends_with(char::is_whitespace);
+
+    let max_overlap = prompt_words.len().min(text_words.len());
+    for n in (MIN_ECHO_WORDS..=max_overlap).rev() {
+        let prompt_tail = &prompt_words[prompt_words.len() - n..];
+        let leading_match = prompt_tail[..n - 1]
+            .iter()
+            .zip(&text_words[..n - 1])
+            .all(|(p, (_, t))| normalize_word(p) == normalize_word(t));
+        if !leading_match {
+            continue;
+        }
+
+        let last_prompt = prompt_tail[n - 1];
+        let (start, last_text) = text_words[n - 1];
+        if normalize_word(last_prompt) == normalize_word(last_text) {
+            let end = start + last_text.len();
+            return Some(Echo { rest: text[end..].trim_start().to_string(), continues_word: false });
+        }
+        if prompt_mid_word {
+            if let Some(split) = completes_word(last_prompt, last_text) {
+                return Some(Echo { rest: text[start + split..].to_string(), continues_word: true });
+            }
+        }
+    }
+    None
+}
+
+// Lowercased word without surrounding punctuation, for echo comparisons
+fn normalize_word(word: &str) -> String {
+    word.trim_matches(|c: char| !c.is_alphanumeric()).to_lowercase()
+}
+
+// If `word` starts with the unfinished `partial` (ignoring case) and goes on, the byte
+// offset in `word` where the rest begins
+fn completes_word(partial: &str, word: &str) -> Option<usize> {
+    let mut word_chars = word.char_indices();
+    for p in partial.chars() {
+        let (_, w) = word_chars.next()?;
+        if !p.to_lowercase().eq(w.to_lowercase()) {
+            return None;
+        }
+    }
+    word_chars.next().map(|(i, _)| i)
+}
+
+fn word_spans(text: &str) -> Vec<(usize, &str)> {
+    let mut spans = Vec::new();
+    let mut start = None;
+    for (i, c) in text.char_indices() {
+        match (c.is_whitespace(), start) {
+            (true, Some(s)) => {
+                spans.push((s, &text[s..i]));
+                start = None;
+            }
+            (false, None) => start = Some(i),
+            _ => {}
+        }
+    }
+    if let Some(s) = start {
+        spans.push((s, &text[s..]));
+    }
+    spans
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn clean(prompt: &str, raw: &str) -> String {
+        postprocess_completion(prompt, raw, &PostProcessOptions::default())
+    }
+
+    #[test]
+    fn trailing_newlines_and_quotes_are_removed() {
+        assert_eq!(clean("The fox", "\"jumps over\"\n\n"), "jumps over");
+        assert_eq!(clean("The fox", "'\"jumps over\"'"), "jumps over");
+        assert_eq!(clean("The fox", "\u{201C}jumps over\u{201D}"), "jumps over");
+    }
+
+    #[test]
+    fn leading_space_is_kept() {
+        assert_eq!(clean("The fox", "  jumps \n\t over\n"), " jumps over");
+    }
+
+    #[test]
+    fn leading_space_is_kept_when_only_collapsing() {
+        let options = PostProcessOptions { trim: false, ..PostProcessOptions::default() };
+        assert_eq!(postprocess_completion("The fox", " jumps \n over", &options), " jumps over");
+        assert_eq!(postprocess_completion("The fox", "jumps  over", &options), "jumps over");
+    }
+
+    #[test]
+    fn prompt_echo_is_removed() {
+        assert_eq!(clean("The quick brown", "quick brown fox jumps"), " fox jumps");
+        assert_eq!(clean("The quick brown", "Quick, brown fox"), " fox");
+        // An echo that finishes the prompt's last word continues it without a space
+        assert_eq!(clean("the qui", "the quick fox"), "ck fox");
+    }
+
+    #[test]
+    fn repeats_of_the_last_few_prompt_words_are_removed() {
+        let prompt = "I went to the store to buy";
+        assert_eq!(clean(prompt, "to the store to buy some milk"), " some milk");
+        assert_eq!(clean(prompt, "the store to buy some milk"), " some milk");
+        assert_eq!(clean(prompt, "  To buy some milk."), " some milk.");
+        assert_eq!(clean("Dear John,", "Dear John, I hope you're well"), " I hope you're well");
+    }
+
+    #[test]
+    fn prompt_words_later_in_the_output_are_kept() {
+        let prompt = "I went to the store to buy";
+        assert_eq!(clean(prompt, "some milk to buy"), "some milk to buy");
+        assert_eq!(clean(prompt, "milk, then went to the store"), "milk, then went to the store");
+    }
+
+    #[test]
+    fn a_single_repeated_word_is_not_an_echo() {
+        assert_eq!(clean("I like", "like it"), "like it");
+    }
+
+    #[test]
+    fn blank_and_quote_only_output_becomes_empty() {
+        assert_eq!(clean("The fox", " \n\t "), "");
+        assert_eq!(clean("The fox", "\"\""), "");
+    }
+
+    #[test]
+    fn disabled_flags_leave_output_alone() {
+        let options = PostProcessOptions { trim: false, strip_quotes: false, remove_prompt_echo: false, collapse_whitespace: false };
+        assert_eq!(postprocess_completion("The fox", " \"fox  jumps\"\n", &options), " \"fox  jumps\"\n");
+    }
+
+    #[test]
+    fn truncation_keeps_leading_whitespace() {
+        assert_eq!(truncate_to_words(" one two three", 2), " one two");
+        assert_eq!(truncate_to_words("one two", 5), "one two");
+        assert_eq!(truncate_to_words("one two", 0), "");
+    }
+}
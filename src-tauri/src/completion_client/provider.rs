@@ -0,0 +1,73 @@
+use super::gemini_client::{GeminiClient, GenerationConfig};
+use anyhow::Result;
+
+// Completion text as it arrives, in chunks. Providers that can't stream yield a single chunk.
+pub type CompletionStream = Box<dyn Iterator<Item = Result<String>> + Send>;
+
+// What the completion commands need from a model backend, so streaming and non-streaming
+// providers can be used the same way
+pub trait CompletionProvider {
+    fn complete(&self, instruction: &str, prompt: String, context: Option<&str>, generation_config: GenerationConfig) -> Result<CompletionStream>;
+}
+
+// Gemini's generateContent endpoint answers in one piece
+impl CompletionProvider for GeminiClient {
+    fn complete(&self, instruction: &str, prompt: String, context: Option<&str>, generation_config: GenerationConfig) -> Result<CompletionStream> {
+        let text = self.generate(instruction, prompt, context, generation_config)?;
+        Ok(Box::new(std::iter::once(Ok(text))))
+    }
+}
+
+// Drain a stream into the full completion, failing on the first bad chunk
+pub fn collect_stream(stream: CompletionStream) -> Result<String> {
+    stream.collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Streams a fixed reply in the given pieces, failing at `fail_at` if set
+    struct FakeProvider {
+        chunks: Vec<&'static str>,
+        fail_at: Option<usize>,
+    }
+
+    impl CompletionProvider for FakeProvider {
+        fn complete(&self, _instruction: &str, _prompt: String, _context: Option<&str>, _generation_config: GenerationConfig) -> Result<CompletionStream> {
+            let fail_at = self.fail_at;
+            let chunks = self.chunks.clone().into_iter().enumerate().map(move |(i, chunk)| {
+                if Some(i) == fail_at {
+                    anyhow::bail!("connection dropped");
+                }
+                Ok(chunk.to_string())
+            });
+            Ok(Box::new(chunks))
+        }
+    }
+
+    fn config() -> GenerationConfig {
+        GenerationConfig {
+            max_output_tokens: None,
+            temperature: None,
+            thinking_config: None,
+            stop_sequences: None,
+            response_mime_type: None,
+            response_schema: None,
+        }
+    }
+
+    #[test]
+    fn chunks_are_joined_in_order() {
+        let provider: Box<dyn CompletionProvider> = Box::new(FakeProvider { chunks: vec!["Hel", "lo", " world"], fail_at: None });
+        let text = provider.complete("", "prompt".to_string(), None, config()).and_then(collect_stream).unwrap();
+        assert_eq!(text, "Hello world");
+    }
+
+    #[test]
+    fn a_bad_chunk_fails_the_whole_completion() {
+        let provider = FakeProvider { chunks: vec!["Hel", "lo", " world"], fail_at: Some(1) };
+        let result = provider.complete("", "prompt".to_string(), None, config()).and_then(collect_stream);
+        assert_eq!(result.unwrap_err().to_string(), "connection dropped");
+    }
+}
@@ -0,0 +1,495 @@
+use crate::embeddings::{EmbeddingStrategy, HnswParams};
+use crate::format::NoteFormat;
+use crate::SortOrder;
+use crate::completion_client::{GenerationConfig, ThinkingConfig};
+use crate::completion_client::cache::CompletionCacheSettings;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{create_dir_all, File};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+// Persisted app settings. Every field has a default so older config files keep loading.
+#[derive(Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct AppConfig {
+    pub default_sort: SortOrder,
+    // Per-feature overrides of the built-in generation defaults, keyed by feature name
+    pub generation: HashMap<String, GenerationDefaults>,
+    // Include a note's `---context---` section with every autocomplete request
+    pub frozen_context_enabled: bool,
+    // Leave fenced code out of completion context, except in notes with `mode: code` metadata
+    pub strip_code_from_context: bool,
+    pub context_format: crate::context::ContextFormat,
+    // How much note text a completion's context may carry, per note and in total
+    pub context_budget: crate::context::ContextBudget,
+    // Format used when writing notes; existing files are read in whatever format they're in
+    pub note_format: NoteFormat,
+    pub embedding_strategy: EmbeddingStrategy,
+    pub hnsw: HnswParams,
+    // Detect and store each note's language when it's saved
+    pub language_detection: bool,
+    pub completion_cache: CompletionCacheSettings,
+    pub autocomplete: AutocompleteSettings,
+    // Title untitled notes from their first line when they're saved
+    pub auto_title: bool,
+    // HTML tags kept when sanitizing imported HTML; None uses the built-in set
+    pub import_allowed_tags: Option<Vec<String>>,
+    // Wraps every autocomplete prompt, e.g. "Write in my concise style: {prompt}"
+    pub prompt_template: Option<String>,
+    // Move notes with blank content to the trash on startup once they're this many days old
+    pub auto_trash_empty_after_days: Option<u32>,
+    // Ask autocomplete to continue in the note's detected language
+    pub language_matched_prompt: bool,
+    // Largest note content accepted, in bytes; None uses DEFAULT_MAX_NOTE_BYTES
+    pub max_note_bytes: Option<usize>,
+    // Words the full-text index ignores; None uses text::DEFAULT_STOP_WORDS
+    pub stop_words: Option<Vec<String>>,
+    // Gemini model used for generation; None uses GEMINI_MODEL
+    pub model: Option<String>,
+    // Temperature for features without their own stored generation defaults
+    pub default_temperature: Option<f32>,
+    // Upper bound on max_tokens for every request, including explicit caller values
+    pub max_tokens_ceiling: Option<i32>,
+    // Where notes are stored; None uses ~/.minimal-notes/notes
+    pub notes_dir: Option<PathBuf>,
+    // Style instructions placed before and after the system instruction of every completion
+    pub global_prompt_prefix: Option<String>,
+    pub global_prompt_suffix: Option<String>,
+    // Let the model think before answering in chat, rewrite and structured requests. Slower
+    // but better answers; autocomplete never thinks so it stays fast.
+    pub thinking_enabled: bool,
+    // Have ask_notes answer only from the notes it's given, citing their ids
+    pub chat_grounding: bool,
+    // Ping the completion API this often to keep its connection warm; None turns it off
+    pub keep_alive_interval_secs: Option<u64>,
+}
+
+// Generous enough for any hand-written note, small enough that a pasted dump can't
+// swamp search and indexing
+pub const DEFAULT_MAX_NOTE_BYTES: usize = 10 * 1024 * 1024;
+
+impl AppConfig {
+    pub fn max_note_bytes(&self) -> usize {
+        self.max_note_bytes.unwrap_or(DEFAULT_MAX_NOTE_BYTES)
+    }
+
+    pub fn model(&self) -> String {
+        self.model.clone().unwrap_or_else(|| crate::completion_client::gemini_client::GEMINI_MODEL.to_string())
+    }
+
+    pub fn notes_dir(&self) -> PathBuf {
+        self.notes_dir.clone().unwrap_or_else(default_notes_dir)
+    }
+    
+    pub fn vault_data_dir(&self) -> PathBuf {
+        vault_data_dir(&self.notes_dir())
+    }
+}
+
+pub fn default_notes_dir() -> PathBuf {
+    dirs::home_dir().unwrap().join(".minimal-notes").join("notes")
+}
+
+// Where the app keeps data derived from the notes in `notes_dir`: the embedding index, the
+// manual order and note history. The default vault keeps these where they always were, in
+// ~/.minimal-notes; any other notes folder gets its own directory there, named after a hash
+// of its path, so switching vaults never mixes them up.
+pub fn vault_data_dir(notes_dir: &Path) -> PathBuf {
+    let base = dirs::home_dir().unwrap().join(".minimal-notes");
+    if notes_dir == default_notes_dir() {
+        return base;
+    }
+    let hash = xxhash_rust::xxh3::xxh3_64(notes_dir.as_os_str().as_encoded_bytes());
+    base.join("vaults").join(format!("{:016x}", hash))
+}
+
+// The vault-wide defaults the other commands fall back to, edited together from settings
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct VaultConfig {
+    pub model: String,
+    pub default_temperature: Option<f32>,
+    pub max_tokens_ceiling: Option<i32>,
+    pub autocomplete: AutocompleteSettings,
+    pub notes_dir: PathBuf,
+    pub default_sort: SortOrder,
+}
+
+impl VaultConfig {
+    // Resolve the vault config from the app config, filling in defaults on first run
+    pub fn from_app_config(config: &AppConfig) -> Self {
+        VaultConfig {
+            model: config.model(),
+            default_temperature: config.default_temperature,
+            max_tokens_ceiling: config.max_tokens_ceiling,
+            autocomplete: config.autocomplete,
+            notes_dir: config.notes_dir(),
+            default_sort: config.default_sort,
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        let model_char = |c: char| c.is_ascii_alphanumeric() || matches!(c, '-' | '.' | '_');
+        if self.model.is_empty() || !self.model.chars().all(model_char) {
+            return Err(format!("invalid model name: {:?}", self.model));
+        }
+        if let Some(temperature) = self.default_temperature {
+            if !(0.0..=2.0).contains(&temperature) {
+                return Err("default_temperature must be between 0.0 and 2.0".to_string());
+            }
+        }
+        if self.max_tokens_ceiling.is_some_and(|ceiling| ceiling <= 0) {
+            return Err("max_tokens_ceiling must be positive".to_string());
+        }
+        self.autocomplete.validate()?;
+        if !self.notes_dir.is_absolute() {
+            return Err("notes_dir must be an absolute path".to_string());
+        }
+        Ok(())
+    }
+
+    // Write these values into `config`. Values equal to the built-in defaults are stored as
+    // unset, so a later change to a default still reaches users who never touched it.
+    pub fn apply_to(&self, config: &mut AppConfig) {
+        use crate::completion_client::gemini_client::GEMINI_MODEL;
+        config.model = (self.model != GEMINI_MODEL).then(|| self.model.clone());
+        config.default_temperature = self.default_temperature;
+        config.max_tokens_ceiling = self.max_tokens_ceiling;
+        config.autocomplete = self.autocomplete;
+        config.notes_dir = (self.notes_dir != default_notes_dir()).then(|| self.notes_dir.clone());
+        config.default_sort = self.default_sort;
+    }
+}
+
+// The global prompt prefix and suffix, as edited together in settings
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct GlobalPrompt {
+    pub prefix: Option<String>,
+    pub suffix: Option<String>,
+}
+
+impl GlobalPrompt {
+    pub fn from_app_config(config: &AppConfig) -> Self {
+        GlobalPrompt { prefix: config.global_prompt_prefix.clone(), suffix: config.global_prompt_suffix.clone() }
+    }
+
+    // Surround a command's own system instruction with the prefix and suffix
+    pub fn wrap(&self, instruction: &str) -> String {
+        let parts = [self.prefix.as_deref(), Some(instruction), self.suffix.as_deref()];
+        parts.into_iter().flatten().filter(|part| !part.trim().is_empty()).collect::<Vec<_>>().join("\n\n")
+    }
+}
+
+// Placeholder a prompt template must contain, replaced by the user's text
+pub const PROMPT_PLACEHOLDER: &str = "{prompt}";
+
+// Metadata key holding a note's own prompt template, which overrides the global one
+pub const PROMPT_TEMPLATE_KEY: &str = "prompt_template";
+
+pub fn validate_prompt_template(template: &str) -> Result<(), String> {
+    if !template.contains(PROMPT_PLACEHOLDER) {
+        return Err(format!("prompt template must contain {}", PROMPT_PLACEHOLDER));
+    }
+    Ok(())
+}
+
+// Substitute `prompt` into `template`; the prompt itself is never scanned for placeholders
+pub fn apply_prompt_template(template: &str, prompt: &str) -> String {
+    template.replace(PROMPT_PLACEHOLDER, prompt)
+}
+
+// When autocomplete should fire. The debounce is applied by the editor; the backend
+// enforces the minimum prefix.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+#[serde(default)]
+pub struct AutocompleteSettings {
+    pub debounce_ms: u64,
+    // Characters the last word of the prompt needs before a completion is requested
+    pub min_prefix_len: usize,
+}
+
+impl Default for AutocompleteSettings {
+    fn default() -> Self {
+        AutocompleteSettings { debounce_ms: 250, min_prefix_len: 0 }
+    }
+}
+
+impl AutocompleteSettings {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.debounce_ms > 10_000 {
+            return Err("debounce_ms must be at most 10000".to_string());
+        }
+        Ok(())
+    }
+
+    // Whether `prompt` is long enough to autocomplete. Trailing whitespace is ignored, so
+    // a finished word still counts.
+    pub fn allows(&self, prompt: &str) -> bool {
+        let last_word = prompt.split_whitespace().next_back().unwrap_or("");
+        last_word.chars().count() >= self.min_prefix_len
+    }
+}
+
+// Features that have their own generation defaults
+pub const GENERATION_FEATURES: [&str; 4] = ["autocomplete", "chat", "rewrite", "structured"];
+
+// Thinking budget, in tokens, given to features other than autocomplete when thinking is on
+pub const THINKING_BUDGET: i32 = 1024;
+
+// Tunable generation settings for one feature
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct GenerationDefaults {
+    pub max_tokens: i32,
+    pub temperature: f32,
+    #[serde(default)]
+    pub thinking_budget: i32,
+    #[serde(default)]
+    pub stop_sequences: Vec<String>,
+}
+
+impl GenerationDefaults {
+    // Built-in defaults used until the user stores their own
+    pub fn builtin(feature: &str) -> Self {
+        match feature {
+            // Chat answers need room to be useful; autocomplete only wants a few words
+            "chat" => GenerationDefaults { max_tokens: 1024, temperature: 0.7, thinking_budget: 0, stop_sequences: vec![] },
+            "rewrite" => GenerationDefaults { max_tokens: 2048, temperature: 0.4, thinking_budget: 0, stop_sequences: vec![] },
+            // Condensing notes, e.g. naming a group of them; short and fairly literal
+            "summarize" => GenerationDefaults { max_tokens: 256, temperature: 0.2, thinking_budget: 0, stop_sequences: vec![] },
+            // Extraction should be as repeatable as possible
+            "structured" => GenerationDefaults { max_tokens: 1024, temperature: 0.0, thinking_budget: 0, stop_sequences: vec![] },
+            _ => GenerationDefaults { max_tokens: 30, temperature: 0.7, thinking_budget: 0, stop_sequences: vec![] },
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.max_tokens <= 0 {
+            return Err("max_tokens must be positive".to_string());
+        }
+        if !(0.0..=2.0).contains(&self.temperature) {
+            return Err("temperature must be between 0.0 and 2.0".to_string());
+        }
+        if self.thinking_budget < 0 {
+            return Err("thinking_budget must not be negative".to_string());
+        }
+        Ok(())
+    }
+
+    // Build a request config, letting explicit caller values win over the defaults. The
+    // vault's max_tokens_ceiling still applies to explicit values.
+    pub fn to_generation_config(&self, max_tokens: Option<i32>, temperature: Option<f32>) -> GenerationConfig {
+        self.to_generation_config_within(max_tokens, temperature, load_config().max_tokens_ceiling)
+    }
+
+    // to_generation_config with the ceiling given. Thinking tokens count against
+    // max_output_tokens, so the thinking budget goes on top of the answer's limit (which is
+    // what the ceiling caps) instead of eating into it.
+    fn to_generation_config_within(&self, max_tokens: Option<i32>, temperature: Option<f32>, ceiling: Option<i32>) -> GenerationConfig {
+        let answer_tokens = max_tokens.unwrap_or(self.max_tokens).min(ceiling.unwrap_or(i32::MAX));
+        GenerationConfig {
+            max_output_tokens: Some(answer_tokens.saturating_add(self.thinking_budget.max(0))),
+            temperature: Some(temperature.unwrap_or(self.temperature)),
+            thinking_config: Some(ThinkingConfig { thinking_budget: self.thinking_budget }),
+            stop_sequences: if self.stop_sequences.is_empty() { None } else { Some(self.stop_sequences.clone()) },
+            response_mime_type: None,
+            response_schema: None,
+        }
+    }
+}
+
+// Look up the effective generation defaults for a feature. Features without stored
+// overrides take the vault's default temperature, if one is set.
+pub fn generation_defaults(feature: &str) -> GenerationDefaults {
+    generation_defaults_from(feature, &load_config())
+}
+
+fn generation_defaults_from(feature: &str, config: &AppConfig) -> GenerationDefaults {
+    let mut defaults = match config.generation.get(feature) {
+        Some(stored) => stored.clone(),
+        None => {
+            let mut builtin = GenerationDefaults::builtin(feature);
+            if let Some(temperature) = config.default_temperature {
+                builtin.temperature = temperature;
+            }
+            builtin
+        }
+    };
+    if let Some(ceiling) = config.max_tokens_ceiling {
+        defaults.max_tokens = defaults.max_tokens.min(ceiling);
+    }
+    // A stored budget still wins; autocomplete is kept at zero for latency
+    if config.thinking_enabled && feature != "autocomplete" && defaults.thinking_budget == 0 {
+        defaults.thinking_budget = THINKING_BUDGET;
+    }
+    defaults
+}
+
+// Where an effective setting's value came from
+#[derive(Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SettingSource {
+    Env,
+    File,
+    Default,
+}
+
+#[derive(Serialize)]
+pub struct EffectiveSetting {
+    pub key: String,
+    pub value: serde_json::Value,
+    pub source: SettingSource,
+}
+
+// Resolved settings as reported by effective_config
+#[derive(Serialize)]
+pub struct EffectiveConfig {
+    pub config_path: String,
+    pub settings: Vec<EffectiveSetting>,
+}
+
+// Resolve every setting and its source. Stored settings are derived from AppConfig itself,
+// so new fields show up here without extra wiring. Secrets are reported only as set/unset.
+pub fn effective_config() -> EffectiveConfig {
+    use crate::completion_client::gemini_client::REQUEST_TIMEOUT_SECS;
+    use serde_json::{json, Value};
+
+    let path = config_path();
+    let stored: Value = std::fs::read_to_string(&path)
+        .ok()
+        .and_then(|text| serde_json::from_str(&text).ok())
+        .unwrap_or(Value::Null);
+    let from_file = |key: &str| stored.get(key).is_some();
+    let source = |present: bool| if present { SettingSource::File } else { SettingSource::Default };
+
+    let config = load_config();
+    let mut settings = Vec::new();
+    if let Ok(Value::Object(fields)) = serde_json::to_value(&config) {
+        for (key, value) in fields {
+            // Generation defaults are resolved per feature, falling back to the built-ins;
+            // model and notes_dir are reported resolved below
+            if matches!(key.as_str(), "generation" | "model" | "notes_dir") {
+                continue;
+            }
+            settings.push(EffectiveSetting { source: source(from_file(&key)), key, value });
+        }
+    }
+    for feature in GENERATION_FEATURES {
+        let stored_feature = stored.get("generation").and_then(|g| g.get(feature)).is_some();
+        settings.push(EffectiveSetting {
+            key: format!("generation.{}", feature),
+            value: serde_json::to_value(generation_defaults(feature)).unwrap_or(Value::Null),
+            source: source(stored_feature),
+        });
+    }
+
+    let api_key_set = std::env::var(crate::completion::GEMINI_API_KEY_ENV).is_ok_and(|key| !key.is_empty());
+    settings.push(EffectiveSetting {
+        key: "gemini_api_key".to_string(),
+        value: json!(if api_key_set { "<set>" } else { "<not set>" }),
+        source: SettingSource::Env,
+    });
+    settings.push(EffectiveSetting {
+        key: "model".to_string(),
+        value: json!(config.model()),
+        source: source(config.model.is_some()),
+    });
+    settings.push(EffectiveSetting {
+        key: "request_timeout_secs".to_string(),
+        value: json!(REQUEST_TIMEOUT_SECS),
+        source: SettingSource::Default,
+    });
+    settings.push(EffectiveSetting {
+        key: "notes_dir".to_string(),
+        value: json!(crate::notes_dir().map(|dir| dir.display().to_string()).unwrap_or_else(|e| e.to_string())),
+        source: source(config.notes_dir.is_some()),
+    });
+
+    EffectiveConfig { config_path: path.display().to_string(), settings }
+}
+
+// Helper function to get the config file path
+fn config_path() -> PathBuf {
+    let dir = dirs::home_dir().unwrap().join(".minimal-notes");
+    create_dir_all(&dir).ok();
+    dir.join("config.json")
+}
+
+// Load the config, falling back to defaults if the file is missing or unreadable
+pub fn load_config() -> AppConfig {
+    let mut contents = String::new();
+    match File::open(config_path()).and_then(|mut f| f.read_to_string(&mut contents)) {
+        Ok(_) => serde_json::from_str(&contents).unwrap_or_else(|e| {
+            eprintln!("Error parsing config, using defaults: {}", e);
+            AppConfig::default()
+        }),
+        Err(_) => AppConfig::default(),
+    }
+}
+
+pub fn save_config(config: &AppConfig) -> Result<(), String> {
+    let json = serde_json::to_string_pretty(config).map_err(|e| e.to_string())?;
+    File::create(config_path())
+        .and_then(|mut f| f.write_all(json.as_bytes()))
+        .map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_vault_keeps_its_data_where_it_was() {
+        assert_eq!(vault_data_dir(&default_notes_dir()), dirs::home_dir().unwrap().join(".minimal-notes"));
+    }
+
+    #[test]
+    fn other_vaults_get_their_own_data_dirs() {
+        let a = vault_data_dir(Path::new("/vaults/work"));
+        let b = vault_data_dir(Path::new("/vaults/home"));
+        assert_ne!(a, b);
+        assert_eq!(a, vault_data_dir(Path::new("/vaults/work")));
+        assert!(a.starts_with(dirs::home_dir().unwrap().join(".minimal-notes").join("vaults")));
+    }
+
+    fn thinking_config() -> AppConfig {
+        AppConfig { thinking_enabled: true, ..AppConfig::default() }
+    }
+
+    #[test]
+    fn thinking_budget_is_added_on_top_of_the_answer_tokens() {
+        let defaults = generation_defaults_from("chat", &thinking_config());
+        assert_eq!(defaults.thinking_budget, THINKING_BUDGET);
+        let request = defaults.to_generation_config_within(Some(16), None, None);
+        assert_eq!(request.max_output_tokens, Some(16 + THINKING_BUDGET));
+        assert_eq!(request.thinking_config.map(|t| t.thinking_budget), Some(THINKING_BUDGET));
+    }
+
+    #[test]
+    fn ceiling_caps_the_answer_not_the_thinking() {
+        let defaults = generation_defaults_from("summarize", &thinking_config());
+        let request = defaults.to_generation_config_within(None, None, Some(100));
+        assert_eq!(request.max_output_tokens, Some(100 + THINKING_BUDGET));
+    }
+
+    #[test]
+    fn summarize_and_autocomplete_thinking() {
+        let config = thinking_config();
+        assert_eq!(generation_defaults_from("summarize", &config).thinking_budget, THINKING_BUDGET);
+        assert_eq!(generation_defaults_from("autocomplete", &config).thinking_budget, 0);
+        let off = AppConfig::default();
+        let request = generation_defaults_from("summarize", &off).to_generation_config_within(None, None, None);
+        assert_eq!(request.max_output_tokens, Some(256));
+    }
+
+    #[test]
+    fn global_prompt_comes_from_the_saved_config() {
+        let saved = r#"{"global_prompt_prefix": "Be formal.", "prompt_template": "Continue: {prompt}"}"#;
+        let config: AppConfig = serde_json::from_str(saved).unwrap();
+        let wrapped = GlobalPrompt::from_app_config(&config).wrap("Complete the text.");
+        // The per-command prompt template isn't part of the system instruction
+        assert_eq!(wrapped, "Be formal.\n\nComplete the text.");
+        // Configs saved before the setting existed leave instructions untouched
+        let config: AppConfig = serde_json::from_str("{}").unwrap();
+        assert_eq!(GlobalPrompt::from_app_config(&config).wrap("Complete the text."), "Complete the text.");
+    }
+}
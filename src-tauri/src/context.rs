@@ -0,0 +1,265 @@
+// Helpers for extracting the context sent alongside completion prompts
+use crate::Note;
+
+// Line that separates a note's frozen context (above) from its body (below)
+pub const FROZEN_CONTEXT_DELIMITER: &str = "---context---";
+
+// Text above the `---context---` line, if the note has one. This is meant for style
+// guides or glossaries that should inform every completion in the note.
+pub fn frozen_context(content: &str) -> Option<&str> {
+    let mut offset = 0;
+    for line in content.split_inclusive('\n') {
+        if line.trim() == FROZEN_CONTEXT_DELIMITER {
+            let context = content[..offset].trim();
+            return if context.is_empty() { None } else { Some(context) };
+        }
+        offset += line.len();
+    }
+    None
+}
+
+// Metadata value of `mode` marking a note as code, whose context keeps its code blocks
+pub const CODE_NOTE_MODE: &str = "code";
+
+// Drop fenced code blocks (``` or ~~~, fences included) so prose completions aren't steered
+// by code. An unclosed fence drops everything after it.
+pub fn strip_code_blocks(text: &str) -> String {
+    let mut kept = String::with_capacity(text.len());
+    let mut fence: Option<&str> = None;
+    for line in text.split_inclusive('\n') {
+        let trimmed = line.trim_start();
+        match fence {
+            Some(marker) => {
+                if trimmed.starts_with(marker) {
+                    fence = None;
+                }
+            }
+            None => match ["```", "~~~"].into_iter().find(|m| trimmed.starts_with(m)) {
+                Some(marker) => fence = Some(marker),
+                None => kept.push_str(line),
+            },
+        }
+    }
+    kept
+}
+
+// Context to send with completions in `note`: its frozen context, with code blocks removed
+// when `strip_code` is set and the note isn't a code note, laid out with `format` and cut
+// down to `budget`
+pub fn completion_context(note: &Note, strip_code: bool, format: &ContextFormat, budget: &ContextBudget) -> Option<String> {
+    let context = frozen_context(&note.content)?;
+    let is_code_note = note.metadata.get("mode").map(String::as_str) == Some(CODE_NOTE_MODE);
+    let context = if !strip_code || is_code_note {
+        context.to_string()
+    } else {
+        strip_code_blocks(context).trim().to_string()
+    };
+    if context.is_empty() {
+        return None;
+    }
+    Some(format.render_within(&[(note.title.as_str(), context.as_str())], budget))
+}
+
+// How much note text goes into context, in characters: at most `note_max_chars` from any
+// one note and `total_chars` in all, layout included
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, Debug)]
+#[serde(default)]
+pub struct ContextBudget {
+    pub note_max_chars: usize,
+    pub total_chars: usize,
+}
+
+impl Default for ContextBudget {
+    fn default() -> Self {
+        ContextBudget { note_max_chars: 2000, total_chars: 8000 }
+    }
+}
+
+impl ContextBudget {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.note_max_chars == 0 || self.total_chars == 0 {
+            return Err("context budget limits must be positive".to_string());
+        }
+        Ok(())
+    }
+}
+
+// The start of `text`, at most `max_chars` characters long. A cut is made at a word
+// boundary when one is close and marked with "…". Empty if nothing of the text fits.
+pub fn preview(text: &str, max_chars: usize) -> String {
+    if text.chars().count() <= max_chars {
+        return text.to_string();
+    }
+    // Leave room for the marker
+    let Some(keep) = max_chars.checked_sub(1) else {
+        return String::new();
+    };
+    let end = text.char_indices().nth(keep).map_or(text.len(), |(i, _)| i);
+    let cut = &text[..end];
+    // Back up to the last space unless that would lose more than a fifth of the preview
+    let cut = match cut.rfind(char::is_whitespace) {
+        Some(space) if cut[..space].chars().count() >= keep - keep / 5 => &cut[..space],
+        _ => cut,
+    };
+    let cut = cut.trim_end();
+    if cut.is_empty() {
+        return String::new();
+    }
+    format!("{}…", cut)
+}
+
+// How note text is laid out when it's sent to the model as context. Each note is rendered
+// through `template` ({title} and {content} are substituted) and notes are joined with
+// `separator`, so the model can tell where one note ends and which note said what.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct ContextFormat {
+    pub template: String,
+    pub separator: String,
+}
+
+impl Default for ContextFormat {
+    fn default() -> Self {
+        ContextFormat {
+            template: "## {title}\n{content}".to_string(),
+            separator: "\n\n---\n\n".to_string(),
+        }
+    }
+}
+
+impl ContextFormat {
+    pub fn validate(&self) -> Result<(), String> {
+        if !self.template.contains("{content}") {
+            return Err("context template must contain {content}".to_string());
+        }
+        Ok(())
+    }
+
+    // Like render, for notes ranked best first, trimmed to fit `budget`. Each note gets up to
+    // note_max_chars of what's left, so higher-ranked notes keep more of their text and
+    // notes that no longer fit are dropped.
+    pub fn render_within(&self, notes: &[(&str, &str)], budget: &ContextBudget) -> String {
+        let mut remaining = budget.total_chars;
+        let mut kept: Vec<(&str, String)> = Vec::new();
+        for &(title, content) in notes {
+            let separator = if kept.is_empty() { 0 } else { self.separator.chars().count() };
+            let overhead = separator + self.render(&[(title, "")]).chars().count();
+            if overhead >= remaining {
+                break;
+            }
+            let text = preview(content, budget.note_max_chars.min(remaining - overhead));
+            // A heading with none of the note under it isn't worth sending
+            if text.is_empty() && !content.is_empty() {
+                break;
+            }
+            remaining -= overhead + text.chars().count();
+            kept.push((title, text));
+        }
+        let kept: Vec<(&str, &str)> = kept.iter().map(|(title, text)| (*title, text.as_str())).collect();
+        self.render(&kept)
+    }
+
+    // Lay out (title, content) pairs as one context block
+    pub fn render(&self, notes: &[(&str, &str)]) -> String {
+        notes
+            .iter()
+            .map(|(title, content)| {
+                // Split around {content} first so placeholders inside the note text are left alone
+                let (head, tail) = self.template.split_once("{content}").unwrap_or((&self.template, ""));
+                format!("{}{}{}", head.replace("{title}", title), content, tail.replace("{title}", title))
+            })
+            .collect::<Vec<_>>()
+            .join(&self.separator)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn note_with_context(context: &str, mode: Option<&str>) -> Note {
+        let mut note = Note {
+            title: "Guide".to_string(),
+            content: format!("{}\n{}\nbody", context, FROZEN_CONTEXT_DELIMITER),
+            ..Default::default()
+        };
+        if let Some(mode) = mode {
+            note.metadata.insert("mode".to_string(), mode.to_string());
+        }
+        note
+    }
+
+    #[test]
+    fn context_block_has_a_heading_per_note_between_separators() {
+        let block = ContextFormat::default().render(&[("Oven", "Preheat to 200."), ("Flour", "Use bread flour.")]);
+        assert_eq!(block, "## Oven\nPreheat to 200.\n\n---\n\n## Flour\nUse bread flour.");
+    }
+
+    #[test]
+    fn custom_templates_substitute_title_and_content_once() {
+        let format = ContextFormat { template: "[{title}] {content} (from {title})".to_string(), separator: "\n".to_string() };
+        // Placeholders inside a note's own text are left alone
+        let block = format.render(&[("A", "say {title}"), ("B", "two")]);
+        assert_eq!(block, "[A] say {title} (from A)\n[B] two (from B)");
+        assert!(ContextFormat { template: "{title}".to_string(), ..ContextFormat::default() }.validate().is_err());
+    }
+
+    #[test]
+    fn context_stays_within_the_total_budget() {
+        let format = ContextFormat::default();
+        let long = "lorem ipsum dolor ".repeat(50);
+        let notes: Vec<(&str, &str)> = ["First", "Second", "Third", "Fourth"].iter().map(|title| (*title, long.as_str())).collect();
+        for total_chars in [10, 100, 250, 700, 5000] {
+            let budget = ContextBudget { note_max_chars: 300, total_chars };
+            let block = format.render_within(&notes, &budget);
+            assert!(block.chars().count() <= total_chars, "{} chars for a budget of {}", block.chars().count(), total_chars);
+        }
+    }
+
+    #[test]
+    fn higher_ranked_notes_keep_more_text() {
+        let long = "word ".repeat(100);
+        let notes = [("Best", long.as_str()), ("Next", long.as_str())];
+        let block = ContextFormat::default().render_within(&notes, &ContextBudget { note_max_chars: 300, total_chars: 450 });
+        let (best, next) = block.split_once("\n\n---\n\n").unwrap();
+        assert!(best.chars().count() > next.chars().count());
+        assert!(best.ends_with('\u{2026}') && next.ends_with('\u{2026}'));
+        // A note that wouldn't get any text in is left out entirely
+        let block = ContextFormat::default().render_within(&notes, &ContextBudget { note_max_chars: 300, total_chars: 310 });
+        assert!(!block.contains("## Next"));
+    }
+
+    #[test]
+    fn previews_cut_at_a_word_boundary() {
+        assert_eq!(preview("short", 10), "short");
+        assert_eq!(preview("the quick brown fox", 12), "the quick\u{2026}");
+        assert_eq!(preview("anything", 0), "");
+    }
+
+    #[test]
+    fn code_blocks_are_dropped_from_mixed_text() {
+        let text = "Intro\n```rust\nfn main() {}\n```\nMiddle\n  ~~~\nraw\n  ~~~\nEnd\n";
+        assert_eq!(strip_code_blocks(text), "Intro\nMiddle\nEnd\n");
+        // A tilde fence isn't closed by backticks
+        assert_eq!(strip_code_blocks("a\n~~~\n```\nb\n~~~\nc"), "a\nc");
+        assert_eq!(strip_code_blocks("a\n```\nnever closed\n"), "a\n");
+    }
+
+    #[test]
+    fn context_keeps_code_only_when_asked_or_in_code_notes() {
+        let format = ContextFormat { template: "{content}".to_string(), ..ContextFormat::default() };
+        let budget = ContextBudget::default();
+        let context = "Use British spelling.\n```\nlet x = 1;\n```";
+        let prose = note_with_context(context, None);
+        assert_eq!(completion_context(&prose, true, &format, &budget).as_deref(), Some("Use British spelling."));
+        assert_eq!(completion_context(&prose, false, &format, &budget).as_deref(), Some(context));
+        let code = note_with_context(context, Some(CODE_NOTE_MODE));
+        assert_eq!(completion_context(&code, true, &format, &budget).as_deref(), Some(context));
+    }
+
+    #[test]
+    fn context_that_was_all_code_is_none() {
+        let note = note_with_context("```\nlet x = 1;\n```", None);
+        assert_eq!(completion_context(&note, true, &ContextFormat::default(), &ContextBudget::default()), None);
+    }
+}
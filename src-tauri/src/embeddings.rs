@@ -2,7 +2,9 @@ use crate::Note;
 use hnsw_rs::hnsw::Hnsw;
 use hnsw_rs::dist::DistCosine;
 // use anndists::dist::DistCosine; // Comment out if this exists
-use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
 use std::sync::{Arc, Mutex};
 use lazy_static::lazy_static;
 
@@ -10,12 +12,23 @@ use lazy_static::lazy_static;
 #[derive(Debug)]
 pub enum EmbeddingError {
     NotFound,
+    Io(String),
+    Corrupt(String),
+    // Adding the vectors would take the index past HnswParams::max_elements
+    CapacityExceeded(usize),
 }
 
 impl std::fmt::Display for EmbeddingError {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
             EmbeddingError::NotFound => write!(f, "Item not found"),
+            EmbeddingError::Io(msg) => write!(f, "Index file error: {}", msg),
+            EmbeddingError::Corrupt(msg) => write!(f, "Index file is corrupt: {}", msg),
+            EmbeddingError::CapacityExceeded(max) => write!(
+                f,
+                "Embedding index is full ({} vectors); raise max_elements to index more notes",
+                max
+            ),
         }
     }
 }
@@ -37,20 +50,284 @@ pub fn get_embedding_manager() -> Arc<Mutex<EmbeddingManager>> {
 
 // Function moved to be a method of EmbeddingManager
 
+// Dimension of the vectors produced by generate_simple_embedding
+pub const EMBEDDING_DIMENSION: usize = 128;
+
+// Name of the embedding backend, reported to the UI
+pub const EMBEDDING_BACKEND: &str = "simple-char-hash";
+
+
+// Share of max_elements in use at which the index status warns that it's nearly full
+const NEAR_CAPACITY_RATIO: f32 = 0.9;
+
+// How many extra candidates to fetch per requested result, since several chunks of the
+// same note can crowd out other notes before deduplication
+const CHUNK_OVERSAMPLE: usize = 4;
+
+// How notes are turned into vectors
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum EmbeddingStrategy {
+    // One vector for "{title} {content}"
+    #[default]
+    Whole,
+    // One vector per window of roughly `chunk_chars` characters of content, each blended
+    // with the title vector scaled by `title_weight`. Long notes then surface when any part
+    // of them matches, and the title isn't drowned out by the body.
+    Chunked { chunk_chars: usize, title_weight: f32 },
+}
+
+impl EmbeddingStrategy {
+    pub fn validate(&self) -> Result<(), String> {
+        if let EmbeddingStrategy::Chunked { chunk_chars, title_weight } = self {
+            if *chunk_chars < 50 {
+                return Err("chunk_chars must be at least 50".to_string());
+            }
+            if !(0.0..=10.0).contains(title_weight) {
+                return Err("title_weight must be between 0 and 10".to_string());
+            }
+        }
+        Ok(())
+    }
+}
+
+// Split text into windows of roughly `chunk_chars` bytes, breaking only on whitespace
+fn chunk_text(text: &str, chunk_chars: usize) -> Vec<&str> {
+    let mut chunks = Vec::new();
+    let mut chunk_start: Option<usize> = None;
+    let mut chunk_end = 0;
+    let mut word_start: Option<usize> = None;
+    // A trailing sentinel space flushes the last word
+    for (i, c) in text.char_indices().chain(std::iter::once((text.len(), ' '))) {
+        if !c.is_whitespace() {
+            word_start.get_or_insert(i);
+            continue;
+        }
+        let Some(start) = word_start.take() else {
+            continue;
+        };
+        match chunk_start {
+            Some(cs) if i - cs > chunk_chars => {
+                chunks.push(&text[cs..chunk_end]);
+                chunk_start = Some(start);
+            }
+            None => chunk_start = Some(start),
+            _ => {}
+        }
+        chunk_end = i;
+    }
+    if let Some(cs) = chunk_start {
+        chunks.push(&text[cs..chunk_end]);
+    }
+    chunks
+}
+
+// Tuning knobs for the HNSW graph. Higher values trade memory and indexing/search time for
+// better recall; the defaults are plenty for a personal vault.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(default)]
+pub struct HnswParams {
+    // Links kept per node. More links improve recall on large vaults at the cost of memory
+    // and slower inserts; 12-48 is the useful range.
+    pub max_nb_connection: usize,
+    // Candidates considered while inserting. Higher builds a better graph but indexes slower.
+    pub ef_construction: usize,
+    // Upper bound on graph layers. Rarely worth changing.
+    pub nb_layer: usize,
+    // Candidates considered per query. Higher improves recall but makes each search slower.
+    pub ef_search: usize,
+    // Smallest capacity an index is created with
+    pub min_elements: usize,
+    // Hard ceiling on stored vectors. Adding past it fails with a clear error rather than
+    // letting the graph degrade.
+    pub max_elements: usize,
+    // Capacity is the vector count times this factor, so the index can grow before it has
+    // to be rebuilt with a larger cap
+    pub headroom: f32,
+}
+
+impl Default for HnswParams {
+    fn default() -> Self {
+        HnswParams {
+            max_nb_connection: 16,
+            ef_construction: 200,
+            nb_layer: 16,
+            ef_search: 50,
+            min_elements: 10000,
+            max_elements: 1_000_000,
+            headroom: 2.0,
+        }
+    }
+}
+
+impl HnswParams {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(2..=256).contains(&self.max_nb_connection) {
+            return Err("max_nb_connection must be between 2 and 256".to_string());
+        }
+        if self.ef_construction < self.max_nb_connection {
+            return Err("ef_construction must be at least max_nb_connection".to_string());
+        }
+        if !(1..=16).contains(&self.nb_layer) {
+            return Err("nb_layer must be between 1 and 16".to_string());
+        }
+        if self.ef_search == 0 {
+            return Err("ef_search must be positive".to_string());
+        }
+        if self.min_elements == 0 {
+            return Err("min_elements must be positive".to_string());
+        }
+        if self.max_elements < self.min_elements {
+            return Err("max_elements must be at least min_elements".to_string());
+        }
+        if self.headroom < 1.0 {
+            return Err("headroom must be at least 1.0".to_string());
+        }
+        Ok(())
+    }
+
+    // Capacity to create an index with when it has to hold `vectors` vectors
+    pub fn capacity_for(&self, vectors: usize) -> usize {
+        self.min_elements
+            .max((vectors as f32 * self.headroom).ceil() as usize)
+            .min(self.max_elements)
+    }
+}
+
+// Slot accounting for the index. HNSW can't delete, so removed notes leave tombstones
+// behind until the index is rebuilt.
+#[derive(serde::Serialize, Clone)]
+pub struct IndexStats {
+    pub live: usize,
+    pub tombstones: usize,
+    pub capacity: usize,
+}
+
+// What gets written to disk. HNSW graphs are cheap to rebuild from stored vectors, so we
+// persist the vectors (the expensive part once a real embedding model is used) and re-insert
+// them on load.
+#[derive(Serialize, Deserialize)]
+struct IndexDump {
+    // Backend the vectors came from. Missing (empty) in older dumps, which forces a rebuild.
+    #[serde(default)]
+    model: String,
+    dimension: usize,
+    strategy: EmbeddingStrategy,
+    embeddings: HashMap<String, Vec<Vec<f32>>>,
+    // Content hash of each note when it was embedded. Missing in older dumps.
+    #[serde(default)]
+    hashes: HashMap<String, String>,
+}
+
+// Just the fields of an IndexDump that say what built it
+#[derive(Deserialize)]
+struct IndexHeader {
+    #[serde(default)]
+    model: String,
+    dimension: usize,
+}
+
+// Whether the saved and loaded indexes were built by the current embedding backend
+#[derive(serde::Serialize, Clone)]
+pub struct IndexVerification {
+    pub expected_model: String,
+    pub expected_dimension: usize,
+    // What the index file on disk says, if there is a readable one
+    pub stored_model: Option<String>,
+    pub stored_dimension: Option<usize>,
+    // What the vectors in memory were built with
+    pub loaded_model: String,
+    pub loaded_dimension: usize,
+    pub rebuild_required: bool,
+}
+
+// Result of bringing the index back in line with the notes on disk
+#[derive(serde::Serialize, Clone)]
+pub struct ReconcileReport {
+    pub added: usize,
+    pub removed: usize,
+    pub unchanged: usize,
+}
+
+// Result of re-embedding only the notes that changed since they were indexed
+#[derive(serde::Serialize, Clone)]
+pub struct RefreshReport {
+    pub added: usize,
+    pub changed: usize,
+    pub removed: usize,
+    pub unchanged: usize,
+}
+
+// Snapshot of the index state for the UI
+#[derive(serde::Serialize, Clone)]
+pub struct IndexStatus {
+    pub ready: bool,
+    pub indexed_notes: usize,
+    pub backend: String,
+    pub dimension: usize,
+    // Slots in the current graph, and the most it may grow to
+    pub capacity: usize,
+    pub max_elements: usize,
+    // Set once the live vectors fill most of max_elements
+    pub near_capacity: bool,
+}
+
+// Whether the embedding backend can produce usable vectors right now
+#[derive(serde::Serialize, Clone)]
+pub struct BackendStatus {
+    pub backend: String,
+    pub reachable: bool,
+    pub expected_dimension: usize,
+    // Dimension of the probe vector, if one was produced
+    pub probe_dimension: Option<usize>,
+    pub error: Option<String>,
+}
+
+// Embed a short probe string and check the result, without touching the index
+pub fn probe_backend() -> BackendStatus {
+    let vector = EmbeddingManager::generate_simple_embedding("embedding backend probe");
+    let error = if vector.len() != EMBEDDING_DIMENSION {
+        Some(format!("expected {} dimensions, got {}", EMBEDDING_DIMENSION, vector.len()))
+    } else if vector.iter().any(|v| !v.is_finite()) {
+        Some("probe vector contains non-finite values".to_string())
+    } else {
+        None
+    };
+    BackendStatus {
+        backend: EMBEDDING_BACKEND.to_string(),
+        reachable: error.is_none(),
+        expected_dimension: EMBEDDING_DIMENSION,
+        probe_dimension: Some(vector.len()),
+        error,
+    }
+}
+
 // EmbeddingManager struct to manage HNSW index and note mappings
 pub struct EmbeddingManager {
     index: Option<Hnsw<f32, DistCosine>>,
-    note_to_id: HashMap<String, usize>,
+    // A note has one index slot per vector (several when chunked)
+    note_to_id: HashMap<String, Vec<usize>>,
     id_to_note: HashMap<usize, String>,
+    // Vectors of live notes, kept so they can be reused without re-embedding
+    embeddings: HashMap<String, Vec<Vec<f32>>>,
+    // Hash of the text each note's vectors were computed from, to spot edits
+    hashes: HashMap<String, String>,
     next_id: usize,
+    strategy: EmbeddingStrategy,
+    params: HnswParams,
+    // Number of slots the current graph was created with
+    capacity: usize,
+    // Backend and dimension the vectors in memory were produced with
+    model: String,
+    dimension: usize,
 }
 
 impl EmbeddingManager {
     pub fn generate_simple_embedding(text: &str) -> Vec<f32> {
         // TODO use an embedding model here
-        let mut embedding = vec![0.0; 128];
+        let mut embedding = vec![0.0; EMBEDDING_DIMENSION];
         for (i, _c) in text.chars().enumerate() {
-            let idx = (_c as usize) % 128;
+            let idx = (_c as usize) % EMBEDDING_DIMENSION;
             embedding[idx] += 1.0 / (i as f32 + 1.0);
         }
 
@@ -61,63 +338,259 @@ impl EmbeddingManager {
         embedding
     }
     
+    // All vectors for a note under the current strategy
+    pub fn embed_note(&self, note: &Note) -> Vec<Vec<f32>> {
+        match self.strategy {
+            EmbeddingStrategy::Whole => {
+                vec![Self::generate_simple_embedding(&format!("{} {}", note.title, note.content))]
+            }
+            EmbeddingStrategy::Chunked { chunk_chars, title_weight } => {
+                let title = Self::generate_simple_embedding(&note.title);
+                let chunks = chunk_text(&note.content, chunk_chars);
+                if chunks.is_empty() {
+                    return vec![title];
+                }
+                chunks
+                    .into_iter()
+                    .map(|chunk| {
+                        let body = Self::generate_simple_embedding(chunk);
+                        let blended: Vec<f32> = body.iter().zip(&title).map(|(b, t)| b + t * title_weight).collect();
+                        normalize(blended)
+                    })
+                    .collect()
+            }
+        }
+    }
+
+    // A single note-level vector: the stored vectors averaged, or freshly computed ones otherwise
+    pub fn embedding_for(&self, note: &Note) -> Vec<f32> {
+        let vectors = self
+            .embeddings
+            .get(&note.id)
+            .cloned()
+            .unwrap_or_else(|| self.embed_note(note));
+        let mut mean = vec![0.0; EMBEDDING_DIMENSION];
+        for vector in &vectors {
+            mean.iter_mut().zip(vector).for_each(|(m, v)| *m += v);
+        }
+        normalize(mean)
+    }
+
+    pub fn strategy(&self) -> EmbeddingStrategy {
+        self.strategy
+    }
+
+    // Switch strategies. Existing vectors are stale afterwards, so callers should rebuild.
+    pub fn set_strategy(&mut self, strategy: EmbeddingStrategy) {
+        self.strategy = strategy;
+    }
+
+    // Cosine similarity of two vectors, clamped to [0, 1]
+    pub fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+        let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+        let norm_a = a.iter().map(|x| x * x).sum::<f32>().sqrt();
+        let norm_b = b.iter().map(|x| x * x).sum::<f32>().sqrt();
+        if norm_a == 0.0 || norm_b == 0.0 {
+            return 0.0;
+        }
+        (dot / (norm_a * norm_b)).clamp(0.0, 1.0)
+    }
+    
     pub fn new() -> Self {
         EmbeddingManager {
             index: None,
             note_to_id: HashMap::new(),
             id_to_note: HashMap::new(),
+            embeddings: HashMap::new(),
+            hashes: HashMap::new(),
             next_id: 0,
+            strategy: EmbeddingStrategy::default(),
+            params: HnswParams::default(),
+            capacity: 0,
+            model: EMBEDDING_BACKEND.to_string(),
+            dimension: EMBEDDING_DIMENSION,
+        }
+    }
+
+    // Whether the vectors in memory come from the current backend, so searching them
+    // against freshly embedded queries is meaningful
+    pub fn is_current(&self) -> bool {
+        self.model == EMBEDDING_BACKEND && self.dimension == EMBEDDING_DIMENSION
+    }
+
+    // Compare the loaded index and the one saved at `path` with the current backend
+    pub fn verify(&self, path: &Path) -> IndexVerification {
+        let header: Option<IndexHeader> = std::fs::read_to_string(path)
+            .ok()
+            .and_then(|json| serde_json::from_str(&json).ok());
+        let stored_current = header
+            .as_ref()
+            .is_none_or(|header| header.model == EMBEDDING_BACKEND && header.dimension == EMBEDDING_DIMENSION);
+        IndexVerification {
+            expected_model: EMBEDDING_BACKEND.to_string(),
+            expected_dimension: EMBEDDING_DIMENSION,
+            stored_model: header.as_ref().map(|header| header.model.clone()),
+            stored_dimension: header.as_ref().map(|header| header.dimension),
+            loaded_model: self.model.clone(),
+            loaded_dimension: self.dimension,
+            rebuild_required: !self.is_current() || !stored_current,
+        }
+    }
+
+    pub fn params(&self) -> HnswParams {
+        self.params
+    }
+
+    // Change the graph parameters. Takes effect the next time the graph is built.
+    pub fn set_params(&mut self, params: HnswParams) {
+        self.params = params;
+    }
+
+    pub fn status(&self) -> IndexStatus {
+        IndexStatus {
+            ready: self.index.is_some(),
+            indexed_notes: self.note_to_id.len(),
+            backend: EMBEDDING_BACKEND.to_string(),
+            dimension: EMBEDDING_DIMENSION,
+            capacity: self.capacity,
+            max_elements: self.params.max_elements,
+            near_capacity: self.live_vectors() as f32 >= self.params.max_elements as f32 * NEAR_CAPACITY_RATIO,
+        }
+    }
+
+    // Number of vectors belonging to live notes
+    fn live_vectors(&self) -> usize {
+        self.note_to_id.values().map(Vec::len).sum()
+    }
+
+    pub fn stats(&self) -> IndexStats {
+        let live = self.live_vectors();
+        IndexStats {
+            live,
+            tombstones: self.next_id - live,
+            capacity: self.capacity,
         }
     }
 
     pub fn initialize(&mut self) -> Result<(), EmbeddingError> {
-        let max_elements = 10000; // Maximum number of elements in the index
-        let max_nb_connection = 16; // Maximum number of connections per element
-        let ef_construction = 200; // Size of the dynamic candidate list for construction
-        let nb_layer = 16; // Number of layers in the graph
-        
+        self.initialize_for(0)
+    }
+
+    // Create an empty graph with room for at least `vectors` vectors plus headroom
+    fn initialize_for(&mut self, vectors: usize) -> Result<(), EmbeddingError> {
+        if vectors > self.params.max_elements {
+            return Err(EmbeddingError::CapacityExceeded(self.params.max_elements));
+        }
+        self.capacity = self.params.capacity_for(vectors);
         self.index = Some(Hnsw::new(
-            max_nb_connection,
-            max_elements,
-            nb_layer,
-            ef_construction,
+            self.params.max_nb_connection,
+            self.capacity,
+            self.params.nb_layer,
+            self.params.ef_construction,
             DistCosine {}
         ));
         
         Ok(())
     }
 
+    // Rebuild the graph from the stored vectors with the current parameters, sized for
+    // at least `vectors` vectors. Tombstoned slots are dropped along the way.
+    pub fn rebuild_graph(&mut self, vectors: usize) -> Result<(), EmbeddingError> {
+        let vectors = vectors.max(self.live_vectors());
+        // Check before clearing so a failed grow leaves the current graph usable
+        if vectors > self.params.max_elements {
+            return Err(EmbeddingError::CapacityExceeded(self.params.max_elements));
+        }
+        let embeddings = std::mem::take(&mut self.embeddings);
+        let hashes = std::mem::take(&mut self.hashes);
+        self.clear();
+        self.initialize_for(vectors)?;
+        for (note_id, vectors) in embeddings {
+            self.insert_embeddings(note_id, vectors)?;
+        }
+        self.hashes = hashes;
+        Ok(())
+    }
+
     pub fn add_note(&mut self, note: &Note) -> Result<(), EmbeddingError> {
         if self.index.is_none() {
             self.initialize()?;
         }
+        let embeddings = self.embed_note(note);
+        self.insert_embeddings(note.id.clone(), embeddings)?;
+        self.hashes.insert(note.id.clone(), content_hash(note));
+        Ok(())
+    }
+
+    // Insert precomputed vectors for a note. The index must already be initialized.
+    fn insert_embeddings(&mut self, note_id: String, embeddings: Vec<Vec<f32>>) -> Result<(), EmbeddingError> {
+        // The graph can't grow in place, so rebuild it with a larger cap once it's full
+        if self.next_id + embeddings.len() > self.capacity {
+            log::info!("Embedding index is full ({} slots), rebuilding with more room", self.capacity);
+            self.rebuild_graph(self.live_vectors() + embeddings.len())?;
+        }
         let index = self.index.as_mut().unwrap();
-        let text = format!("{} {}", note.title, note.content);
-        let embedding = Self::generate_simple_embedding(&text);
-        let id = self.next_id;
-        
-        // Create a tuple with the embedding slice and the ID
-        let data_point = (&embedding, id);
-        index.insert(data_point);
-        
-        self.note_to_id.insert(note.id.clone(), id);
-        self.id_to_note.insert(id, note.id.clone());
-        self.next_id += 1;
+        let mut ids = Vec::with_capacity(embeddings.len());
+        for embedding in &embeddings {
+            let id = self.next_id;
+            
+            // Create a tuple with the embedding slice and the ID
+            let data_point = (embedding, id);
+            index.insert(data_point);
+            
+            self.id_to_note.insert(id, note_id.clone());
+            ids.push(id);
+            self.next_id += 1;
+        }
+        self.note_to_id.insert(note_id.clone(), ids);
+        self.embeddings.insert(note_id, embeddings);
         Ok(())
     }
 
+    pub fn contains(&self, note_id: &str) -> bool {
+        self.note_to_id.contains_key(note_id)
+    }
+
+    // Ids of the given notes that have no vectors in the index, in their listed order
+    pub fn unindexed(&self, notes: &[Note]) -> Vec<String> {
+        notes.iter().filter(|note| !self.contains(&note.id)).map(|note| note.id.clone()).collect()
+    }
+
+    // Whether a note is missing from the index or has changed since it was embedded
+    pub fn is_stale(&self, note: &Note) -> bool {
+        self.hashes.get(&note.id) != Some(&content_hash(note))
+    }
+
     pub fn update_note(&mut self, note: &Note) -> Result<(), EmbeddingError> {
-        // Remove the old note if it exists
+        if self.index.is_none() {
+            self.initialize()?;
+        }
+        let embeddings = self.embed_note(note);
+        // Make room before dropping the old vectors, so a note that no longer fits keeps the
+        // ones it has instead of falling out of the index
+        let old_vectors = self.note_to_id.get(&note.id).map_or(0, Vec::len);
+        if self.next_id + embeddings.len() > self.capacity {
+            self.rebuild_graph(self.live_vectors() - old_vectors + embeddings.len())?;
+        }
         if self.note_to_id.contains_key(&note.id) {
             self.remove_note(note)?;
         }
-        // Add the updated note
-        self.add_note(note)
+        self.insert_embeddings(note.id.clone(), embeddings)?;
+        self.hashes.insert(note.id.clone(), content_hash(note));
+        Ok(())
     }
 
     pub fn remove_note(&mut self, note: &Note) -> Result<(), EmbeddingError> {
-        if let Some(id) = self.note_to_id.remove(&note.id) {
-            self.id_to_note.remove(&id);
+        self.remove_note_id(&note.id)
+    }
+
+    pub fn remove_note_id(&mut self, note_id: &str) -> Result<(), EmbeddingError> {
+        if let Some(ids) = self.note_to_id.remove(note_id) {
+            for id in ids {
+                self.id_to_note.remove(&id);
+            }
+            self.embeddings.remove(note_id);
+            self.hashes.remove(note_id);
             // Note: HNSW doesn't support removal, so we just remove from our mappings
             // The actual vector in the index will remain but won't be accessible
             Ok(())
@@ -127,50 +600,577 @@ impl EmbeddingManager {
     }
 
     pub fn search(&mut self, query: &str, k: usize, distance_cutoff: Option<f32>) -> Result<Vec<String>, EmbeddingError> {
-        if self.index.is_none() {
-            return Ok(vec![]);
-        }
-        
-        let index = self.index.as_ref().unwrap();
         let embedding = Self::generate_simple_embedding(query);
-        let ef_search = 50; // Size of the dynamic candidate list for searching
-        
-        let neighbors = index.search(&embedding, k, ef_search);
+        let neighbors = self.search_embedding(&embedding, k)?;
         
         let mut result = Vec::new();
-        for neighbor in neighbors {
+        for (note_id, distance) in neighbors {
             // Apply distance cutoff if specified
             if let Some(cutoff) = distance_cutoff {
-                if neighbor.distance > cutoff {
+                if distance > cutoff {
                     continue; // Skip this result if it's beyond the cutoff
                 }
             }
-            
-            if let Some(note_id) = self.id_to_note.get(&neighbor.d_id) {
-                result.push(note_id.clone());
-            }
+            result.push(note_id);
         }
         
         Ok(result)
     }
+
+    // Nearest live notes to an embedding, with their cosine distance (closest first).
+    // Each note appears once, at the distance of its best-matching chunk.
+    pub fn search_embedding(&self, embedding: &[f32], k: usize) -> Result<Vec<(String, f32)>, EmbeddingError> {
+        Ok(best_per_note(self.search_chunks(embedding, k)?, k))
+    }
+
+    // Nearest live vectors to an embedding, closest first, without merging chunks of the
+    // same note. With chunked embeddings this oversamples so that `k` distinct notes usually
+    // survive dedup_hits.
+    pub fn search_chunks(&self, embedding: &[f32], k: usize) -> Result<Vec<ChunkHit>, EmbeddingError> {
+        let index = match self.index.as_ref() {
+            Some(index) => index,
+            None => return Ok(vec![]),
+        };
+        let candidates = match self.strategy {
+            EmbeddingStrategy::Whole => k,
+            EmbeddingStrategy::Chunked { .. } => k * CHUNK_OVERSAMPLE,
+        };
+        let ef_search = self.params.ef_search.max(candidates); // Size of the dynamic candidate list for searching
+        
+        let mut hits: Vec<ChunkHit> = index
+            .search(embedding, candidates, ef_search)
+            .into_iter()
+            .filter_map(|neighbor| {
+                let note_id = self.id_to_note.get(&neighbor.d_id)?;
+                let chunk = self.note_to_id.get(note_id)?.iter().position(|&id| id == neighbor.d_id)?;
+                Some(ChunkHit { note_id: note_id.clone(), chunk, distance: neighbor.distance })
+            })
+            .collect();
+        hits.sort_by(|a, b| a.distance.total_cmp(&b.distance));
+        Ok(hits)
+    }
     
-    pub fn rebuild_index(&mut self, notes: &[Note]) -> Result<(), EmbeddingError> {
-        // Clear existing data
+    // Notes nearest to `note`, closest first, with their cosine distance. Each of the note's
+    // vectors is searched, so under the chunked strategy notes are compared chunk to chunk
+    // and ranked by their closest pair; the note itself is left out.
+    pub fn related_to(&self, note: &Note, k: usize) -> Result<Vec<(String, f32)>, EmbeddingError> {
+        let vectors = self.embeddings.get(&note.id).cloned().unwrap_or_else(|| self.embed_note(note));
+        let mut hits = Vec::new();
+        for vector in &vectors {
+            hits.extend(self.search_chunks(vector, k + 1)?);
+        }
+        Ok(closest_other_notes(hits, &note.id, k))
+    }
+    
+    // Whether the indexed notes differ from `notes`, so reconcile has something to do
+    pub fn needs_reconcile(&self, notes: &[Note]) -> bool {
+        self.note_to_id.len() != notes.len() || notes.iter().any(|note| !self.note_to_id.contains_key(&note.id))
+    }
+
+    // Add notes missing from the index and tombstone indexed notes no longer on disk,
+    // leaving everything else untouched. Cheaper than rebuild_index for large vaults.
+    pub fn reconcile(&mut self, notes: &[Note]) -> Result<ReconcileReport, EmbeddingError> {
+        let on_disk: HashSet<&str> = notes.iter().map(|n| n.id.as_str()).collect();
+        let stale: Vec<String> = self
+            .note_to_id
+            .keys()
+            .filter(|id| !on_disk.contains(id.as_str()))
+            .cloned()
+            .collect();
+        for id in &stale {
+            self.remove_note_id(id)?;
+        }
+
+        let mut added = 0;
+        for note in notes {
+            if !self.note_to_id.contains_key(&note.id) {
+                self.add_note(note)?;
+                added += 1;
+            }
+        }
+
+        Ok(ReconcileReport {
+            added,
+            removed: stale.len(),
+            unchanged: notes.len() - added,
+        })
+    }
+
+    // Like reconcile, but also re-embeds notes whose content changed since they were indexed
+    pub fn refresh(&mut self, notes: &[Note]) -> Result<RefreshReport, EmbeddingError> {
+        let on_disk: HashSet<&str> = notes.iter().map(|n| n.id.as_str()).collect();
+        let stale: Vec<String> = self
+            .note_to_id
+            .keys()
+            .filter(|id| !on_disk.contains(id.as_str()))
+            .cloned()
+            .collect();
+        for id in &stale {
+            self.remove_note_id(id)?;
+        }
+
+        let mut report = RefreshReport { added: 0, changed: 0, removed: stale.len(), unchanged: 0 };
+        for note in notes {
+            if !self.note_to_id.contains_key(&note.id) {
+                self.add_note(note)?;
+                report.added += 1;
+            } else if self.hashes.get(&note.id) != Some(&content_hash(note)) {
+                self.update_note(note)?;
+                report.changed += 1;
+            } else {
+                report.unchanged += 1;
+            }
+        }
+        Ok(report)
+    }
+
+    pub fn save_to_disk(&self, path: &Path) -> Result<(), EmbeddingError> {
+        let dump = IndexDump {
+            model: self.model.clone(),
+            dimension: self.dimension,
+            strategy: self.strategy,
+            embeddings: self.embeddings.clone(),
+            hashes: self.hashes.clone(),
+        };
+        let json = serde_json::to_string(&dump).map_err(|e| EmbeddingError::Io(e.to_string()))?;
+        // Write to a temp file first so a crash mid-write can't leave a truncated index
+        let tmp = path.with_extension("json.tmp");
+        std::fs::write(&tmp, json).map_err(|e| EmbeddingError::Io(e.to_string()))?;
+        std::fs::rename(&tmp, path).map_err(|e| EmbeddingError::Io(e.to_string()))
+    }
+
+    // Load a saved index. Vectors for notes that no longer exist are skipped; callers
+    // should reconcile afterwards to pick up notes added since the dump.
+    pub fn load_from_disk(&mut self, path: &Path, notes: &[Note]) -> Result<(), EmbeddingError> {
+        let json = std::fs::read_to_string(path).map_err(|e| EmbeddingError::Io(e.to_string()))?;
+        let dump: IndexDump = serde_json::from_str(&json).map_err(|e| EmbeddingError::Corrupt(e.to_string()))?;
+        if dump.dimension != EMBEDDING_DIMENSION
+            || dump.embeddings.values().flatten().any(|v| v.len() != EMBEDDING_DIMENSION)
+        {
+            return Err(EmbeddingError::Corrupt("embedding dimension mismatch".to_string()));
+        }
+        if dump.model != EMBEDDING_BACKEND {
+            return Err(EmbeddingError::Corrupt(format!(
+                "index was built with embedding model {:?}, expected {:?}",
+                dump.model, EMBEDDING_BACKEND
+            )));
+        }
+        if dump.strategy != self.strategy {
+            return Err(EmbeddingError::Corrupt("index was built with a different embedding strategy".to_string()));
+        }
+
+        let live: HashSet<&str> = notes.iter().map(|n| n.id.as_str()).collect();
+        let embeddings: Vec<(String, Vec<Vec<f32>>)> = dump
+            .embeddings
+            .into_iter()
+            .filter(|(note_id, _)| live.contains(note_id.as_str()))
+            .collect();
+
+        self.clear();
+        self.model = dump.model;
+        self.dimension = dump.dimension;
+        self.initialize_for(embeddings.iter().map(|(_, v)| v.len()).sum())?;
+        for (note_id, embeddings) in embeddings {
+            self.insert_embeddings(note_id, embeddings)?;
+        }
+        self.hashes = dump
+            .hashes
+            .into_iter()
+            .filter(|(note_id, _)| self.note_to_id.contains_key(note_id))
+            .collect();
+        Ok(())
+    }
+
+    // Load the saved index, or rebuild from `notes` if it's missing or unreadable.
+    // A corrupt file is deleted so it can't fail the next startup too; one that couldn't be
+    // read (say, a locked or briefly unavailable drive) is left for next time. An index built with
+    // another model or dimension never loads, so searches can't compare its vectors with
+    // the current model's.
+    pub fn load_or_rebuild(&mut self, path: &Path, notes: &[Note]) -> Result<(), EmbeddingError> {
+        if !path.exists() {
+            return self.rebuild_index(notes);
+        }
+        match self.load_from_disk(path, notes) {
+            Ok(()) => Ok(()),
+            Err(e) => {
+                log::error!("Failed to load embedding index, rebuilding: {}", e);
+                if matches!(e, EmbeddingError::Corrupt(_)) {
+                    if let Err(e) = std::fs::remove_file(path) {
+                        log::error!("Failed to delete bad index file: {}", e);
+                    }
+                }
+                self.rebuild_index(notes)
+            }
+        }
+    }
+
+    fn clear(&mut self) {
         self.index = None;
+        self.capacity = 0;
         self.note_to_id.clear();
         self.id_to_note.clear();
+        self.embeddings.clear();
+        self.hashes.clear();
         self.next_id = 0;
+    }
+
+    pub fn rebuild_index(&mut self, notes: &[Note]) -> Result<(), EmbeddingError> {
+        // Clear existing data
+        self.clear();
+        self.model = EMBEDDING_BACKEND.to_string();
+        self.dimension = EMBEDDING_DIMENSION;
         
-        // Initialize a new index
-        self.initialize()?;
+        // Embed everything up front so the new index can be sized to fit
+        let embedded: Vec<(String, Vec<Vec<f32>>)> = notes
+            .iter()
+            .map(|note| (note.id.clone(), self.embed_note(note)))
+            .collect();
+        self.hashes = notes.iter().map(|note| (note.id.clone(), content_hash(note))).collect();
+        self.initialize_for(embedded.iter().map(|(_, v)| v.len()).sum())?;
         
         // Add all notes to the index
-        for note in notes {
-            self.add_note(note)?;
+        for (note_id, embeddings) in embedded {
+            self.insert_embeddings(note_id, embeddings)?;
         }
         
         Ok(())
     }
 }
 
+// One matching vector: which note and which of its chunks (0 for whole-note embeddings)
+#[derive(Serialize, Clone, Debug)]
+pub struct ChunkHit {
+    pub note_id: String,
+    pub chunk: usize,
+    pub distance: f32,
+}
+
+// The `k` notes other than `exclude` with the closest hits, from hits of several searches
+fn closest_other_notes(mut hits: Vec<ChunkHit>, exclude: &str, k: usize) -> Vec<(String, f32)> {
+    hits.retain(|hit| hit.note_id != exclude);
+    best_per_note(hits, k)
+}
+
+// The `k` notes with the closest hits, each at the distance of its best chunk
+fn best_per_note(mut hits: Vec<ChunkHit>, k: usize) -> Vec<(String, f32)> {
+    hits.sort_by(|a, b| a.distance.total_cmp(&b.distance));
+    dedup_hits(hits).into_iter().take(k).map(|hit| (hit.note_id, hit.distance)).collect()
+}
+
+// Keep only each note's best hit, preserving the order of `hits`, which must be sorted
+// closest first. Every search that ranks notes rather than chunks goes through this.
+pub fn dedup_hits(hits: Vec<ChunkHit>) -> Vec<ChunkHit> {
+    let mut seen = HashSet::new();
+    hits.into_iter().filter(|hit| seen.insert(hit.note_id.clone())).collect()
+}
+
+// Hash of everything a note's embedding depends on
+fn content_hash(note: &Note) -> String {
+    crate::exact_content_hash(note)
+}
+
+// Group vectors into (at most) `k` clusters with k-means, returning each vector's cluster.
+// Seeds are picked farthest-first from the first vector so results are deterministic.
+pub fn kmeans(vectors: &[Vec<f32>], k: usize, iterations: usize) -> Vec<usize> {
+    if vectors.is_empty() || k == 0 {
+        return vec![];
+    }
+    let k = k.min(vectors.len());
+    let distance = |a: &[f32], b: &[f32]| a.iter().zip(b).map(|(x, y)| (x - y).powi(2)).sum::<f32>();
 
+    let mut centroids = vec![vectors[0].clone()];
+    while centroids.len() < k {
+        let farthest = vectors
+            .iter()
+            .max_by(|a, b| {
+                let da = centroids.iter().map(|c| distance(a, c)).fold(f32::MAX, f32::min);
+                let db = centroids.iter().map(|c| distance(b, c)).fold(f32::MAX, f32::min);
+                da.total_cmp(&db)
+            })
+            .cloned()
+            .unwrap_or_default();
+        centroids.push(farthest);
+    }
+
+    let mut assignment = vec![0; vectors.len()];
+    for _ in 0..iterations {
+        let mut changed = false;
+        for (i, vector) in vectors.iter().enumerate() {
+            let nearest = (0..k)
+                .min_by(|&a, &b| distance(vector, &centroids[a]).total_cmp(&distance(vector, &centroids[b])))
+                .unwrap_or(0);
+            if assignment[i] != nearest {
+                assignment[i] = nearest;
+                changed = true;
+            }
+        }
+        // Move each centroid to the mean of its members; empty clusters keep their centroid
+        for (cluster, centroid) in centroids.iter_mut().enumerate() {
+            let members: Vec<&Vec<f32>> = vectors.iter().zip(&assignment).filter(|(_, &a)| a == cluster).map(|(v, _)| v).collect();
+            if members.is_empty() {
+                continue;
+            }
+            let mut mean = vec![0.0; centroid.len()];
+            for member in &members {
+                mean.iter_mut().zip(member.iter()).for_each(|(m, v)| *m += v);
+            }
+            mean.iter_mut().for_each(|m| *m /= members.len() as f32);
+            *centroid = mean;
+        }
+        if !changed {
+            break;
+        }
+    }
+    assignment
+}
+
+fn normalize(mut vector: Vec<f32>) -> Vec<f32> {
+    let magnitude: f32 = vector.iter().map(|x| x.powi(2)).sum::<f32>().sqrt();
+    if magnitude > 0.0 {
+        vector.iter_mut().for_each(|val| *val /= magnitude);
+    }
+    vector
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn note(id: &str, content: &str) -> Note {
+        Note { id: id.to_string(), title: id.to_string(), content: content.to_string(), ..Default::default() }
+    }
+
+    fn small_manager(max_elements: usize) -> EmbeddingManager {
+        let mut manager = EmbeddingManager::new();
+        manager.set_params(HnswParams { min_elements: 2, max_elements, headroom: 1.0, ..HnswParams::default() });
+        manager
+    }
+
+    fn hit(note_id: &str, chunk: usize, distance: f32) -> ChunkHit {
+        ChunkHit { note_id: note_id.to_string(), chunk, distance }
+    }
+
+    #[test]
+    fn related_notes_rank_by_their_closest_chunk() {
+        // Hits from searching two chunks of "self"
+        let hits = vec![
+            hit("self", 0, 0.0),
+            hit("a", 2, 0.4),
+            hit("b", 0, 0.3),
+            hit("self", 1, 0.0),
+            hit("a", 0, 0.1),
+            hit("c", 1, 0.5),
+        ];
+        let related = closest_other_notes(hits, "self", 2);
+        assert_eq!(related, [("a".to_string(), 0.1), ("b".to_string(), 0.3)]);
+    }
+
+    #[test]
+    fn dedup_keeps_each_notes_best_hit_in_rank_order() {
+        // Sorted closest first, so the first hit of each note is its best
+        let hits = vec![hit("a", 1, 0.1), hit("b", 0, 0.2), hit("a", 0, 0.3), hit("c", 2, 0.4), hit("b", 3, 0.5)];
+        let kept = dedup_hits(hits);
+        let kept: Vec<(&str, usize)> = kept.iter().map(|hit| (hit.note_id.as_str(), hit.chunk)).collect();
+        assert_eq!(kept, [("a", 1), ("b", 0), ("c", 2)]);
+    }
+
+    #[test]
+    fn notes_matching_through_several_chunks_are_listed_once() {
+        let mut manager = small_manager(16);
+        manager.set_strategy(EmbeddingStrategy::Chunked { chunk_chars: 50, title_weight: 0.0 });
+        let long = note("long", &"garden tomatoes grow tall in summer. ".repeat(4));
+        assert!(manager.embed_note(&long).len() > 1);
+
+        // Two chunks of "long" match, the second one better than "short"
+        let hits = vec![hit("long", 2, 0.4), hit("short", 0, 0.2), hit("long", 0, 0.1), hit("other", 0, 0.6)];
+        assert_eq!(
+            best_per_note(hits, 2),
+            [("long".to_string(), 0.1), ("short".to_string(), 0.2)]
+        );
+    }
+
+    #[test]
+    fn compaction_clears_tombstones() {
+        // Room to spare, so the edit below leaves a tombstone rather than growing the graph
+        let mut manager = small_manager(16);
+        manager.set_params(HnswParams { min_elements: 16, ..manager.params() });
+        let notes = [note("a", "first"), note("b", "second"), note("c", "third")];
+        manager.rebuild_index(&notes).unwrap();
+        manager.remove_note_id("b").unwrap();
+        manager.update_note(&note("a", "first, edited")).unwrap();
+        let stats = manager.stats();
+        assert_eq!((stats.live, stats.tombstones), (2, 2));
+
+        manager.rebuild_index(&[note("a", "first, edited"), note("c", "third")]).unwrap();
+        let stats = manager.stats();
+        assert_eq!((stats.live, stats.tombstones), (2, 0));
+    }
+
+    #[test]
+    fn status_warns_near_the_cap_and_adds_stop_at_it() {
+        let mut manager = small_manager(10);
+        for i in 0..8 {
+            manager.add_note(&note(&i.to_string(), "words")).unwrap();
+        }
+        assert!(!manager.status().near_capacity);
+        manager.add_note(&note("8", "words")).unwrap();
+        assert!(manager.status().near_capacity);
+        manager.add_note(&note("9", "words")).unwrap();
+        assert_eq!(manager.stats().live, 10);
+        assert!(matches!(manager.add_note(&note("10", "words")), Err(EmbeddingError::CapacityExceeded(10))));
+        assert!(!manager.contains("10"));
+    }
+
+    #[test]
+    fn notes_on_disk_but_not_in_the_index_are_unindexed() {
+        let mut manager = small_manager(16);
+        manager.rebuild_index(&[note("a", "first"), note("b", "second")]).unwrap();
+        let on_disk = [note("a", "first"), note("b", "second"), note("c", "saved after indexing")];
+        assert_eq!(manager.unindexed(&on_disk), ["c"]);
+        manager.remove_note_id("a").unwrap();
+        assert_eq!(manager.unindexed(&on_disk), ["a", "c"]);
+        manager.update_note(&on_disk[2]).unwrap();
+        assert_eq!(manager.unindexed(&on_disk), ["a"]);
+    }
+
+    #[test]
+    fn refresh_reembeds_only_what_changed() {
+        let mut manager = small_manager(16);
+        manager.rebuild_index(&[note("same", "kept"), note("edited", "before"), note("deleted", "gone")]).unwrap();
+        let on_disk = [note("same", "kept"), note("edited", "after"), note("new", "added")];
+        let report = manager.refresh(&on_disk).unwrap();
+        assert_eq!((report.added, report.changed, report.removed, report.unchanged), (1, 1, 1, 1));
+        assert!(on_disk.iter().all(|note| !manager.is_stale(note)));
+        assert!(!manager.contains("deleted"));
+
+        let report = manager.refresh(&on_disk).unwrap();
+        assert_eq!((report.added, report.changed, report.removed, report.unchanged), (0, 0, 0, 3));
+    }
+
+    #[test]
+    fn content_hashes_survive_a_save_and_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let notes = [note("a", "first"), note("b", "second")];
+        let path = saved_index(dir.path(), &notes, EMBEDDING_BACKEND);
+        let mut manager = small_manager(8);
+        manager.load_from_disk(&path, &notes).unwrap();
+        assert!(notes.iter().all(|note| !manager.is_stale(note)));
+        assert!(manager.is_stale(&note("a", "first, edited")));
+    }
+
+    #[test]
+    fn update_grows_a_full_index() {
+        let mut manager = small_manager(4);
+        manager.add_note(&note("a", "first")).unwrap();
+        manager.add_note(&note("b", "second")).unwrap();
+        manager.update_note(&note("a", "first, edited")).unwrap();
+        assert!(!manager.is_stale(&note("a", "first, edited")));
+        assert!(manager.contains("b"));
+        assert_eq!(manager.stats().tombstones, 0);
+    }
+
+    // Save an index for `notes`, then rewrite its header as if `model` had built it
+    fn saved_index(dir: &Path, notes: &[Note], model: &str) -> std::path::PathBuf {
+        let mut manager = small_manager(8);
+        manager.rebuild_index(notes).unwrap();
+        let path = dir.join("index.json");
+        manager.save_to_disk(&path).unwrap();
+        let mut dump: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        dump["model"] = serde_json::json!(model);
+        std::fs::write(&path, dump.to_string()).unwrap();
+        path
+    }
+
+    #[test]
+    fn index_from_the_current_model_loads() {
+        let dir = tempfile::tempdir().unwrap();
+        let notes = [note("a", "first"), note("b", "second")];
+        let path = saved_index(dir.path(), &notes, EMBEDDING_BACKEND);
+        assert!(!small_manager(8).verify(&path).rebuild_required);
+        let mut manager = small_manager(8);
+        manager.load_from_disk(&path, &notes).unwrap();
+        assert!(manager.contains("a") && manager.contains("b"));
+    }
+
+    #[test]
+    fn index_from_another_model_is_rebuilt_instead_of_loaded() {
+        let dir = tempfile::tempdir().unwrap();
+        let notes = [note("a", "first"), note("b", "second")];
+        let path = saved_index(dir.path(), &notes, "some-older-model");
+
+        let verification = small_manager(8).verify(&path);
+        assert!(verification.rebuild_required);
+        assert_eq!(verification.stored_model.as_deref(), Some("some-older-model"));
+
+        let mut manager = small_manager(8);
+        assert!(manager.load_from_disk(&path, &notes).is_err());
+        manager.load_or_rebuild(&path, &notes).unwrap();
+        assert!(manager.is_current());
+        assert!(manager.contains("a") && manager.contains("b"));
+    }
+
+    #[test]
+    fn truncated_index_is_deleted_and_rebuilt() {
+        let dir = tempfile::tempdir().unwrap();
+        let notes = [note("a", "first"), note("b", "second")];
+        let path = saved_index(dir.path(), &notes, EMBEDDING_BACKEND);
+        let json = std::fs::read_to_string(&path).unwrap();
+        std::fs::write(&path, &json[..json.len() / 2]).unwrap();
+
+        let mut manager = small_manager(8);
+        assert!(matches!(manager.load_from_disk(&path, &notes), Err(EmbeddingError::Corrupt(_))));
+        manager.load_or_rebuild(&path, &notes).unwrap();
+        assert!(manager.contains("a") && manager.contains("b"));
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn unreadable_index_is_rebuilt_but_kept() {
+        let dir = tempfile::tempdir().unwrap();
+        let notes = [note("a", "first")];
+        // A directory where the file should be can't be read, but isn't corrupt either
+        let path = dir.path().join("index.json");
+        std::fs::create_dir(&path).unwrap();
+
+        let mut manager = small_manager(8);
+        assert!(matches!(manager.load_from_disk(&path, &notes), Err(EmbeddingError::Io(_))));
+        manager.load_or_rebuild(&path, &notes).unwrap();
+        assert!(manager.contains("a"));
+        assert!(path.exists());
+    }
+
+    #[test]
+    fn reconcile_is_only_needed_after_the_notes_diverge() {
+        let mut manager = small_manager(8);
+        let notes = vec![note("a", "first"), note("b", "second")];
+        manager.rebuild_index(&notes).unwrap();
+        assert!(!manager.needs_reconcile(&notes));
+        // The same number of notes, but one swapped for another
+        let swapped = vec![note("a", "first"), note("c", "third")];
+        assert!(manager.needs_reconcile(&swapped));
+        assert!(manager.needs_reconcile(&notes[..1]));
+    }
+
+    #[test]
+    fn reconcile_adds_and_removes_diverged_notes() {
+        let mut manager = small_manager(8);
+        manager.rebuild_index(&[note("a", "first"), note("b", "second")]).unwrap();
+        let on_disk = vec![note("a", "first"), note("c", "third"), note("d", "fourth")];
+        let report = manager.reconcile(&on_disk).unwrap();
+        assert_eq!((report.added, report.removed, report.unchanged), (2, 1, 1));
+        assert!(!manager.contains("b") && manager.contains("c") && manager.contains("d"));
+        assert!(!manager.needs_reconcile(&on_disk));
+    }
+
+    #[test]
+    fn update_that_does_not_fit_keeps_the_old_vectors() {
+        let mut manager = small_manager(2);
+        manager.add_note(&note("a", "first")).unwrap();
+        manager.add_note(&note("b", "second")).unwrap();
+        manager.set_strategy(EmbeddingStrategy::Chunked { chunk_chars: 10, title_weight: 0.5 });
+        let long = note("a", "several words that make up more than one chunk of text");
+        assert!(matches!(manager.update_note(&long), Err(EmbeddingError::CapacityExceeded(2))));
+        assert!(manager.contains("a"));
+        assert!(!manager.is_stale(&note("a", "first")));
+    }
+}
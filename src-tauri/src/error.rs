@@ -0,0 +1,88 @@
+use crate::completion_client::RateLimited;
+use crate::embeddings::EmbeddingError;
+use serde::{Serialize, Serializer};
+
+// Error type shared by note commands. Serialized to the frontend as its message.
+#[derive(Debug)]
+pub enum NoteError {
+    NotFound(String),
+    Io(String),
+    Index(String),
+    Lock(String),
+    InvalidInput(String),
+    Completion(String),
+    // The completion API kept throttling us; it's worth trying again after this long
+    RateLimited { retry_after_secs: u64 },
+    // Note content over the configured size limit, both in bytes
+    TooLarge { size: usize, limit: usize },
+    // The notes folder can't be created or written to
+    VaultNotWritable { path: String, reason: String },
+    // Structured model output that doesn't match the requested schema; `path` points at
+    // the offending value, e.g. `$.tags[2]`
+    SchemaViolation { path: String, message: String },
+}
+
+impl std::fmt::Display for NoteError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            NoteError::NotFound(id) => write!(f, "Note not found: {}", id),
+            NoteError::Io(msg) => write!(f, "IO error: {}", msg),
+            NoteError::Index(msg) => write!(f, "Index error: {}", msg),
+            NoteError::Lock(msg) => write!(f, "Failed to acquire lock: {}", msg),
+            NoteError::InvalidInput(msg) => write!(f, "Invalid input: {}", msg),
+            NoteError::Completion(msg) => write!(f, "Completion error: {}", msg),
+            NoteError::RateLimited { retry_after_secs } => {
+                write!(f, "Rate limited by the completion API. Try again in {} seconds.", retry_after_secs)
+            }
+            NoteError::TooLarge { size, limit } => write!(
+                f,
+                "Note is too large ({} bytes); the limit is {} bytes",
+                size, limit
+            ),
+            NoteError::VaultNotWritable { path, reason } => write!(
+                f,
+                "The notes folder {} is not writable ({}). Check its permissions and that the drive isn't read-only.",
+                path, reason
+            ),
+            NoteError::SchemaViolation { path, message } => {
+                write!(f, "Model output doesn't match the schema at {}: {}", path, message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for NoteError {}
+
+impl Serialize for NoteError {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl From<std::io::Error> for NoteError {
+    fn from(e: std::io::Error) -> Self {
+        NoteError::Io(e.to_string())
+    }
+}
+
+impl From<EmbeddingError> for NoteError {
+    fn from(e: EmbeddingError) -> Self {
+        NoteError::Index(e.to_string())
+    }
+}
+
+// Completion client errors, keeping rate limiting distinct so the UI can count down
+impl From<anyhow::Error> for NoteError {
+    fn from(e: anyhow::Error) -> Self {
+        match e.downcast_ref::<RateLimited>() {
+            Some(limited) => NoteError::RateLimited { retry_after_secs: limited.retry_after_secs },
+            None => NoteError::Completion(e.to_string()),
+        }
+    }
+}
+
+impl<T> From<std::sync::PoisonError<T>> for NoteError {
+    fn from(e: std::sync::PoisonError<T>) -> Self {
+        NoteError::Lock(e.to_string())
+    }
+}
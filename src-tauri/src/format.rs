@@ -0,0 +1,355 @@
+use crate::Note;
+use serde::{Deserialize, Serialize};
+use std::iter::Peekable;
+use std::path::{Path, PathBuf};
+use std::str::Chars;
+
+// On-disk serialization format for notes. The file extension always matches the format,
+// and reads detect the format from the extension so mixed vaults keep working.
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum NoteFormat {
+    #[default]
+    Json,
+    Toml,
+    Yaml,
+}
+
+// Every extension a note file can have. Notes are only written as .yaml, but .yml files
+// (e.g. from other tools) are read too.
+pub const NOTE_EXTENSIONS: [&str; 4] = ["json", "toml", "yaml", "yml"];
+
+impl NoteFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            NoteFormat::Json => "json",
+            NoteFormat::Toml => "toml",
+            NoteFormat::Yaml => "yaml",
+        }
+    }
+
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "json" => Some(NoteFormat::Json),
+            "toml" => Some(NoteFormat::Toml),
+            "yaml" | "yml" => Some(NoteFormat::Yaml),
+            _ => None,
+        }
+    }
+
+    pub fn serialize(self, note: &Note) -> Result<String, String> {
+        match self {
+            NoteFormat::Json => serde_json::to_string(note).map_err(|e| e.to_string()),
+            NoteFormat::Toml => toml::to_string(note).map_err(|e| e.to_string()),
+            NoteFormat::Yaml => serde_yaml::to_string(note).map_err(|e| e.to_string()),
+        }
+    }
+
+    pub fn parse(self, contents: &str) -> Result<Note, String> {
+        match self {
+            NoteFormat::Json => serde_json::from_str(contents).map_err(|e| e.to_string()),
+            NoteFormat::Toml => toml::from_str(contents).map_err(|e| e.to_string()),
+            NoteFormat::Yaml => serde_yaml::from_str(contents).map_err(|e| e.to_string()),
+        }
+    }
+}
+
+// The file holding note `id` in `dir`, whichever extension it has
+pub fn note_file_in(dir: &Path, id: &str) -> Option<PathBuf> {
+    NOTE_EXTENSIONS
+        .iter()
+        .map(|extension| dir.join(format!("{}.{}", id, extension)))
+        .find(|path| path.exists())
+}
+
+// Delete every copy of note `id` in `dir` except the one written as `format`, so a note
+// saved in a new format (or a .yml note saved as .yaml) isn't left behind in the old one
+pub fn remove_other_copies(dir: &Path, id: &str, format: NoteFormat) -> std::io::Result<()> {
+    for extension in NOTE_EXTENSIONS.iter().filter(|extension| **extension != format.extension()) {
+        let stale = dir.join(format!("{}.{}", id, extension));
+        if stale.exists() {
+            std::fs::remove_file(&stale)?;
+        }
+    }
+    Ok(())
+}
+
+// Best-effort recovery of a JSON note file that was cut off partway through, e.g. by an
+// interrupted sync. Walks the top-level object field by field and keeps every field it
+// reaches intact, plus a final unterminated string, so whatever content made it to disk
+// survives along with the metadata, state, reminder and so on. A field that doesn't fit
+// the note (e.g. a cut-off state name) is dropped rather than failing the whole note.
+// Returns None if not even a title or content can be found.
+pub fn recover_json(contents: &str, fallback_id: &str) -> Option<Note> {
+    let mut fields: Vec<(String, serde_json::Value)> = Vec::new();
+    let mut chars = contents.trim_start().strip_prefix('{')?.chars().peekable();
+    loop {
+        skip_whitespace(&mut chars);
+        if chars.peek() == Some(&',') {
+            chars.next();
+            skip_whitespace(&mut chars);
+        }
+        if chars.next() != Some('"') {
+            break;
+        }
+        let Some(key) = read_string(&mut chars).ok() else {
+            break;
+        };
+        skip_whitespace(&mut chars);
+        if chars.next() != Some(':') {
+            break;
+        }
+        skip_whitespace(&mut chars);
+        if chars.peek() == Some(&'"') {
+            chars.next();
+            match read_string(&mut chars) {
+                Ok(value) => fields.push((key, serde_json::Value::String(value))),
+                Err(partial) => {
+                    fields.push((key, serde_json::Value::String(partial)));
+                    break;
+                }
+            }
+        } else {
+            let Some(raw) = read_raw_value(&mut chars) else {
+                break;
+            };
+            if let Ok(value) = serde_json::from_str(&raw) {
+                fields.push((key, value));
+            }
+        }
+    }
+
+    let string_field = |name: &str| {
+        fields.iter().find(|(key, _)| key == name).and_then(|(_, value)| value.as_str().map(str::to_string))
+    };
+    let (title, content) = (string_field("title"), string_field("content"));
+    if title.is_none() && content.is_none() {
+        return None;
+    }
+    let mut object = serde_json::Map::new();
+    object.insert("id".to_string(), string_field("id").filter(|id| !id.is_empty()).unwrap_or_else(|| fallback_id.to_string()).into());
+    object.insert("title".to_string(), title.unwrap_or_default().into());
+    object.insert("content".to_string(), content.unwrap_or_default().into());
+    let mut note: Note = serde_json::from_value(serde_json::Value::Object(object.clone())).ok()?;
+    // Add the other fields one at a time, keeping each only if the note still parses with it
+    for (key, value) in fields {
+        if object.contains_key(&key) {
+            continue;
+        }
+        let mut candidate = object.clone();
+        candidate.insert(key, value);
+        if let Ok(parsed) = serde_json::from_value(serde_json::Value::Object(candidate.clone())) {
+            object = candidate;
+            note = parsed;
+        }
+    }
+    note.recovered = true;
+    Some(note)
+}
+
+fn skip_whitespace(chars: &mut Peekable<Chars>) {
+    while chars.peek().is_some_and(|c| c.is_whitespace()) {
+        chars.next();
+    }
+}
+
+// Read a JSON string whose opening quote was already consumed. A string cut off before its
+// closing quote comes back as Err with everything decoded up to that point.
+fn read_string(chars: &mut Peekable<Chars>) -> Result<String, String> {
+    let mut value = String::new();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => return Ok(value),
+            '\\' => {
+                let decoded = match chars.next() {
+                    Some('n') => '\n',
+                    Some('t') => '\t',
+                    Some('r') => '\r',
+                    Some('b') => '\u{8}',
+                    Some('f') => '\u{c}',
+                    Some('u') => {
+                        let Some(unit) = read_hex4(chars) else {
+                            return Err(value);
+                        };
+                        // A high surrogate needs the low half that follows it
+                        if (0xD800..0xDC00).contains(&unit) {
+                            let low = (chars.next() == Some('\\') && chars.next() == Some('u'))
+                                .then(|| read_hex4(chars))
+                                .flatten();
+                            let Some(low) = low else {
+                                return Err(value);
+                            };
+                            char::from_u32(0x10000 + ((unit - 0xD800) << 10) + (low.wrapping_sub(0xDC00) & 0x3FF))
+                                .unwrap_or(char::REPLACEMENT_CHARACTER)
+                        } else {
+                            char::from_u32(unit).unwrap_or(char::REPLACEMENT_CHARACTER)
+                        }
+                    }
+                    Some(other) => other,
+                    None => return Err(value),
+                };
+                value.push(decoded);
+            }
+            c => value.push(c),
+        }
+    }
+    Err(value)
+}
+
+fn read_hex4(chars: &mut Peekable<Chars>) -> Option<u32> {
+    let hex: String = chars.take(4).collect();
+    if hex.len() < 4 {
+        return None;
+    }
+    u32::from_str_radix(&hex, 16).ok()
+}
+
+// Read a non-string value (number, literal, object or array) as JSON text. Returns None if
+// the input ends before the value does.
+fn read_raw_value(chars: &mut Peekable<Chars>) -> Option<String> {
+    let mut raw = String::new();
+    let mut depth = 0usize;
+    while let Some(&c) = chars.peek() {
+        match c {
+            '"' => {
+                chars.next();
+                let value = read_string(chars).ok()?;
+                raw.push_str(&serde_json::Value::String(value).to_string());
+                continue;
+            }
+            '{' | '[' => depth += 1,
+            '}' | ']' if depth > 0 => depth -= 1,
+            ',' | '}' if depth == 0 => return Some(raw),
+            _ => {}
+        }
+        raw.push(c);
+        chars.next();
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::NoteState;
+    use std::collections::HashMap;
+
+    fn sample() -> Note {
+        Note {
+            id: "note-1".to_string(),
+            title: "Trip: \"Lisbon\" & more".to_string(),
+            content: "# Plan\n\n- pack\n- go: early\n\tindented 'quotes'".to_string(),
+            created_at: "2024-03-01T10:00:00+00:00".to_string(),
+            updated_at: "2024-03-02T11:30:00+00:00".to_string(),
+            language: Some("eng".to_string()),
+            metadata: HashMap::from([("source".to_string(), "import".to_string())]),
+            reminder_at: Some("2024-04-01T09:00:00+00:00".to_string()),
+            reminder_interval_secs: Some(86_400),
+            cursor: Some(7),
+            completion_temperature: Some(0.25),
+            state: NoteState::Pinned,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn every_format_round_trips() {
+        let note = sample();
+        for format in [NoteFormat::Json, NoteFormat::Toml, NoteFormat::Yaml] {
+            let parsed = format.parse(&format.serialize(&note).unwrap()).unwrap();
+            assert_eq!(
+                serde_json::to_value(&parsed).unwrap(),
+                serde_json::to_value(&note).unwrap(),
+                "{:?} round trip",
+                format
+            );
+        }
+    }
+
+    #[test]
+    fn formats_are_detected_from_the_extension() {
+        assert_eq!(NoteFormat::from_path(Path::new("a.json")), Some(NoteFormat::Json));
+        assert_eq!(NoteFormat::from_path(Path::new("a.toml")), Some(NoteFormat::Toml));
+        assert_eq!(NoteFormat::from_path(Path::new("a.yaml")), Some(NoteFormat::Yaml));
+        assert_eq!(NoteFormat::from_path(Path::new("a.yml")), Some(NoteFormat::Yaml));
+        assert_eq!(NoteFormat::from_path(Path::new("a.md")), None);
+        for extension in NOTE_EXTENSIONS {
+            assert!(NoteFormat::from_path(Path::new(&format!("a.{}", extension))).is_some());
+        }
+    }
+
+    #[test]
+    fn yml_notes_are_found_and_migrated() {
+        let dir = tempfile::tempdir().unwrap();
+        let yml = dir.path().join("note-1.yml");
+        std::fs::write(&yml, NoteFormat::Yaml.serialize(&sample()).unwrap()).unwrap();
+        assert_eq!(note_file_in(dir.path(), "note-1"), Some(yml.clone()));
+
+        // Saving as .yaml leaves no .yml copy behind to shadow it
+        let yaml = dir.path().join("note-1.yaml");
+        std::fs::write(&yaml, NoteFormat::Yaml.serialize(&sample()).unwrap()).unwrap();
+        remove_other_copies(dir.path(), "note-1", NoteFormat::Yaml).unwrap();
+        assert!(!yml.exists());
+        assert_eq!(note_file_in(dir.path(), "note-1"), Some(yaml.clone()));
+
+        // ...and switching formats removes the .yaml one
+        let json = dir.path().join("note-1.json");
+        std::fs::write(&json, NoteFormat::Json.serialize(&sample()).unwrap()).unwrap();
+        remove_other_copies(dir.path(), "note-1", NoteFormat::Json).unwrap();
+        assert!(!yaml.exists());
+        assert_eq!(note_file_in(dir.path(), "note-1"), Some(json));
+    }
+
+    #[test]
+    fn missing_notes_are_not_found() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(note_file_in(dir.path(), "nope"), None);
+    }
+
+    #[test]
+    fn recovery_keeps_every_field_before_the_cut() {
+        let mut original = sample();
+        original.state = NoteState::Pinned;
+        original.reminder_interval_secs = Some(3600);
+        let json = serde_json::to_string(&original).unwrap();
+        // Cut inside the last field, whatever it is
+        let truncated = &json[..json.len() - 3];
+        let recovered = recover_json(truncated, "fallback").unwrap();
+        assert!(recovered.recovered);
+        assert_eq!(recovered.id, original.id);
+        assert_eq!(recovered.title, original.title);
+        assert_eq!(recovered.content, original.content);
+        assert_eq!(recovered.language, original.language);
+        assert_eq!(recovered.metadata, original.metadata);
+    }
+
+    #[test]
+    fn recovery_keeps_metadata_state_and_partial_content() {
+        let truncated = r#"{"id":"n","title":"T","metadata":{"source":"sync"},"state":"archived","reminder_interval_secs":60,"content":"half a sen"#;
+        let recovered = recover_json(truncated, "fallback").unwrap();
+        assert_eq!(recovered.metadata.get("source").map(String::as_str), Some("sync"));
+        assert_eq!(recovered.state, NoteState::Archived);
+        assert_eq!(recovered.reminder_interval_secs, Some(60));
+        assert_eq!(recovered.content, "half a sen");
+    }
+
+    #[test]
+    fn recovery_drops_fields_that_do_not_fit() {
+        // A state name cut short, and a value of the wrong type
+        let truncated = r#"{"title":"T","cursor":"nine","state":"pin"#;
+        let recovered = recover_json(truncated, "fallback").unwrap();
+        assert_eq!(recovered.id, "fallback");
+        assert_eq!(recovered.title, "T");
+        assert_eq!(recovered.state, NoteState::default());
+        assert_eq!(recovered.cursor, None);
+        // A nested value cut off partway is dropped too
+        let truncated = r#"{"title":"T","metadata":{"source":"sy"#;
+        assert!(recover_json(truncated, "fallback").unwrap().metadata.is_empty());
+    }
+
+    #[test]
+    fn recovery_needs_a_title_or_content() {
+        assert!(recover_json(r#"{"id":"n","created_at":"2024"#, "fallback").is_none());
+        assert!(recover_json("not json", "fallback").is_none());
+    }
+}
@@ -0,0 +1,120 @@
+use crate::Note;
+use serde::Serialize;
+use similar::{ChangeTag, TextDiff};
+use std::fs::create_dir_all;
+use std::path::PathBuf;
+
+// Versions kept per note; the oldest are dropped past this
+const MAX_VERSIONS: usize = 50;
+
+// Earlier versions of a note, one JSON file each, named so they sort oldest first
+fn history_dir(id: &str) -> PathBuf {
+    crate::config::load_config().vault_data_dir().join("history").join(id)
+}
+
+// Keep `note` as it is now as a version, before it's overwritten
+pub fn record(note: &Note) -> Result<(), String> {
+    let dir = history_dir(&note.id);
+    create_dir_all(&dir).map_err(|e| e.to_string())?;
+    let contents = serde_json::to_string(note).map_err(|e| e.to_string())?;
+    let name = format!("{:020}.json", chrono::Utc::now().timestamp_micros());
+    std::fs::write(dir.join(name), contents).map_err(|e| e.to_string())?;
+    prune(&dir);
+    Ok(())
+}
+
+fn version_paths(dir: &PathBuf) -> Vec<PathBuf> {
+    let mut paths: Vec<PathBuf> = std::fs::read_dir(dir)
+        .map(|entries| entries.flatten().map(|entry| entry.path()).collect())
+        .unwrap_or_default();
+    paths.retain(|path| path.extension().is_some_and(|ext| ext == "json"));
+    paths.sort();
+    paths
+}
+
+fn prune(dir: &PathBuf) {
+    let paths = version_paths(dir);
+    for path in paths.iter().take(paths.len().saturating_sub(MAX_VERSIONS)) {
+        let _ = std::fs::remove_file(path);
+    }
+}
+
+// Saved versions of a note, oldest first. Unreadable versions are skipped.
+pub fn versions(id: &str) -> Vec<Note> {
+    version_paths(&history_dir(id))
+        .iter()
+        .filter_map(|path| std::fs::read_to_string(path).ok())
+        .filter_map(|contents| serde_json::from_str(&contents).ok())
+        .collect()
+}
+
+// Drop a deleted note's history
+pub fn forget(id: &str) -> Result<(), String> {
+    let dir = history_dir(id);
+    if !dir.exists() {
+        return Ok(());
+    }
+    std::fs::remove_dir_all(dir).map_err(|e| e.to_string())
+}
+
+// Lines added and removed going from `old` to `new`
+pub fn line_changes(old: &str, new: &str) -> (usize, usize) {
+    let diff = TextDiff::from_lines(old, new);
+    let mut added = 0;
+    let mut removed = 0;
+    for change in diff.iter_all_changes() {
+        match change.tag() {
+            ChangeTag::Insert => added += 1,
+            ChangeTag::Delete => removed += 1,
+            ChangeTag::Equal => {}
+        }
+    }
+    (added, removed)
+}
+
+// One step in a note's history, for a timeline
+#[derive(Serialize)]
+pub struct ChangelogEntry {
+    // When this version was saved
+    pub updated_at: String,
+    pub title: String,
+    pub lines_added: usize,
+    pub lines_removed: usize,
+    // Short description, e.g. "+3 −1 lines, renamed from \"Draft\""
+    pub summary: String,
+    // The note as it is now rather than a stored version
+    pub current: bool,
+}
+
+// Describe how a note changed from one version to the next, oldest first. The first version
+// is described as created, since there's nothing earlier to compare it with.
+pub fn changelog(versions: &[Note], current: &Note) -> Vec<ChangelogEntry> {
+    let mut entries = Vec::new();
+    let mut previous: Option<&Note> = None;
+    for (i, version) in versions.iter().chain(std::iter::once(current)).enumerate() {
+        let (lines_added, lines_removed) = line_changes(previous.map_or("", |p| p.content.as_str()), &version.content);
+        let summary = match previous {
+            None => format!("Created with {} lines", lines_added),
+            Some(previous) => {
+                let mut summary = match (lines_added, lines_removed) {
+                    (0, 0) => "No content changes".to_string(),
+                    (added, removed) => format!("+{} −{} lines", added, removed),
+                };
+                if previous.title != version.title {
+                    summary.push_str(&format!(", renamed from \"{}\"", previous.title));
+                }
+                summary
+            }
+        };
+        entries.push(ChangelogEntry {
+            updated_at: version.updated_at.clone(),
+            title: version.title.clone(),
+            lines_added,
+            lines_removed,
+            summary,
+            current: i == versions.len(),
+        });
+        previous = Some(version);
+    }
+    entries
+}
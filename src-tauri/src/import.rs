@@ -0,0 +1,430 @@
+use crate::commands::insert_note;
+use crate::NoteError;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+
+// Summary of an import_path run. Per-file failures are collected rather than aborting.
+#[derive(Serialize, Default)]
+pub struct ImportReport {
+    pub imported: usize,
+    pub by_type: HashMap<String, usize>,
+    pub note_ids: Vec<String>,
+    pub errors: Vec<ImportFileError>,
+    pub skipped: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct ImportFileError {
+    pub path: String,
+    pub message: String,
+}
+
+// Shape accepted by the JSON and NDJSON importers; extra fields such as `id` are ignored
+#[derive(Deserialize)]
+struct ImportedNote {
+    title: String,
+    #[serde(default)]
+    content: String,
+}
+
+// Import a single file or every file under a directory, dispatching on extension
+pub fn import_path(path: &Path) -> Result<ImportReport, NoteError> {
+    if !path.exists() {
+        return Err(NoteError::InvalidInput(format!("Path does not exist: {}", path.display())));
+    }
+    let mut report = ImportReport::default();
+    if path.is_dir() {
+        import_dir(path, &mut report);
+    } else {
+        import_file(path, &mut report);
+    }
+    Ok(report)
+}
+
+// Split one markdown file into a note per section at `heading_level` (1-6), returning the
+// ids of the created notes in file order
+pub fn import_markdown_split(path: &Path, heading_level: u8) -> Result<Vec<String>, NoteError> {
+    if !(1..=6).contains(&heading_level) {
+        return Err(NoteError::InvalidInput("heading_level must be between 1 and 6".to_string()));
+    }
+    let text = fs::read_to_string(path)?;
+    split_markdown_sections(&file_title(path), &text, heading_level as usize)
+        .into_iter()
+        .map(|(title, content)| insert_note(title, content).map(|note| note.id))
+        .collect()
+}
+
+fn import_dir(dir: &Path, report: &mut ImportReport) {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            report.errors.push(ImportFileError { path: dir.display().to_string(), message: e.to_string() });
+            return;
+        }
+    };
+    let mut paths: Vec<_> = entries.flatten().map(|e| e.path()).collect();
+    paths.sort();
+    for path in paths {
+        if path.is_dir() {
+            import_dir(&path, report);
+        } else {
+            import_file(&path, report);
+        }
+    }
+}
+
+fn import_file(path: &Path, report: &mut ImportReport) {
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_lowercase())
+        .unwrap_or_default();
+
+    let parsed = match extension.as_str() {
+        "md" | "markdown" => fs::read_to_string(path).map_err(|e| e.to_string()).map(|text| vec![parse_markdown(path, &text)]),
+        "txt" => fs::read_to_string(path).map_err(|e| e.to_string()).map(|text| vec![(file_title(path), text)]),
+        "json" => fs::read_to_string(path).map_err(|e| e.to_string()).and_then(|text| parse_json(&text)),
+        "ndjson" => fs::read_to_string(path).map_err(|e| e.to_string()).and_then(|text| parse_ndjson(&text)),
+        "enex" => fs::read_to_string(path).map_err(|e| e.to_string()).map(|text| parse_enex(&text)),
+        _ => {
+            report.skipped.push(format!("{} (unsupported extension)", path.display()));
+            return;
+        }
+    };
+
+    let notes = match parsed {
+        Ok(notes) => notes,
+        Err(message) => {
+            report.errors.push(ImportFileError { path: path.display().to_string(), message });
+            return;
+        }
+    };
+
+    for (title, content) in notes {
+        match insert_note(title, content) {
+            Ok(note) => {
+                report.imported += 1;
+                *report.by_type.entry(extension.clone()).or_insert(0) += 1;
+                report.note_ids.push(note.id);
+            }
+            Err(e) => report.errors.push(ImportFileError { path: path.display().to_string(), message: e.to_string() }),
+        }
+    }
+}
+
+fn file_title(path: &Path) -> String {
+    path.file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or("Imported Note")
+        .to_string()
+}
+
+// Use a leading `# Heading` as the title, otherwise the file name
+fn parse_markdown(path: &Path, text: &str) -> (String, String) {
+    let trimmed = text.trim_start();
+    if let Some(heading) = trimmed.strip_prefix("# ") {
+        let (title, body) = heading.split_once('\n').unwrap_or((heading, ""));
+        return (title.trim().to_string(), body.trim_start_matches('\n').to_string());
+    }
+    (file_title(path), text.to_string())
+}
+
+// ATX heading level and text of a line, e.g. `## Plans` -> (2, "Plans")
+fn heading(line: &str) -> Option<(usize, &str)> {
+    let trimmed = line.trim_start();
+    // More than three spaces of indent makes it a code block instead
+    if line.len() - trimmed.len() > 3 {
+        return None;
+    }
+    let level = trimmed.chars().take_while(|&c| c == '#').count();
+    let rest = &trimmed[level..];
+    if !(1..=6).contains(&level) || !(rest.is_empty() || rest.starts_with([' ', '\t'])) {
+        return None;
+    }
+    Some((level, rest.trim().trim_end_matches('#').trim_end()))
+}
+
+// Split markdown into (title, body) sections. Headings at `level` or above start a new
+// section; deeper headings stay in their parent's body. Text before the first heading
+// becomes an intro section named after the file.
+fn split_markdown_sections(file_title: &str, text: &str, level: usize) -> Vec<(String, String)> {
+    let mut sections = Vec::new();
+    let mut title: Option<String> = None;
+    let mut body: Vec<&str> = Vec::new();
+    let mut fence: Option<&str> = None;
+
+    for line in text.lines() {
+        let trimmed = line.trim_start();
+        // Lines inside fenced code blocks are never headings
+        if let Some(marker) = fence {
+            if trimmed.starts_with(marker) {
+                fence = None;
+            }
+            body.push(line);
+            continue;
+        }
+        if let Some(marker) = ["```", "~~~"].into_iter().find(|m| trimmed.starts_with(m)) {
+            fence = Some(marker);
+            body.push(line);
+            continue;
+        }
+
+        match heading(line) {
+            Some((heading_level, text)) if heading_level <= level => {
+                push_section(&mut sections, file_title, title.take(), &body);
+                title = Some(if text.is_empty() { "Untitled".to_string() } else { text.to_string() });
+                body.clear();
+            }
+            _ => body.push(line),
+        }
+    }
+    push_section(&mut sections, file_title, title, &body);
+    sections
+}
+
+// Add a finished section. A blank intro is dropped, but an empty section under a heading is kept.
+fn push_section(sections: &mut Vec<(String, String)>, file_title: &str, title: Option<String>, body: &[&str]) {
+    let content = body.join("\n").trim().to_string();
+    match title {
+        Some(title) => sections.push((title, content)),
+        None if !content.is_empty() => sections.push((format!("{} (intro)", file_title), content)),
+        None => {}
+    }
+}
+
+// Accept either a single note object or an array of them
+fn parse_json(text: &str) -> Result<Vec<(String, String)>, String> {
+    let value: serde_json::Value = serde_json::from_str(text).map_err(|e| e.to_string())?;
+    let items = match value {
+        serde_json::Value::Array(items) => items,
+        other => vec![other],
+    };
+    items
+        .into_iter()
+        .map(|item| {
+            serde_json::from_value::<ImportedNote>(item)
+                .map(|n| (n.title, n.content))
+                .map_err(|e| e.to_string())
+        })
+        .collect()
+}
+
+fn parse_ndjson(text: &str) -> Result<Vec<(String, String)>, String> {
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| {
+            serde_json::from_str::<ImportedNote>(line)
+                .map(|n| (n.title, n.content))
+                .map_err(|e| format!("line {}: {}", i + 1, e))
+        })
+        .collect()
+}
+
+// Evernote export: a sequence of <note> elements with a <title> and HTML <content>
+fn parse_enex(text: &str) -> Vec<(String, String)> {
+    let allowed_tags = crate::config::load_config().import_allowed_tags;
+    let allowed_tags: Vec<&str> = match &allowed_tags {
+        Some(tags) => tags.iter().map(String::as_str).collect(),
+        None => DEFAULT_ALLOWED_TAGS.to_vec(),
+    };
+    let mut notes = Vec::new();
+    let mut rest = text;
+    while let Some(start) = rest.find("<note>") {
+        let Some(len) = rest[start..].find("</note>") else {
+            break;
+        };
+        let note = &rest[start..start + len];
+        let title = element_text(note, "title").unwrap_or_else(|| "Imported Note".to_string());
+        let content = element_text(note, "content")
+            .map(|html| html_to_markdown(&sanitize_html(&html, &allowed_tags)))
+            .unwrap_or_default();
+        notes.push((decode_entities(&title), content));
+        rest = &rest[start + len + "</note>".len()..];
+    }
+    notes
+}
+
+fn element_text(xml: &str, tag: &str) -> Option<String> {
+    let open = format!("<{}>", tag);
+    let close = format!("</{}>", tag);
+    let start = xml.find(&open)? + open.len();
+    let end = start + xml[start..].find(&close)?;
+    let inner = xml[start..end].trim();
+    let inner = inner
+        .strip_prefix("<![CDATA[")
+        .and_then(|s| s.strip_suffix("]]>"))
+        .unwrap_or(inner);
+    Some(inner.to_string())
+}
+
+// Tags kept by sanitize_html unless the user configures their own set
+pub const DEFAULT_ALLOWED_TAGS: [&str; 22] = [
+    "p", "div", "br", "h1", "h2", "h3", "h4", "h5", "h6", "ul", "ol", "li",
+    "strong", "b", "em", "i", "code", "pre", "blockquote", "a", "tr", "td",
+];
+
+// Tags whose contents ammonia always removes; they can never be allowed as well
+pub const FORBIDDEN_TAGS: [&str; 2] = ["script", "style"];
+
+// Strip everything but `allowed_tags` from imported HTML. Scripts and styles are dropped
+// with their contents, all attributes except link targets are removed (so no event handlers
+// or inline styles), and images and other embedded resources never survive, which also
+// removes tracking pixels. Links may only use http(s) or mailto.
+fn sanitize_html(html: &str, allowed_tags: &[&str]) -> String {
+    ammonia::Builder::empty()
+        .tags(allowed_tags.iter().copied().filter(|tag| !FORBIDDEN_TAGS.contains(tag)).collect())
+        .generic_attributes(HashSet::new())
+        .tag_attributes(HashMap::from([("a", HashSet::from(["href"]))]))
+        .url_schemes(HashSet::from(["http", "https", "mailto"]))
+        .url_relative(ammonia::UrlRelative::Deny)
+        .link_rel(None)
+        .clean(html)
+        .to_string()
+}
+
+// Convert sanitized HTML to markdown, keeping headings, lists, emphasis, code and links
+fn html_to_markdown(html: &str) -> String {
+    let mut text = String::new();
+    let mut links: Vec<Option<String>> = Vec::new();
+    let mut in_pre = false;
+    let mut rest = html;
+    let newline = |text: &mut String| {
+        if !text.is_empty() && !text.ends_with('\n') {
+            text.push('\n');
+        }
+    };
+    while let Some(start) = rest.find('<') {
+        text.push_str(&rest[..start]);
+        let Some(len) = rest[start..].find('>') else {
+            break;
+        };
+        let raw = &rest[start + 1..start + len];
+        let closing = raw.starts_with('/');
+        let tag = raw.trim_start_matches('/').to_lowercase();
+        let name = tag.split(|c: char| c.is_whitespace() || c == '/').next().unwrap_or("");
+        match (name, closing) {
+            ("h1" | "h2" | "h3" | "h4" | "h5" | "h6", false) => {
+                newline(&mut text);
+                text.push_str(&"#".repeat(name[1..].parse().unwrap_or(1)));
+                text.push(' ');
+            }
+            ("li", false) => {
+                newline(&mut text);
+                text.push_str("- ");
+            }
+            ("br", _) => text.push('\n'),
+            ("strong" | "b", _) => text.push_str("**"),
+            ("em" | "i", _) => text.push('*'),
+            ("code", _) if !in_pre => text.push('`'),
+            ("pre", false) => {
+                newline(&mut text);
+                text.push_str("```\n");
+                in_pre = true;
+            }
+            ("pre", true) => {
+                newline(&mut text);
+                text.push_str("```\n");
+                in_pre = false;
+            }
+            ("a", false) => {
+                // ammonia always writes attributes as name="value"
+                let href = raw
+                    .split_once("href=\"")
+                    .and_then(|(_, rest)| rest.split_once('"'))
+                    .map(|(href, _)| href.to_string());
+                if href.is_some() {
+                    text.push('[');
+                }
+                links.push(href);
+            }
+            ("a", true) => {
+                if let Some(Some(href)) = links.pop() {
+                    text.push_str(&format!("]({})", href));
+                }
+            }
+            ("td", true) => text.push(' '),
+            ("div" | "p" | "tr" | "blockquote" | "ul" | "ol" | "h1" | "h2" | "h3" | "h4" | "h5" | "h6" | "li", _) => {
+                newline(&mut text)
+            }
+            _ => {}
+        }
+        rest = &rest[start + len + 1..];
+    }
+    text.push_str(rest);
+
+    // Collapse the blank lines left behind by nested blocks
+    let mut markdown = String::new();
+    for line in decode_entities(text.trim()).lines() {
+        if line.trim().is_empty() && markdown.ends_with("\n\n") {
+            continue;
+        }
+        markdown.push_str(line.trim_end());
+        markdown.push('\n');
+    }
+    markdown.trim_end().to_string()
+}
+
+fn decode_entities(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&nbsp;", " ")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn clean(html: &str) -> String {
+        sanitize_html(html, &DEFAULT_ALLOWED_TAGS)
+    }
+
+    #[test]
+    fn drops_scripts_and_styles_with_their_contents() {
+        let html = "<p>keep</p><script>alert('x')</script><style>p { color: red }</style>";
+        assert_eq!(clean(html), "<p>keep</p>");
+    }
+
+    #[test]
+    fn strips_event_handlers() {
+        let html = r#"<p onclick="steal()" onmouseover="steal()">hi</p><div onload="x()">there</div>"#;
+        assert_eq!(clean(html), "<p>hi</p><div>there</div>");
+    }
+
+    #[test]
+    fn drops_javascript_urls() {
+        let cleaned = clean(r#"<a href="javascript:alert(1)">link</a><a href="JaVaScRiPt:alert(1)">other</a>"#);
+        assert!(!cleaned.to_lowercase().contains("javascript"));
+        assert!(cleaned.contains("link") && cleaned.contains("other"));
+    }
+
+    #[test]
+    fn keeps_safe_links() {
+        let cleaned = clean(r#"<a href="https://example.com" target="_blank">site</a>"#);
+        assert_eq!(cleaned, r#"<a href="https://example.com">site</a>"#);
+    }
+
+    #[test]
+    fn drops_tracking_pixels() {
+        let html = r#"<p>text</p><img src="https://tracker.example/p.gif" width="1" height="1"><iframe src="https://x"></iframe>"#;
+        assert_eq!(clean(html), "<p>text</p>");
+    }
+
+    #[test]
+    fn forbidden_tags_never_survive_a_custom_allow_list() {
+        // Allowing script as a tag would otherwise make ammonia panic
+        let cleaned = sanitize_html("<p>a</p><script>b()</script>", &["p", "script", "style"]);
+        assert_eq!(cleaned, "<p>a</p>");
+    }
+
+    #[test]
+    fn converts_sanitized_html_to_markdown() {
+        let html = clean(r#"<h2>Title</h2><p>Some <strong>bold</strong> <a href="https://a.b">link</a></p><script>x</script>"#);
+        assert_eq!(html_to_markdown(&html), "## Title\nSome **bold** [link](https://a.b)");
+    }
+}
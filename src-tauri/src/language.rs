@@ -0,0 +1,118 @@
+use rust_stemmers::{Algorithm, Stemmer};
+use std::collections::HashSet;
+use unicode_normalization::char::is_combining_mark;
+use unicode_normalization::UnicodeNormalization;
+
+// Detect the language of a piece of text as an ISO 639-3 code (e.g. "eng", "fra").
+// Returns None when the detector isn't confident.
+pub fn detect(text: &str) -> Option<String> {
+    let info = whatlang::detect(text)?;
+    if !info.is_reliable() {
+        return None;
+    }
+    Some(info.lang().code().to_string())
+}
+
+// English name of a language code from detect, e.g. "fra" -> "French"
+pub fn name(code: &str) -> Option<&'static str> {
+    whatlang::Lang::from_code(code).map(|lang| lang.eng_name())
+}
+
+// ISO 639-1 codes of common languages, mapped to the ISO 639-3 codes detect returns
+const TWO_LETTER_CODES: [(&str, &str); 25] = [
+    ("ar", "ara"), ("cs", "ces"), ("da", "dan"), ("de", "deu"), ("el", "ell"),
+    ("en", "eng"), ("es", "spa"), ("fi", "fin"), ("fr", "fra"), ("he", "heb"),
+    ("hi", "hin"), ("hu", "hun"), ("it", "ita"), ("ja", "jpn"), ("ko", "kor"),
+    ("nb", "nob"), ("nl", "nld"), ("no", "nob"), ("pl", "pol"), ("pt", "por"),
+    ("ro", "ron"), ("ru", "rus"), ("sv", "swe"), ("tr", "tur"), ("zh", "cmn"),
+];
+
+// Turn a user-supplied language into a code as stored by detect. Accepts ISO 639-3 codes,
+// common ISO 639-1 codes and English names, in any case, e.g. "FR", "fra" or "French".
+pub fn normalize_code(language: &str) -> Option<String> {
+    let language = language.trim().to_lowercase();
+    if let Some(lang) = whatlang::Lang::from_code(language.as_str()) {
+        return Some(lang.code().to_string());
+    }
+    if let Some((_, code)) = TWO_LETTER_CODES.iter().find(|(short, _)| *short == language) {
+        return Some(code.to_string());
+    }
+    whatlang::Lang::all()
+        .iter()
+        .find(|lang| lang.eng_name().to_lowercase() == language)
+        .map(|lang| lang.code().to_string())
+}
+
+// Snowball stemmer for a detected language, if one exists
+pub(crate) fn stemmer_for(language: &str) -> Option<Stemmer> {
+    let algorithm = match language {
+        "ara" => Algorithm::Arabic,
+        "dan" => Algorithm::Danish,
+        "nld" => Algorithm::Dutch,
+        "eng" => Algorithm::English,
+        "fra" => Algorithm::French,
+        "deu" => Algorithm::German,
+        "ell" => Algorithm::Greek,
+        "hun" => Algorithm::Hungarian,
+        "ita" => Algorithm::Italian,
+        "nob" => Algorithm::Norwegian,
+        "por" => Algorithm::Portuguese,
+        "ron" => Algorithm::Romanian,
+        "rus" => Algorithm::Russian,
+        "spa" => Algorithm::Spanish,
+        "swe" => Algorithm::Swedish,
+        "tam" => Algorithm::Tamil,
+        "tur" => Algorithm::Turkish,
+        _ => return None,
+    };
+    Some(Stemmer::create(algorithm))
+}
+
+// Lowercase and drop accents from Latin letters ("Café" -> "cafe"). Marks on other
+// scripts are kept since they're often meaningful (e.g. Cyrillic "й").
+pub fn fold(text: &str) -> String {
+    let mut folded = String::with_capacity(text.len());
+    let mut base_is_latin = false;
+    for c in text.nfd() {
+        if is_combining_mark(c) {
+            if !base_is_latin {
+                folded.push(c);
+            }
+            continue;
+        }
+        base_is_latin = c.is_ascii_alphabetic();
+        folded.extend(c.to_lowercase());
+    }
+    folded.nfc().collect()
+}
+
+fn stems(text: &str, stemmer: &Stemmer) -> HashSet<String> {
+    crate::text::tokenize(text)
+        .iter()
+        .map(|word| stemmer.stem(word).into_owned())
+        .collect()
+}
+
+// Whether `text` matches `query`, using the note's language (when known) to also match
+// across accents and word forms ("running" finds "runs" in English notes)
+pub fn matches_query(text: &str, query: &str, language: Option<&str>) -> bool {
+    if text.to_lowercase().contains(&query.to_lowercase()) {
+        return true;
+    }
+    let Some(language) = language else {
+        return false;
+    };
+
+    let text = fold(text);
+    let query = fold(query);
+    if text.contains(&query) {
+        return true;
+    }
+    match stemmer_for(language) {
+        Some(stemmer) => {
+            let query_stems = stems(&query, &stemmer);
+            !query_stems.is_empty() && query_stems.is_subset(&stems(&text, &stemmer))
+        }
+        None => false,
+    }
+}
@@ -1,27 +1,447 @@
 use serde::{Deserialize, Serialize};
 use std::fs::{File, create_dir_all, read_dir, remove_file};
 use std::io::{Read, Write};
-use std::path::PathBuf;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use tauri::Emitter;
 use uuid::Uuid;
 
 // LLM client module for local completions
 mod completion_client;
 
-// Embeddings module removed
+// Markdown rendering and wiki-link parsing
+mod markdown;
+
+// Vector index for semantic features
+mod embeddings;
+use embeddings::{EmbeddingManager, EmbeddingStrategy, HnswParams, IndexStats, IndexStatus, ReconcileReport, RefreshReport, EMBEDDING_MANAGER};
+
+// Persisted app settings
+mod config;
+
+// In-memory cache of parsed notes
+mod cache;
+use cache::NOTE_CACHE;
+
+// Context extraction for completion prompts
+mod context;
+
+// Importers for markdown, text, JSON and Evernote files
+mod import;
+
+// On-disk note formats
+mod format;
+use format::NoteFormat;
+
+// Language detection and language-aware search matching
+mod language;
+
+// Shared error type for commands
+mod error;
+
+// Per-command timing instrumentation
+mod timing;
+
+// Note reminders and the background check that fires them
+mod reminders;
+
+// PDF export
+mod pdf;
+
+// Persisted manual note order
+mod order;
+
+// Tokenizer and inverted full-text index
+mod text;
+
+// Earlier versions of notes, kept on every save
+mod history;
+pub use error::NoteError;
 
 // Define our Note structure
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, Default)]
 pub struct Note {
     pub id: String,
     pub title: String,
     pub content: String,
+    // RFC 3339 timestamps; older note files without them fall back to the file's mtime
+    #[serde(default)]
+    pub created_at: String,
+    #[serde(default)]
+    pub updated_at: String,
+    // Detected ISO 639-3 language code, cached until the content changes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+    // Free-form key/value fields for users and plugins. Not searched.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub metadata: HashMap<String, String>,
+    // RFC 3339 time to remind about this note, and how often to repeat once it fires
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reminder_at: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reminder_interval_secs: Option<u64>,
+    // Byte offset of the editor cursor when the note was last left, always on a char boundary
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cursor: Option<usize>,
+    // Autocomplete temperature for this note, overriding the feature default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub completion_temperature: Option<f32>,
+    // Rebuilt from a damaged file by format::recover_json and not yet saved by the user
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub recovered: bool,
+    // Whether the note is pinned, archived or in the trash
+    #[serde(default, skip_serializing_if = "NoteState::is_active")]
+    pub state: NoteState,
+    // Flags written before NoteState existed. Only ever read, then folded into `state`.
+    #[serde(default, skip_serializing)]
+    pinned: bool,
+    #[serde(default, skip_serializing)]
+    archived: bool,
+    #[serde(default, skip_serializing)]
+    trashed: bool,
+}
+
+impl Note {
+    // Fold any legacy pinned/archived/trashed flags into `state`, the strongest one winning.
+    // Returns whether the note had any, i.e. whether its file needs rewriting.
+    fn fold_legacy_state(&mut self) -> bool {
+        let legacy = if self.trashed {
+            NoteState::Trashed
+        } else if self.archived {
+            NoteState::Archived
+        } else if self.pinned {
+            NoteState::Pinned
+        } else {
+            return false;
+        };
+        if self.state.is_active() {
+            self.state = legacy;
+        }
+        self.pinned = false;
+        self.archived = false;
+        self.trashed = false;
+        true
+    }
+}
+
+// Lifecycle state of a note. A single enum so a note can't be, say, archived and trashed at once.
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum NoteState {
+    #[default]
+    Active,
+    Pinned,
+    Archived,
+    Trashed,
+}
+
+impl NoteState {
+    fn is_active(&self) -> bool {
+        *self == NoteState::Active
+    }
+
+    // Pinning and archiving only apply to live notes, and trashed notes must be restored
+    // to active before anything else
+    pub fn can_transition_to(self, to: NoteState) -> bool {
+        use NoteState::*;
+        match (self, to) {
+            (from, to) if from == to => true,
+            (Active, _) => true,
+            (Pinned, Active | Archived | Trashed) => true,
+            (Archived, Active | Trashed) => true,
+            (Trashed, Active) => true,
+            _ => false,
+        }
+    }
+}
+
+// Orderings supported by list_notes_sorted
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+    #[default]
+    NewestFirst,
+    OldestFirst,
+    RecentlyUpdated,
+    TitleAsc,
+    TitleDesc,
+    // The order saved by set_note_order
+    Manual,
+}
+
+impl std::str::FromStr for SortOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "newest_first" => Ok(SortOrder::NewestFirst),
+            "oldest_first" => Ok(SortOrder::OldestFirst),
+            "recently_updated" => Ok(SortOrder::RecentlyUpdated),
+            "title_asc" => Ok(SortOrder::TitleAsc),
+            "title_desc" => Ok(SortOrder::TitleDesc),
+            "manual" => Ok(SortOrder::Manual),
+            _ => Err(format!(
+                "Unknown sort order '{}'. Expected one of: newest_first, oldest_first, recently_updated, title_asc, title_desc, manual",
+                s
+            )),
+        }
+    }
+}
+
+// Which search count_matches counts results for
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum SearchMode {
+    // search_notes
+    #[default]
+    Text,
+    // title_autocomplete
+    Fuzzy,
+    // semantic_search
+    Semantic,
+}
+
+// A single operation in a batch() call
+#[derive(Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum BatchOp {
+    CreateNote,
+    GetNote { id: String },
+    SaveNote { id: String, title: String, content: String },
+    DeleteNote { id: String },
+}
+
+// Outcome of a single batch operation, in the same position as its op
+#[derive(Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum BatchOpResult {
+    Ok { value: serde_json::Value },
+    Error { message: String },
+    // Not run because an earlier op failed with stop_on_error set
+    Skipped,
+}
+
+fn sort_notes(notes: &mut [Note], order: SortOrder) {
+    match order {
+        SortOrder::NewestFirst => notes.sort_by(|a, b| b.created_at.cmp(&a.created_at)),
+        SortOrder::OldestFirst => notes.sort_by(|a, b| a.created_at.cmp(&b.created_at)),
+        SortOrder::RecentlyUpdated => notes.sort_by(|a, b| b.updated_at.cmp(&a.updated_at)),
+        SortOrder::TitleAsc => notes.sort_by_key(|n| n.title.to_lowercase()),
+        SortOrder::TitleDesc => notes.sort_by_key(|n| std::cmp::Reverse(n.title.to_lowercase())),
+        SortOrder::Manual => order::apply(notes, &order::load_order()),
+    }
+}
+
+fn now_rfc3339() -> String {
+    chrono::Utc::now().to_rfc3339()
+}
+
+// Words in a note's content, counted as whitespace-separated runs
+pub(crate) fn word_count(text: &str) -> usize {
+    text.split_whitespace().count()
+}
+
+// Fast, stable hash of a note's title and content, as 16 hex digits. Both are normalized
+// first (NFC, LF line endings, trailing whitespace dropped from each line and the end) so
+// copies that differ only in invisible ways hash the same.
+pub(crate) fn content_hash(note: &Note) -> String {
+    use unicode_normalization::UnicodeNormalization;
+    let normalize = |text: &str| -> String {
+        let text: String = text.nfc().collect();
+        text.lines().map(str::trim_end).collect::<Vec<_>>().join("\n").trim_end().to_string()
+    };
+    hash_title_and_content(&normalize(&note.title), &normalize(&note.content))
+}
+
+// Like content_hash, but over the title and content exactly as stored, for callers where
+// any difference at all counts (exact duplicates, stale embeddings)
+pub(crate) fn exact_content_hash(note: &Note) -> String {
+    hash_title_and_content(&note.title, &note.content)
+}
+
+fn hash_title_and_content(title: &str, content: &str) -> String {
+    let mut hasher = xxhash_rust::xxh3::Xxh3::new();
+    hasher.update(title.as_bytes());
+    hasher.update(&[0]);
+    hasher.update(content.as_bytes());
+    format!("{:016x}", hasher.digest())
+}
+
+// Reject content over the configured size limit
+fn check_note_size(content: &str, config: &config::AppConfig) -> Result<(), NoteError> {
+    let limit = config.max_note_bytes();
+    if content.len() > limit {
+        return Err(NoteError::TooLarge { size: content.len(), limit });
+    }
+    Ok(())
+}
+
+// Clamp a cursor offset into `content` and back it up onto a char boundary
+fn clamp_cursor(content: &str, offset: usize) -> usize {
+    let mut offset = offset.min(content.len());
+    while !content.is_char_boundary(offset) {
+        offset -= 1;
+    }
+    offset
+}
+
+// Title given to newly created notes until the user (or auto-title) replaces it
+pub const NEW_NOTE_TITLE: &str = "New Note";
+
+// Longest title auto-title will derive from a note's first line
+const AUTO_TITLE_MAX_CHARS: usize = 80;
+
+// Title taken from the first non-blank line of `content`, minus any heading marker
+fn title_from_content(content: &str) -> Option<String> {
+    let line = content.lines().map(str::trim).find(|line| !line.is_empty())?;
+    let line = line.trim_start_matches('#').trim();
+    if line.is_empty() {
+        return None;
+    }
+    Some(line.chars().take(AUTO_TITLE_MAX_CHARS).collect::<String>().trim_end().to_string())
+}
+
+// The title to save a note under: with auto-title on, the placeholder is replaced by the
+// first line once there's content, and any other title is kept as the user wrote it
+fn auto_title(title: String, content: &str, enabled: bool) -> String {
+    match title_from_content(content) {
+        Some(derived) if enabled && title == NEW_NOTE_TITLE => derived,
+        _ => title,
+    }
+}
+
+// Lightweight view of a note for lists that don't need the content
+#[derive(Serialize, Deserialize, Clone)]
+pub struct NoteSummary {
+    pub id: String,
+    pub title: String,
+}
+
+impl From<&Note> for NoteSummary {
+    fn from(note: &Note) -> Self {
+        NoteSummary {
+            id: note.id.clone(),
+            title: note.title.clone(),
+        }
+    }
+}
+
+// Helper function to get the notes directory, creating it if needed
+fn notes_dir() -> Result<PathBuf, NoteError> {
+    let dir = config::load_config().notes_dir();
+    create_dir_all(&dir).map_err(|e| vault_not_writable(&dir, &e))?;
+    Ok(dir)
+}
+
+fn vault_not_writable(dir: &Path, e: &std::io::Error) -> NoteError {
+    NoteError::VaultNotWritable { path: dir.display().to_string(), reason: e.to_string() }
+}
+
+// Why the notes folder couldn't be written when the app started, if it couldn't. Kept so
+// the UI can ask once it's ready, since the check runs before any window exists.
+static STARTUP_VAULT_ERROR: once_cell::sync::OnceCell<String> = once_cell::sync::OnceCell::new();
+
+// Check that notes can actually be written by creating and removing a probe file
+fn check_vault_writable() -> Result<(), NoteError> {
+    check_dir_writable(&notes_dir()?)
 }
 
-// Helper function to get the notes directory
-fn notes_dir() -> PathBuf {
-    let dir = dirs::home_dir().unwrap().join(".minimal-notes").join("notes");
+// Create `dir` if needed and check that files can be written in it
+fn check_dir_writable(dir: &Path) -> Result<(), NoteError> {
+    create_dir_all(dir).map_err(|e| vault_not_writable(dir, &e))?;
+    let probe = dir.join(".write-test");
+    File::create(&probe)
+        .and_then(|mut f| f.write_all(b"ok"))
+        .and_then(|_| remove_file(&probe))
+        .map_err(|e| vault_not_writable(dir, &e))
+}
+
+// Helper function to get the path of the persisted embedding index
+fn index_path() -> PathBuf {
+    let dir = config::load_config().vault_data_dir();
     create_dir_all(&dir).ok();
-    dir
+    dir.join("index.json")
+}
+
+// Helper function to find a note's file, whichever format it was saved in
+fn find_note_file(id: &str) -> Option<PathBuf> {
+    crate::format::note_file_in(&notes_dir().ok()?, id)
+}
+
+// Helper function to read and parse a single note file, detecting the format from its extension
+fn read_note_file(path: &Path) -> Result<Note, String> {
+    let format = NoteFormat::from_path(path)
+        .ok_or_else(|| format!("Unrecognized note file: {}", path.display()))?;
+    let mut contents = String::new();
+    File::open(path)
+        .and_then(|mut f| f.read_to_string(&mut contents))
+        .map_err(|e| e.to_string())?;
+    let mut note = match format.parse(&contents) {
+        Ok(note) => note,
+        // A truncated JSON file still yields whatever title and content made it to disk
+        Err(e) if format == NoteFormat::Json => {
+            let stem = path.file_stem().and_then(|stem| stem.to_str()).unwrap_or_default();
+            let Some(note) = crate::format::recover_json(&contents, stem) else {
+                return Err(e);
+            };
+            eprintln!("Recovered damaged note file {}: {}", path.display(), e);
+            note
+        }
+        Err(e) => return Err(e),
+    };
+    note.fold_legacy_state();
+    
+    // Notes written before timestamps existed borrow them from the file
+    if note.created_at.is_empty() || note.updated_at.is_empty() {
+        let modified = std::fs::metadata(path)
+            .and_then(|m| m.modified())
+            .map(|t| chrono::DateTime::<chrono::Utc>::from(t).to_rfc3339())
+            .unwrap_or_default();
+        if note.created_at.is_empty() {
+            note.created_at = modified.clone();
+        }
+        if note.updated_at.is_empty() {
+            note.updated_at = modified;
+        }
+    }
+    Ok(note)
+}
+
+// Replace `path` with `contents` via a temp file and rename, so a crash mid-write leaves
+// either the old file or the new one, never half of each
+fn write_atomically(path: &Path, contents: &str) -> std::io::Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+    std::fs::write(&tmp, contents)?;
+    std::fs::rename(&tmp, path)
+}
+
+type NoteLocks = HashMap<String, std::sync::Arc<std::sync::Mutex<()>>>;
+
+// One lock per note id, held while a note is read, changed and written back
+static NOTE_LOCKS: once_cell::sync::Lazy<std::sync::Mutex<NoteLocks>> =
+    once_cell::sync::Lazy::new(|| std::sync::Mutex::new(HashMap::new()));
+
+fn note_lock(id: &str) -> std::sync::Arc<std::sync::Mutex<()>> {
+    let mut locks = NOTE_LOCKS.lock().unwrap_or_else(|e| e.into_inner());
+    locks.entry(id.to_string()).or_default().clone()
+}
+
+// Re-read a note and apply `change` to it under the note's lock, saving it when `change`
+// returns true. Writers going through here see each other's changes instead of saving over
+// them with a stale copy.
+pub(crate) fn update_note_locked(
+    id: &str,
+    change: impl FnOnce(&mut Note) -> Result<bool, NoteError>,
+) -> Result<Note, NoteError> {
+    let lock = note_lock(id);
+    let _guard = lock.lock().unwrap_or_else(|e| e.into_inner());
+    let mut note = commands::get_note(id.to_string()).map_err(|_| NoteError::NotFound(id.to_string()))?;
+    if change(&mut note)? {
+        commands::save_note_to_disk(&note)?;
+    }
+    Ok(note)
 }
 
 // Define a module for our commands
@@ -29,127 +449,2390 @@ pub mod commands {
     use super::*;
     
     
-    // Basic text search for notes
+    // Basic text search for notes
+    #[tauri::command]
+    pub fn search_notes(query: String) -> Vec<Note> {
+        if query.is_empty() {
+            return list_notes(None);
+        }
+        
+        // Perform basic text search, widened by each note's language when it's known. The
+        // full-text index narrows down which notes need checking.
+        let Some(ids) = text_index_candidates(&query) else {
+            return list_notes(None).into_iter().filter(|note| note_matches(note, &query)).collect();
+        };
+        let mut notes: Vec<Note> = ids
+            .into_iter()
+            .filter_map(|id| get_note(id).ok())
+            .filter(|note| note_matches(note, &query))
+            .collect();
+        sort_notes(&mut notes, crate::config::load_config().default_sort);
+        notes
+    }
+    
+    // The notes the full-text index says could match `query`, if it can tell
+    fn text_index_candidates(query: &str) -> Option<std::collections::HashSet<String>> {
+        crate::text::TEXT_INDEX.lock().ok()?.candidates(query)
+    }
+    
+    // Length of the excerpts search_notes_detailed returns, in characters
+    const SEARCH_SNIPPET_CHARS: usize = 160;
+    
+    // A search result with an excerpt around the match, for result lists
+    #[derive(Serialize, Clone)]
+    pub struct SearchHit {
+        pub id: String,
+        pub title: String,
+        pub snippet: String,
+    }
+    
+    // search_notes, returning each note's id and title with an excerpt around the match
+    #[tauri::command]
+    pub fn search_notes_detailed(query: String) -> Vec<SearchHit> {
+        search_notes(query.clone())
+            .into_iter()
+            .map(|note| SearchHit {
+                snippet: crate::text::snippet(&note.content, &query, SEARCH_SNIPPET_CHARS),
+                id: note.id,
+                title: note.title,
+            })
+            .collect()
+    }
+    
+    // Most "search-result" events a single search_notes_streaming query sends
+    const MAX_STREAMED_RESULTS: usize = 500;
+    
+    // Cancels remembered for queries that hadn't started yet
+    const MAX_EARLY_CANCELS: usize = 64;
+    
+    // search_notes_streaming queries still running, and cancels that arrived before their
+    // query did (the two commands can be handled in either order)
+    #[derive(Default)]
+    struct SearchRegistry {
+        active: std::collections::HashSet<String>,
+        // Bounded, since a cancel for a query that already finished ends up here too
+        cancelled_early: std::collections::VecDeque<String>,
+    }
+    
+    impl SearchRegistry {
+        // Register a query as running; false if it was cancelled before it started
+        fn start(&mut self, query_id: &str) -> bool {
+            if let Some(i) = self.cancelled_early.iter().position(|id| id == query_id) {
+                self.cancelled_early.remove(i);
+                return false;
+            }
+            self.active.insert(query_id.to_string());
+            true
+        }
+        
+        fn is_active(&self, query_id: &str) -> bool {
+            self.active.contains(query_id)
+        }
+        
+        fn finish(&mut self, query_id: &str) {
+            self.active.remove(query_id);
+        }
+        
+        // Stop a running query, or stop it as soon as it starts; true if it was running
+        fn cancel(&mut self, query_id: String) -> bool {
+            if self.active.remove(&query_id) {
+                return true;
+            }
+            if self.cancelled_early.len() == MAX_EARLY_CANCELS {
+                self.cancelled_early.pop_front();
+            }
+            self.cancelled_early.push_back(query_id);
+            false
+        }
+    }
+    
+    static ACTIVE_SEARCHES: once_cell::sync::Lazy<std::sync::Mutex<SearchRegistry>> =
+        once_cell::sync::Lazy::new(Default::default);
+    
+    // One match found by search_notes_streaming, emitted as a "search-result" event
+    #[derive(Serialize, Clone)]
+    pub struct StreamedSearchResult {
+        pub query_id: String,
+        pub hit: SearchHit,
+    }
+    
+    // Emitted as "search-done" when a search_notes_streaming scan finishes or stops
+    #[derive(Serialize, Clone)]
+    pub struct SearchDone {
+        pub query_id: String,
+        pub matched: usize,
+        pub cancelled: bool,
+        // The scan stopped at MAX_STREAMED_RESULTS; there may be more matches
+        pub truncated: bool,
+        // Why the scan failed, if it did; the results sent before it are still valid
+        pub error: Option<String>,
+    }
+    
+    // Scan the vault for `query` off the main thread, emitting each match as a "search-result"
+    // event as soon as it's found and a "search-done" event at the end, so large vaults show
+    // results before the scan completes. Matches arrive in directory order. Tag the query with
+    // `query_id` so a newer query can stop it with cancel_search, even one that hasn't started
+    // yet. "search-done" is sent however the scan ends, with `error` set if it failed.
+    // Returns the match count.
+    #[tauri::command(async)]
+    pub fn search_notes_streaming(window: tauri::Window, query_id: String, query: String) -> Result<usize, NoteError> {
+        let _timer = crate::timing::CommandTimer::start("search_notes_streaming");
+        let mut done = SearchDone { query_id: query_id.clone(), matched: 0, cancelled: false, truncated: false, error: None };
+        let scan = match ACTIVE_SEARCHES.lock().map(|mut searches| searches.start(&query_id)) {
+            Ok(true) => {
+                let scan = stream_search_results(&window, &query, &mut done);
+                if let Ok(mut searches) = ACTIVE_SEARCHES.lock() {
+                    searches.finish(&query_id);
+                }
+                scan
+            }
+            Ok(false) => {
+                done.cancelled = true;
+                Ok(())
+            }
+            Err(e) => Err(e.into()),
+        };
+        if let Err(e) = &scan {
+            done.error = Some(e.to_string());
+        }
+        let matched = done.matched;
+        window.emit("search-done", done).map_err(|e| NoteError::Io(e.to_string()))?;
+        scan.map(|()| matched)
+    }
+    
+    // The scan behind search_notes_streaming, recording what happened in `done`
+    fn stream_search_results(window: &tauri::Window, query: &str, done: &mut SearchDone) -> Result<(), NoteError> {
+        for entry in read_dir(notes_dir()?)?.flatten() {
+            if !ACTIVE_SEARCHES.lock()?.is_active(&done.query_id) {
+                done.cancelled = true;
+                break;
+            }
+            let Ok(note) = read_note_file(&entry.path()) else {
+                continue;
+            };
+            if note.state == NoteState::Trashed || (!query.is_empty() && !note_matches(&note, query)) {
+                continue;
+            }
+            if done.matched == MAX_STREAMED_RESULTS {
+                done.truncated = true;
+                break;
+            }
+            let hit = SearchHit {
+                snippet: crate::text::snippet(&note.content, query, SEARCH_SNIPPET_CHARS),
+                id: note.id,
+                title: note.title,
+            };
+            window
+                .emit("search-result", StreamedSearchResult { query_id: done.query_id.clone(), hit })
+                .map_err(|e| NoteError::Io(e.to_string()))?;
+            done.matched += 1;
+        }
+        Ok(())
+    }
+    
+    // Stop a search_notes_streaming query. If it hasn't started yet it stops as soon as it
+    // does. Returns whether it was running.
+    #[tauri::command]
+    pub fn cancel_search(query_id: String) -> Result<bool, NoteError> {
+        Ok(ACTIVE_SEARCHES.lock()?.cancel(query_id))
+    }
+    
+    // Whether search_notes would return `note` for `query`. Trashed notes never match. The
+    // title is checked first so the content is only scanned when it has to be.
+    fn note_matches(note: &Note, query: &str) -> bool {
+        if note.state == NoteState::Trashed {
+            return false;
+        }
+        let language = note.language.as_deref();
+        crate::language::matches_query(&note.title, query, language) || 
+        crate::language::matches_query(&note.content, query, language)
+    }
+    
+    // Number of notes a search would return, without building the results, for a live
+    // "N results" count. Fuzzy counts every title match, not just the ones
+    // title_autocomplete would show.
+    #[tauri::command]
+    pub fn count_matches(query: String, mode: Option<SearchMode>) -> Result<usize, NoteError> {
+        // semantic_search falls back to text search for now, so it counts the same
+        if mode != Some(SearchMode::Fuzzy) && !query.is_empty() {
+            if let Some(ids) = text_index_candidates(&query) {
+                return Ok(ids
+                    .into_iter()
+                    .filter_map(|id| get_note(id).ok())
+                    .filter(|note| note_matches(note, &query))
+                    .count());
+            }
+        }
+        let fuzzy_query = query.trim().to_lowercase();
+        let mode = mode.unwrap_or_default();
+        let mut count = 0;
+        for entry in read_dir(notes_dir()?)?.flatten() {
+            let Ok(note) = read_note_file(&entry.path()) else {
+                continue;
+            };
+            if counts_as_match(&note, &query, &fuzzy_query, mode) {
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+    
+    // Whether count_matches counts `note`; `fuzzy_query` is `query` trimmed and lowercased
+    fn counts_as_match(note: &Note, query: &str, fuzzy_query: &str, mode: SearchMode) -> bool {
+        if note.state == NoteState::Trashed {
+            return false;
+        }
+        match mode {
+            // semantic_search falls back to text search for now, so it counts the same
+            SearchMode::Text | SearchMode::Semantic => query.is_empty() || note_matches(note, query),
+            SearchMode::Fuzzy => title_match_rank(&note.title.to_lowercase(), fuzzy_query).is_some(),
+        }
+    }
+    
+    // Semantic search (simplified version - falls back to text search for now)
+    #[tauri::command]
+    pub fn semantic_search(query: String, _distance_cutoff: Option<f32>) -> Vec<Note> {
+        // For now, just use the basic text search
+        // In the future, this could be enhanced with embeddings or other semantic techniques
+        search_notes(query)
+    }
+    
+    // List the notes in the given state, or every note outside the trash. Trashed notes are
+    // only listed when asked for by state.
+    #[tauri::command]
+    pub fn list_notes(state: Option<NoteState>) -> Vec<Note> {
+        list_all_notes().into_iter().filter(|note| listed_in(note, state)).collect()
+    }
+    
+    // Whether list_notes(state) includes `note`: notes in `state`, or with None every note
+    // that isn't trashed
+    fn listed_in(note: &Note, state: Option<NoteState>) -> bool {
+        match state {
+            Some(state) => note.state == state,
+            None => note.state != NoteState::Trashed,
+        }
+    }
+    
+    // Every note, trashed ones included, for indexes and bookkeeping that must see them all
+    pub(crate) fn list_all_notes() -> Vec<Note> {
+        list_notes_sorted(None)
+    }
+    
+    // Notes rebuilt from damaged files that the user hasn't saved since, for review
+    #[tauri::command]
+    pub fn list_recovered_notes() -> Vec<Note> {
+        list_all_notes().into_iter().filter(|note| note.recovered).collect()
+    }
+    
+    // Move a note to a new state, rejecting transitions NoteState doesn't allow
+    #[tauri::command]
+    pub fn set_note_state(id: String, state: NoteState) -> Result<Note, NoteError> {
+        update_note_locked(&id, |note| {
+            if !note.state.can_transition_to(state) {
+                return Err(NoteError::InvalidInput(format!(
+                    "a {:?} note can't be moved to {:?}",
+                    note.state, state
+                )));
+            }
+            if note.state == state {
+                return Ok(false);
+            }
+            note.state = state;
+            Ok(true)
+        })
+    }
+    
+    // Rewrite note files that still carry pinned/archived/trashed flags so they store a
+    // NoteState instead. Reads already fold the flags, so this only tidies the files.
+    // Returns the ids of the migrated notes.
+    #[tauri::command]
+    pub fn migrate_note_states() -> Result<Vec<String>, NoteError> {
+        let mut migrated = Vec::new();
+        for entry in read_dir(notes_dir()?)?.flatten() {
+            let path = entry.path();
+            let Some(format) = NoteFormat::from_path(&path) else {
+                continue;
+            };
+            let Ok(contents) = std::fs::read_to_string(&path) else {
+                continue;
+            };
+            let Ok(mut note) = format.parse(&contents) else {
+                continue;
+            };
+            if note.fold_legacy_state() {
+                // Go through read_note_file so missing timestamps get filled in as usual
+                let mut current = read_note_file(&path).map_err(NoteError::Io)?;
+                current.state = note.state;
+                save_note_to_disk(&current)?;
+                migrated.push(current.id);
+            }
+        }
+        Ok(migrated)
+    }
+    
+    // What migrate_note_schema did, or would do in a dry run
+    #[derive(Serialize)]
+    pub struct MigrationReport {
+        pub dry_run: bool,
+        pub scanned: usize,
+        // Files rewritten (or that would be) with every current field filled in
+        pub migrated: usize,
+        pub up_to_date: usize,
+        // Recovered notes are left for the user to review before anything rewrites them
+        pub skipped_recovered: usize,
+        // Files that couldn't be read or written, with the reason
+        pub failed: Vec<String>,
+    }
+    
+    // Bring every note file up to the current schema: fields added since it was written get
+    // their defaults, missing timestamps come from the file's modification time, and legacy
+    // state flags are folded into `state`. Files keep their format and are replaced
+    // atomically. With `dry_run` nothing is written.
+    #[tauri::command]
+    pub fn migrate_note_schema(dry_run: bool) -> Result<MigrationReport, NoteError> {
+        migrate_dir(&notes_dir()?, dry_run)
+    }
+    
+    // migrate_note_schema over the note files in `dir`
+    fn migrate_dir(dir: &Path, dry_run: bool) -> Result<MigrationReport, NoteError> {
+        let mut report = MigrationReport { dry_run, scanned: 0, migrated: 0, up_to_date: 0, skipped_recovered: 0, failed: vec![] };
+        for entry in read_dir(dir)?.flatten() {
+            let path = entry.path();
+            let Some(format) = NoteFormat::from_path(&path) else {
+                continue;
+            };
+            report.scanned += 1;
+            let (contents, note, serialized) = match current_schema(&path, format) {
+                Ok(migration) => migration,
+                Err(e) => {
+                    report.failed.push(format!("{}: {}", path.display(), e));
+                    continue;
+                }
+            };
+            if note.recovered {
+                report.skipped_recovered += 1;
+                continue;
+            }
+            if serialized == contents {
+                report.up_to_date += 1;
+                continue;
+            }
+            if !dry_run {
+                if let Err(e) = write_atomically(&path, &serialized) {
+                    report.failed.push(format!("{}: {}", path.display(), e));
+                    continue;
+                }
+                if let Ok(mut cache) = NOTE_CACHE.lock() {
+                    cache.invalidate(&note.id);
+                }
+            }
+            report.migrated += 1;
+        }
+        Ok(report)
+    }
+    
+    // A note file's contents, the note read from it with defaults filled in, and that note
+    // serialized as it would be written today
+    fn current_schema(path: &Path, format: NoteFormat) -> Result<(String, Note, String), String> {
+        let contents = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        let note = read_note_file(path)?;
+        let serialized = format.serialize(&note)?;
+        Ok((contents, note, serialized))
+    }
+    
+    // Default and maximum number of notes per list_notes_stream event
+    const STREAM_BATCH_SIZE: usize = 200;
+    const MAX_STREAM_BATCH_SIZE: usize = 1000;
+    
+    // One batch of notes emitted by list_notes_stream
+    #[derive(Serialize, Clone)]
+    pub struct NotesBatch {
+        pub batch: usize,
+        pub notes: Vec<Note>,
+        pub done: bool,
+    }
+    
+    // Stream the notes list_notes(state) would return to the window as "notes-batch" events
+    // instead of returning them all at once, so only one batch is held in memory. Notes
+    // arrive unsorted, in directory order. Returns the number of notes sent.
+    #[tauri::command]
+    pub fn list_notes_stream(window: tauri::Window, batch_size: Option<usize>, state: Option<NoteState>) -> Result<usize, NoteError> {
+        let batch_size = batch_size.unwrap_or(STREAM_BATCH_SIZE).clamp(1, MAX_STREAM_BATCH_SIZE);
+        stream_notes(&notes_dir()?, batch_size, state, |batch| {
+            window.emit("notes-batch", batch).map_err(|e| NoteError::Io(e.to_string()))
+        })
+    }
+    
+    // Read the notes in `dir` that list_notes(state) would include, handing them to `send` in
+    // batches of `batch_size` as each one fills. The last batch is always sent and marked
+    // done, even if it's empty. Returns the number of notes sent. Public for the list_notes
+    // benchmark.
+    pub fn stream_notes(
+        dir: &Path,
+        batch_size: usize,
+        state: Option<NoteState>,
+        mut send: impl FnMut(NotesBatch) -> Result<(), NoteError>,
+    ) -> Result<usize, NoteError> {
+        let mut batch = 0;
+        let mut sent = 0;
+        let mut notes = Vec::with_capacity(batch_size);
+        for entry in read_dir(dir)?.flatten() {
+            match read_note_file(&entry.path()) {
+                Ok(note) if listed_in(&note, state) => notes.push(note),
+                _ => continue,
+            }
+            if notes.len() == batch_size {
+                sent += notes.len();
+                send(NotesBatch { batch, notes: std::mem::take(&mut notes), done: false })?;
+                batch += 1;
+            }
+        }
+        sent += notes.len();
+        send(NotesBatch { batch, notes, done: true })?;
+        Ok(sent)
+    }
+    
+    // List all notes in the given order, or the configured default order
+    #[tauri::command]
+    pub fn list_notes_sorted(sort: Option<SortOrder>) -> Vec<Note> {
+        let mut notes = vec![];
+        let dir = match notes_dir() {
+            Ok(dir) => dir,
+            Err(e) => {
+                eprintln!("{}", e);
+                return notes;
+            }
+        };
+        if let Ok(entries) = read_dir(dir) {
+            for entry in entries.flatten() {
+                if let Ok(note) = read_note_file(&entry.path()) {
+                    notes.push(note);
+                }
+            }
+        }
+        let order = sort.unwrap_or_else(|| crate::config::load_config().default_sort);
+        sort_notes(&mut notes, order);
+        notes
+    }
+    
+    // Get the sort order used when list_notes isn't given one
+    #[tauri::command]
+    pub fn get_default_sort() -> SortOrder {
+        crate::config::load_config().default_sort
+    }
+    
+    // The vault-wide defaults: model, temperature, token ceiling, autocomplete, notes
+    // directory and sort order, with built-in values for anything not yet set
+    #[tauri::command]
+    pub fn vault_config() -> crate::config::VaultConfig {
+        crate::config::VaultConfig::from_app_config(&crate::config::load_config())
+    }
+    
+    // Validate and save the vault config in one write. Returns the config as saved. A new
+    // notes directory must be writable, and everything loaded from the old one is dropped.
+    #[tauri::command]
+    pub fn set_vault_config(config: crate::config::VaultConfig) -> Result<crate::config::VaultConfig, NoteError> {
+        config.validate().map_err(NoteError::InvalidInput)?;
+        let mut app_config = crate::config::load_config();
+        let old_notes_dir = app_config.notes_dir();
+        config.apply_to(&mut app_config);
+        let vault_changed = app_config.notes_dir() != old_notes_dir;
+        if vault_changed {
+            check_dir_writable(&app_config.notes_dir())?;
+            // Keep the old vault's embeddings for when it's opened again
+            if let Err(e) = persist_index() {
+                eprintln!("Error saving embedding index: {}", e);
+            }
+        }
+        crate::config::save_config(&app_config).map_err(NoteError::Io)?;
+        if vault_changed {
+            switch_vault();
+        }
+        Ok(crate::config::VaultConfig::from_app_config(&app_config))
+    }
+    
+    // Drop everything cached from the previous notes directory and load the new one's
+    // indexes. The full-text index is rebuilt in the background; searches scan the notes
+    // until it's ready.
+    fn switch_vault() {
+        if let Ok(mut cache) = NOTE_CACHE.lock() {
+            cache.clear();
+        }
+        if let Ok(mut clusters) = CLUSTER_CACHE.lock() {
+            *clusters = None;
+        }
+        crate::reminders::reset_index();
+        crate::cache::reset_titles();
+        crate::text::reset();
+        std::thread::spawn(|| {
+            crate::text::rebuild(|index| list_all_notes().iter().for_each(|note| index.update(note)));
+        });
+        if let Err(e) = load_index() {
+            eprintln!("Error loading embedding index for the new vault: {}", e);
+        }
+    }
+    
+    // Persist the sort order used when list_notes isn't given one
+    #[tauri::command]
+    pub fn set_default_sort(order: String) -> Result<SortOrder, String> {
+        let order: SortOrder = order.parse()?;
+        let mut config = crate::config::load_config();
+        config.default_sort = order;
+        crate::config::save_config(&config)?;
+        Ok(order)
+    }
+    
+    // Save a manual note order for SortOrder::Manual. Unknown and repeated ids are dropped;
+    // notes left out are listed after the ordered ones. Returns the order as saved.
+    #[tauri::command]
+    pub fn set_note_order(ordered_ids: Vec<String>) -> Result<Vec<String>, NoteError> {
+        let existing: std::collections::HashSet<String> =
+            list_all_notes().into_iter().map(|note| note.id).collect();
+        let ids = crate::order::normalize(ordered_ids, &existing);
+        crate::order::save_order(&ids).map_err(NoteError::Io)?;
+        Ok(ids)
+    }
+    
+    // Get the saved manual note order
+    #[tauri::command]
+    pub fn get_note_order() -> Vec<String> {
+        crate::order::load_order()
+    }
+    
+    // Get a single note by id
+    #[tauri::command]
+    pub fn get_note(id: String) -> Result<Note, String> {
+        crate::cache::cached_or_load(&NOTE_CACHE, &id, || {
+            let path = find_note_file(&id).ok_or_else(|| format!("Note not found: {}", id))?;
+            read_note_file(&path)
+        })
+    }
+    
+    // Nested table of contents built from a note's markdown headings. Code notes aren't
+    // markdown, so their `#` lines are comments rather than headings and they get no outline.
+    #[tauri::command]
+    pub fn note_outline(id: String) -> Result<Vec<crate::markdown::OutlineEntry>, NoteError> {
+        let note = get_note(id.clone()).map_err(|_| NoteError::NotFound(id))?;
+        if note.metadata.get("mode").map(String::as_str) == Some(crate::context::CODE_NOTE_MODE) {
+            return Ok(Vec::new());
+        }
+        Ok(crate::markdown::outline(&note.content))
+    }
+    
+    // Absolute path of a note's file, in whichever format it's stored, for opening it in
+    // other tools
+    #[tauri::command]
+    pub fn note_path(id: String) -> Result<String, NoteError> {
+        let path = find_note_file(&id).ok_or(NoteError::NotFound(id))?;
+        Ok(std::path::absolute(&path)?.display().to_string())
+    }
+    
+    // Render a note's markdown content to sanitized HTML for preview/export
+    #[tauri::command]
+    pub fn render_note_html(id: String) -> Result<String, String> {
+        let note = get_note(id)?;
+        
+        // Resolve [[wiki-links]] by case-insensitive title match
+        let titles: std::collections::HashMap<String, String> = list_notes(None)
+            .into_iter()
+            .map(|n| (n.title.to_lowercase(), n.id))
+            .collect();
+        
+        Ok(crate::markdown::render_html(&note.content, |target| {
+            titles.get(&target.to_lowercase()).cloned()
+        }))
+    }
+    
+    // A note in the link graph, or a placeholder for a link target that doesn't exist yet
+    #[derive(Serialize)]
+    pub struct GraphNode {
+        pub id: String,
+        pub title: String,
+        pub placeholder: bool,
+    }
+    
+    // A wiki-link from `source` to `target` (node ids)
+    #[derive(Serialize, PartialEq, Eq, Hash, Clone)]
+    pub struct GraphEdge {
+        pub source: String,
+        pub target: String,
+    }
+    
+    #[derive(Serialize)]
+    pub struct NoteGraph {
+        pub nodes: Vec<GraphNode>,
+        pub edges: Vec<GraphEdge>,
+    }
+    
+    // Build the [[wiki-link]] graph for a graph view. Titles resolve case-insensitively;
+    // unresolved targets get one placeholder node each (id `unresolved:<title>`). Self-links
+    // are dropped and repeated links between the same pair become a single edge.
+    #[tauri::command]
+    pub fn export_graph() -> NoteGraph {
+        let notes = list_notes(None);
+        let titles: HashMap<String, String> = notes
+            .iter()
+            .map(|n| (n.title.to_lowercase(), n.id.clone()))
+            .collect();
+        
+        let mut nodes: Vec<GraphNode> = notes
+            .iter()
+            .map(|n| GraphNode { id: n.id.clone(), title: n.title.clone(), placeholder: false })
+            .collect();
+        let mut placeholders = std::collections::HashSet::new();
+        let mut seen = std::collections::HashSet::new();
+        let mut edges = Vec::new();
+        for note in &notes {
+            for link in crate::markdown::wiki_links(&note.content) {
+                let key = link.to_lowercase();
+                let target = match titles.get(&key) {
+                    Some(id) => id.clone(),
+                    None => {
+                        let id = format!("unresolved:{}", key);
+                        if placeholders.insert(id.clone()) {
+                            nodes.push(GraphNode { id: id.clone(), title: link.clone(), placeholder: true });
+                        }
+                        id
+                    }
+                };
+                if target == note.id {
+                    continue;
+                }
+                let edge = GraphEdge { source: note.id.clone(), target };
+                if seen.insert(edge.clone()) {
+                    edges.push(edge);
+                }
+            }
+        }
+        NoteGraph { nodes, edges }
+    }
+    
+    // List notes whose content contains a [[wiki-link]] to the given title.
+    // The target doesn't have to exist yet, so this works for planned notes too.
+    #[tauri::command]
+    pub fn notes_linking_to(title: String) -> Vec<NoteSummary> {
+        let target = title.trim().to_lowercase();
+        let mut sources: Vec<NoteSummary> = list_notes(None)
+            .iter()
+            .filter(|note| {
+                crate::markdown::wiki_links(&note.content)
+                    .iter()
+                    .any(|link| link.to_lowercase() == target)
+            })
+            .map(NoteSummary::from)
+            .collect();
+        sources.sort_by_key(|s| s.title.to_lowercase());
+        sources
+    }
+    
+    // Notes whose word count lies in `min_words..=max_words` (either bound may be left
+    // open), shortest first, leaving out the trash like list_notes. Reads one file at a time
+    // rather than loading every note.
+    #[tauri::command]
+    pub fn list_notes_by_length(min_words: Option<usize>, max_words: Option<usize>) -> Result<Vec<NoteSummary>, NoteError> {
+        let notes = read_dir(notes_dir()?)?.flatten().filter_map(|entry| read_note_file(&entry.path()).ok());
+        Ok(notes_by_length(notes, min_words, max_words))
+    }
+    
+    // The filtering and ordering behind list_notes_by_length
+    fn notes_by_length(notes: impl Iterator<Item = Note>, min_words: Option<usize>, max_words: Option<usize>) -> Vec<NoteSummary> {
+        let range = min_words.unwrap_or(0)..=max_words.unwrap_or(usize::MAX);
+        let mut matches = Vec::new();
+        for note in notes.filter(|note| listed_in(note, None)) {
+            let words = word_count(&note.content);
+            if range.contains(&words) {
+                matches.push((words, NoteSummary::from(&note)));
+            }
+        }
+        matches.sort_by(|(a_words, a), (b_words, b)| a_words.cmp(b_words).then_with(|| a.title.cmp(&b.title)));
+        matches.into_iter().map(|(_, summary)| summary).collect()
+    }
+    
+    // Most runs benchmark_search will do of each search, and how many semantic hits it asks for
+    const MAX_BENCHMARK_ITERATIONS: usize = 1000;
+    const BENCHMARK_SEMANTIC_K: usize = 10;
+    
+    // Latency of one kind of search over a benchmark run, in microseconds
+    #[derive(Serialize)]
+    pub struct SearchTiming {
+        pub min_us: u64,
+        pub median_us: u64,
+        pub max_us: u64,
+        // Results returned by the last run
+        pub results: usize,
+        // Set when the search failed, e.g. the semantic index isn't built
+        pub error: Option<String>,
+    }
+    
+    #[derive(Serialize)]
+    pub struct SearchBenchmark {
+        pub query: String,
+        pub iterations: usize,
+        pub notes: usize,
+        // Full scan over every note's title and content
+        pub keyword: SearchTiming,
+        // Title matching as used by the quick switcher
+        pub fuzzy: SearchTiming,
+        // Nearest-neighbour lookup in the embedding index
+        pub semantic: SearchTiming,
+    }
+    
+    // Run keyword, fuzzy and semantic search `iterations` times each and report their
+    // latencies, to tell whether the scan or the index is slow on this vault. Diagnostic
+    // only; runs on a worker thread so the UI stays responsive.
+    #[tauri::command(async)]
+    pub fn benchmark_search(query: String, iterations: usize) -> SearchBenchmark {
+        let _timer = crate::timing::CommandTimer::start("benchmark_search");
+        let iterations = iterations.clamp(1, MAX_BENCHMARK_ITERATIONS);
+        let keyword = time_search(iterations, || Ok(search_notes(query.clone()).len()));
+        let fuzzy = time_search(iterations, || Ok(title_autocomplete(query.clone(), MAX_TITLE_MATCHES).len()));
+        let semantic = time_search(iterations, || {
+            let mut manager = EMBEDDING_MANAGER.lock().map_err(|e| e.to_string())?;
+            manager.search(&query, BENCHMARK_SEMANTIC_K, None).map(|ids| ids.len()).map_err(|e| e.to_string())
+        });
+        SearchBenchmark { notes: list_notes(None).len(), query, iterations, keyword, fuzzy, semantic }
+    }
+    
+    // Time `search` over `iterations` runs; it returns its result count. Stops at the first error.
+    fn time_search<F>(iterations: usize, mut search: F) -> SearchTiming
+    where
+        F: FnMut() -> Result<usize, String>,
+    {
+        let mut durations = Vec::with_capacity(iterations);
+        let mut results = 0;
+        let mut error = None;
+        for _ in 0..iterations {
+            let start = std::time::Instant::now();
+            match search() {
+                Ok(count) => results = count,
+                Err(e) => {
+                    error = Some(e);
+                    break;
+                }
+            }
+            durations.push(start.elapsed().as_micros() as u64);
+        }
+        durations.sort_unstable();
+        SearchTiming {
+            min_us: durations.first().copied().unwrap_or_default(),
+            median_us: durations.get(durations.len() / 2).copied().unwrap_or_default(),
+            max_us: durations.last().copied().unwrap_or_default(),
+            results,
+            error,
+        }
+    }
+    
+    // Most results title_autocomplete will return
+    const MAX_TITLE_MATCHES: usize = 50;
+    
+    // Quick-switcher lookup over titles only, served from the title index (see
+    // cache::titles) rather than by reading every note. Trashed notes are left out. Matches
+    // rank as: title starts with the prefix, a word in the title does, the title contains
+    // it, then fuzzy (the prefix's characters appear in order, tighter first). Ties go to the
+    // most recently updated note.
+    #[tauri::command]
+    pub fn title_autocomplete(prefix: String, limit: usize) -> Vec<NoteSummary> {
+        let prefix = prefix.trim().to_lowercase();
+        let limit = limit.clamp(1, MAX_TITLE_MATCHES);
+        rank_titles(crate::cache::titles(|| list_notes(None)), &prefix)
+            .into_iter()
+            .take(limit)
+            .map(|entry| NoteSummary { id: entry.id, title: entry.title })
+            .collect()
+    }
+    
+    // Every entry whose title matches `prefix` (lowercase), best first (see title_match_rank).
+    // Entries come most recent first and keep that order within a rank.
+    fn rank_titles(entries: Vec<crate::cache::TitleEntry>, prefix: &str) -> Vec<crate::cache::TitleEntry> {
+        let mut matches: Vec<((u8, usize), crate::cache::TitleEntry)> = entries
+            .into_iter()
+            .filter_map(|entry| title_match_rank(&entry.title.to_lowercase(), prefix).map(|rank| (rank, entry)))
+            .collect();
+        // Stable sort keeps the recency order within equal ranks
+        matches.sort_by_key(|(rank, _)| *rank);
+        matches.into_iter().map(|(_, entry)| entry).collect()
+    }
+    
+    // Rank of `title` for `prefix` (both lowercase), lower is better; None if it doesn't match
+    fn title_match_rank(title: &str, prefix: &str) -> Option<(u8, usize)> {
+        if title.starts_with(prefix) {
+            return Some((0, 0));
+        }
+        if title.split(|c: char| !c.is_alphanumeric()).any(|word| word.starts_with(prefix)) {
+            return Some((1, 0));
+        }
+        if let Some(position) = title.find(prefix) {
+            return Some((2, position));
+        }
+        // Fuzzy: every character in order, scored by how many title characters are skipped
+        let mut title_chars = title.chars();
+        let mut skipped = 0;
+        for wanted in prefix.chars() {
+            loop {
+                let c = title_chars.next()?;
+                if c == wanted {
+                    break;
+                }
+                skipped += 1;
+            }
+        }
+        Some((3, skipped))
+    }
+    
+    // Export a note to PDF. `dest` may be a file path or an existing folder, in which case
+    // the file is named after the note. Images in the vault are embedded; remote images
+    // appear as a placeholder line. Returns the path written.
+    #[tauri::command]
+    pub fn export_note_pdf(id: String, dest: String) -> Result<String, NoteError> {
+        let note = get_note(id.clone()).map_err(|_| NoteError::NotFound(id))?;
+        let mut path = PathBuf::from(dest);
+        if path.is_dir() {
+            path.push(format!("{}.pdf", export_file_stem(&note)));
+        }
+        crate::pdf::export_note(&note, &path, &notes_dir()?).map_err(|e| NoteError::Io(format!("Failed to export PDF: {}", e)))?;
+        Ok(path.display().to_string())
+    }
+    
+    // Export every note tagged `#tag` as markdown into a `tag` subfolder of `dest`, skipping
+    // trashed notes. Returns the files written, which is empty (and creates nothing) when no
+    // note has the tag.
+    #[tauri::command]
+    pub fn export_by_tag(tag: String, dest: String) -> Result<Vec<String>, NoteError> {
+        let tag = tag.trim().trim_start_matches('#').to_lowercase();
+        if tag.is_empty() {
+            return Err(NoteError::InvalidInput("tag must not be empty".to_string()));
+        }
+        let notes: Vec<Note> = list_notes(None)
+            .into_iter()
+            .filter(|note| note.state != NoteState::Trashed && crate::markdown::tags(&note.content).contains(&tag))
+            .collect();
+        if notes.is_empty() {
+            return Ok(Vec::new());
+        }
+        
+        // Tags only contain letters, digits, '-', '_' and '/', so nested tags become nested folders
+        let dir = Path::new(&dest).join(&tag);
+        create_dir_all(&dir)?;
+        let mut used = std::collections::HashSet::new();
+        let mut written = Vec::new();
+        for note in notes {
+            let stem = export_file_stem(&note);
+            let mut name = format!("{}.md", stem);
+            let mut n = 2;
+            while !used.insert(name.clone()) {
+                name = format!("{} ({}).md", stem, n);
+                n += 1;
+            }
+            let path = dir.join(name);
+            std::fs::write(&path, format!("# {}\n\n{}\n", note.title, note.content.trim_end()))?;
+            written.push(path.display().to_string());
+        }
+        Ok(written)
+    }
+    
+    // Add `tag` to a note if it doesn't have it, or remove every occurrence if it does, and
+    // return the note's tags afterwards. Tags live inline in the content as `#tag`, so this
+    // is saved like save_note, under the note's lock.
+    #[tauri::command]
+    pub fn toggle_tag(id: String, tag: String) -> Result<Vec<String>, NoteError> {
+        let tag = crate::markdown::normalize_tag(&tag).ok_or_else(|| {
+            NoteError::InvalidInput(format!(
+                "tags must start with a letter and contain only letters, digits, '-', '_' or '/': {:?}",
+                tag
+            ))
+        })?;
+        let config = crate::config::load_config();
+        let lock = note_lock(&id);
+        let _guard = lock.lock().unwrap_or_else(|e| e.into_inner());
+        let note = get_note(id.clone()).map_err(|_| NoteError::NotFound(id.clone()))?;
+        let content = crate::markdown::toggle_tag(&note.content, &tag);
+        check_note_size(&content, &config)?;
+        let tags = crate::markdown::tags(&content);
+        save_note_locked(id, note.title, content, &config)?;
+        Ok(tags)
+    }
+    
+    // Metadata key marking a note generated by build_index_note. The value is the tag the
+    // index covers, or INDEX_ALL_NOTES for the index of every note.
+    const INDEX_NOTE_KEY: &str = "index_note";
+    const INDEX_ALL_NOTES: &str = "*";
+    const INDEX_SNIPPET_CHARS: usize = 80;
+    
+    // A note's entry in an index note: a link to it and the start of its content
+    fn index_line(note: &Note) -> String {
+        // Links and tags in the snippet would count as the index note's own
+        let snippet = crate::markdown::strip_links_and_tags(&note.content).split_whitespace().collect::<Vec<_>>().join(" ");
+        let mut line = format!("- [[{}]]", note.title);
+        if !snippet.is_empty() {
+            let short: String = snippet.chars().take(INDEX_SNIPPET_CHARS).collect();
+            let ellipsis = if short.len() < snippet.len() { "…" } else { "" };
+            line.push_str(&format!(" — {}{}", short, ellipsis));
+        }
+        line
+    }
+    
+    // Build a note listing `[[links]]` to every note (or every note tagged `tag`), newest
+    // first, each with a short snippet. Re-running it rewrites the same index note.
+    #[tauri::command]
+    pub fn build_index_note(tag: Option<String>) -> Result<Note, NoteError> {
+        let tag = tag
+            .map(|tag| tag.trim().trim_start_matches('#').to_lowercase())
+            .filter(|tag| !tag.is_empty());
+        let all_notes = list_notes_sorted(Some(SortOrder::RecentlyUpdated));
+        let (existing, content) = plan_index_note(&all_notes, tag.as_deref());
+        let mut note = match existing.cloned() {
+            Some(note) => note,
+            None => {
+                let title = match &tag {
+                    Some(tag) => format!("Index: #{}", tag),
+                    None => "Index".to_string(),
+                };
+                let mut note = new_note(title, String::new());
+                note.metadata.insert(INDEX_NOTE_KEY.to_string(), index_marker(tag.as_deref()));
+                note
+            }
+        };
+        note.content = content;
+        note.updated_at = now_rfc3339();
+        save_note_to_disk(&note)?;
+        index_note(&note);
+        Ok(note)
+    }
+    
+    // Value of INDEX_NOTE_KEY on the index note for `tag`
+    fn index_marker(tag: Option<&str>) -> String {
+        tag.unwrap_or(INDEX_ALL_NOTES).to_string()
+    }
+    
+    // The existing index note for `tag` among `notes`, if there is one, and the content the
+    // index should have: a line for each note tagged `tag` (or every note with None), in the
+    // order given. Trashed notes and index notes themselves aren't listed.
+    fn plan_index_note<'a>(notes: &'a [Note], tag: Option<&str>) -> (Option<&'a Note>, String) {
+        let marker = index_marker(tag);
+        let existing = notes.iter().find(|note| note.metadata.get(INDEX_NOTE_KEY) == Some(&marker));
+        let mut content = String::new();
+        for note in notes {
+            let listed = !note.metadata.contains_key(INDEX_NOTE_KEY)
+                && note.state != NoteState::Trashed
+                && tag.is_none_or(|tag| crate::markdown::tags(&note.content).iter().any(|t| t == tag));
+            if !listed {
+                continue;
+            }
+            content.push_str(&index_line(note));
+            content.push('\n');
+        }
+        (existing, content)
+    }
+    
+    // File name (without extension) for an exported note: its title with anything unsafe in
+    // a file name replaced, or the id for untitled notes
+    fn export_file_stem(note: &Note) -> String {
+        let name: String = note
+            .title
+            .chars()
+            .map(|c| if c.is_alphanumeric() || matches!(c, ' ' | '-' | '_') { c } else { '_' })
+            .collect();
+        if name.trim().is_empty() { note.id.clone() } else { name.trim().to_string() }
+    }
+    
+    // Find notes semantically related to the given note. Under the chunked strategy notes
+    // are compared chunk to chunk (see EmbeddingManager::related_to).
+    // The index uses cosine distance (0 = identical direction, 2 = opposite); we report
+    // similarity as `1 - distance`, so `min_similarity` of 0.5 keeps notes within distance 0.5.
+    // May return fewer than `k` notes when the threshold filters some out.
+    #[tauri::command]
+    pub fn related_notes(id: String, k: usize, min_similarity: Option<f32>) -> Result<Vec<Note>, String> {
+        let note = get_note(id.clone())?;
+        let neighbors = EMBEDDING_MANAGER.lock().map_err(|e| e.to_string())?.related_to(&note, k).map_err(|e| e.to_string())?;
+        
+        let related = neighbors
+            .into_iter()
+            .filter(|(_, distance)| min_similarity.is_none_or(|min| 1.0 - distance >= min))
+            .take(k)
+            .filter_map(|(note_id, _)| get_note(note_id).ok())
+            .collect();
+        Ok(related)
+    }
+    
+    // Semantic matches for `query` as the index sees them, for debugging search quality.
+    // Notes appear once at their best chunk unless `per_chunk` is set, which lists every
+    // matching chunk.
+    #[tauri::command]
+    pub fn semantic_hits(query: String, k: usize, per_chunk: Option<bool>) -> Result<Vec<crate::embeddings::ChunkHit>, NoteError> {
+        let embedding = EmbeddingManager::generate_simple_embedding(&query);
+        let hits = EMBEDDING_MANAGER.lock()?.search_chunks(&embedding, k)?;
+        let hits = if per_chunk.unwrap_or(false) { hits } else { crate::embeddings::dedup_hits(hits) };
+        Ok(hits.into_iter().take(k).collect())
+    }
+    
+    // k-means rounds run by cluster_notes
+    const CLUSTER_ITERATIONS: usize = 25;
+    
+    // Words in a list_notes_clustered label
+    const CLUSTER_LABEL_TERMS: usize = 3;
+    
+    // Notes grouped by topic for list_notes_clustered
+    #[derive(Serialize, Clone)]
+    pub struct NoteCluster {
+        // The cluster's most distinctive words, e.g. "recipe, oven, flour"
+        pub label: String,
+        pub notes: Vec<NoteSummary>,
+    }
+    
+    // A clustering with the k and the notes fingerprint it was computed for
+    type CachedClusters = (usize, u64, Vec<NoteCluster>);
+    
+    // The last clustering, reused while k and the notes (ids and edit times) stay the same
+    static CLUSTER_CACHE: once_cell::sync::Lazy<std::sync::Mutex<Option<CachedClusters>>> =
+        once_cell::sync::Lazy::new(Default::default);
+    
+    // Group notes into at most `k` clusters by k-means over their embeddings, largest first.
+    // Shared by list_notes_clustered and suggest_tag_clusters.
+    pub(crate) fn cluster_notes(notes: &[Note], k: usize) -> Result<Vec<Vec<&Note>>, NoteError> {
+        let vectors: Vec<Vec<f32>> = {
+            let manager = EMBEDDING_MANAGER.lock()?;
+            notes.iter().map(|note| manager.embedding_for(note)).collect()
+        };
+        let assignment = crate::embeddings::kmeans(&vectors, k, CLUSTER_ITERATIONS);
+        Ok(group_by_cluster(notes, &assignment, k))
+    }
+    
+    // Notes gathered by their cluster in `assignment`, empty clusters dropped, largest first
+    fn group_by_cluster<'a>(notes: &'a [Note], assignment: &[usize], k: usize) -> Vec<Vec<&'a Note>> {
+        let mut groups: Vec<Vec<&Note>> = vec![Vec::new(); k.min(notes.len())];
+        for (note, &cluster) in notes.iter().zip(assignment) {
+            groups[cluster].push(note);
+        }
+        groups.retain(|group| !group.is_empty());
+        groups.sort_by_key(|group| std::cmp::Reverse(group.len()));
+        groups
+    }
+    
+    // Group the vault into at most `k` topics by k-means over the notes' embeddings, largest
+    // group first, each labeled with its most distinctive words. Vaults with fewer than `k`
+    // notes get one group per note. The result is cached until a note changes; `refresh`
+    // recomputes it anyway.
+    #[tauri::command]
+    pub fn list_notes_clustered(k: usize, refresh: Option<bool>) -> Result<Vec<NoteCluster>, NoteError> {
+        if k == 0 {
+            return Err(NoteError::InvalidInput("k must be positive".to_string()));
+        }
+        let notes = list_notes(None);
+        let mut hasher = xxhash_rust::xxh3::Xxh3::new();
+        for note in &notes {
+            hasher.update(note.id.as_bytes());
+            hasher.update(note.updated_at.as_bytes());
+        }
+        let fingerprint = hasher.digest();
+        if !refresh.unwrap_or(false) {
+            if let Some((cached_k, cached_fingerprint, clusters)) = CLUSTER_CACHE.lock()?.as_ref() {
+                if *cached_k == k && *cached_fingerprint == fingerprint {
+                    return Ok(clusters.clone());
+                }
+            }
+        }
+        
+        let groups = cluster_notes(&notes, k)?;
+        let stop_words = crate::text::active_stop_words();
+        let clusters: Vec<NoteCluster> = groups
+            .iter()
+            .map(|group| {
+                let terms = crate::text::distinctive_terms(group, &notes, CLUSTER_LABEL_TERMS, &stop_words);
+                let label = if terms.is_empty() { group[0].title.clone() } else { terms.join(", ") };
+                NoteCluster { label, notes: group.iter().map(|note| NoteSummary::from(*note)).collect() }
+            })
+            .collect();
+        *CLUSTER_CACHE.lock()? = Some((k, fingerprint, clusters.clone()));
+        Ok(clusters)
+    }
+    
+    // Run several note operations in order with a single IPC call.
+    // With stop_on_error (the default), ops after the first failure are skipped.
+    #[tauri::command]
+    pub fn batch(ops: Vec<BatchOp>, stop_on_error: Option<bool>) -> Vec<BatchOpResult> {
+        let stop_on_error = stop_on_error.unwrap_or(true);
+        let mut failed = false;
+        ops.into_iter()
+            .map(|op| {
+                if failed && stop_on_error {
+                    return BatchOpResult::Skipped;
+                }
+                let result = match op {
+                    BatchOp::CreateNote => serde_json::to_value(create_note()).map_err(|e| e.to_string()),
+                    BatchOp::GetNote { id } => get_note(id)
+                        .and_then(|note| serde_json::to_value(note).map_err(|e| e.to_string())),
+                    BatchOp::SaveNote { id, title, content } => {
+                        save_note(id, title, content).map(|_| serde_json::Value::Null).map_err(|e| e.to_string())
+                    }
+                    BatchOp::DeleteNote { id } => delete_note(id).map(|_| serde_json::Value::Null),
+                };
+                match result {
+                    Ok(value) => BatchOpResult::Ok { value },
+                    Err(message) => {
+                        failed = true;
+                        BatchOpResult::Error { message }
+                    }
+                }
+            })
+            .collect()
+    }
+    
+    // Cosine similarity in [0, 1] between two notes' embeddings
+    #[tauri::command]
+    pub fn note_similarity(id_a: String, id_b: String) -> Result<f32, NoteError> {
+        let note_a = get_note(id_a.clone()).map_err(|_| NoteError::NotFound(id_a))?;
+        let note_b = get_note(id_b.clone()).map_err(|_| NoteError::NotFound(id_b))?;
+        let manager = EMBEDDING_MANAGER.lock()?;
+        Ok(EmbeddingManager::cosine_similarity(
+            &manager.embedding_for(&note_a),
+            &manager.embedding_for(&note_b),
+        ))
+    }
+    
+    // Embed arbitrary text with the same embedder used for the index (unit length)
+    #[tauri::command]
+    pub fn embed_text(text: String) -> Result<Vec<f32>, NoteError> {
+        if text.trim().is_empty() {
+            return Err(NoteError::InvalidInput("text must not be empty".to_string()));
+        }
+        Ok(EmbeddingManager::generate_simple_embedding(&text))
+    }
+    
+    // A note's vector as used for similarity: its stored vectors averaged when indexed,
+    // otherwise computed on the fly
+    #[tauri::command]
+    pub fn embed_note(id: String) -> Result<Vec<f32>, NoteError> {
+        let note = get_note(id.clone()).map_err(|_| NoteError::NotFound(id))?;
+        Ok(EMBEDDING_MANAGER.lock()?.embedding_for(&note))
+    }
+    
+    // Probe the embedding backend so the UI can warn before a reindex that would fail
+    #[tauri::command]
+    pub fn embedding_backend_status() -> crate::embeddings::BackendStatus {
+        crate::embeddings::probe_backend()
+    }
+    
+    // Length of the vectors returned by embed_text and embed_note
+    #[tauri::command]
+    pub fn embedding_dimension() -> usize {
+        crate::embeddings::EMBEDDING_DIMENSION
+    }
+    
+    // Create a new note
+    #[tauri::command]
+    pub fn create_note() -> Note {
+        let note = new_note(NEW_NOTE_TITLE.to_string(), "".to_string());
+        
+        // Save the note to disk
+        if let Err(e) = save_note_to_disk(&note) {
+            eprintln!("Error saving note: {}", e);
+        }
+        
+        index_note(&note);
+        
+        note
+    }
+    
+    // Helper function to build a note with a fresh id and timestamps
+    fn new_note(title: String, content: String) -> Note {
+        let now = now_rfc3339();
+        let id = unused_id(|| Uuid::new_v4().to_string(), |id| find_note_file(id).is_some());
+        Note {
+            id,
+            title,
+            content,
+            created_at: now.clone(),
+            updated_at: now,
+            ..Default::default()
+        }
+    }
+    
+    // The first id from `generate` that isn't `taken`. Never hand out an id that's already on
+    // disk, since saving would overwrite that note; a v4 UUID colliding means something is
+    // badly wrong, so make it loud.
+    fn unused_id(mut generate: impl FnMut() -> String, taken: impl Fn(&str) -> bool) -> String {
+        let mut id = generate();
+        while taken(&id) {
+            eprintln!("Note id collision: {} already exists, generating a new id", id);
+            id = generate();
+        }
+        id
+    }
+    
+    // Helper function to create, save and index a note in one step
+    pub(crate) fn insert_note(title: String, content: String) -> Result<Note, NoteError> {
+        let note = new_note(title, content);
+        save_note_to_disk(&note)?;
+        index_note(&note);
+        Ok(note)
+    }
+    
+    // Import a file or directory of .md, .txt, .json, .ndjson or .enex files
+    #[tauri::command]
+    pub fn import_path(path: String) -> Result<crate::import::ImportReport, NoteError> {
+        crate::import::import_path(Path::new(&path))
+    }
+    
+    // Set which HTML tags survive sanitization of imported HTML (None restores the defaults)
+    #[tauri::command]
+    pub fn set_import_allowed_tags(tags: Option<Vec<String>>) -> Result<(), String> {
+        let tags: Option<Vec<String>> = tags.map(|tags| tags.into_iter().map(|tag| tag.trim().to_lowercase()).filter(|tag| !tag.is_empty()).collect());
+        if let Some(tag) = tags.iter().flatten().find(|tag| crate::import::FORBIDDEN_TAGS.contains(&tag.as_str())) {
+            return Err(format!("<{}> can't be allowed in imported HTML", tag));
+        }
+        let mut config = crate::config::load_config();
+        config.import_allowed_tags = tags;
+        crate::config::save_config(&config)
+    }
+    
+    // Import one markdown file as a note per section at the given heading level
+    #[tauri::command]
+    pub fn import_markdown_split(path: String, heading_level: u8) -> Result<Vec<String>, NoteError> {
+        crate::import::import_markdown_split(Path::new(&path), heading_level)
+    }
+    
+    // Save a note
+    #[tauri::command]
+    pub fn save_note(id: String, title: String, content: String) -> Result<(), NoteError> {
+        let config = crate::config::load_config();
+        check_note_size(&content, &config)?;
+        let lock = note_lock(&id);
+        let _guard = lock.lock().unwrap_or_else(|e| e.into_inner());
+        save_note_locked(id, title, content, &config)
+    }
+    
+    // The rest of save_note, for callers that already hold the note's lock
+    fn save_note_locked(id: String, title: String, content: String, config: &crate::config::AppConfig) -> Result<(), NoteError> {
+        // Start from the existing note so its creation time and metadata are kept
+        let existing = get_note(id.clone()).ok();
+        if let Some(existing) = existing.as_ref().filter(|existing| existing.title != title || existing.content != content) {
+            if let Err(e) = crate::history::record(existing) {
+                eprintln!("Error saving note history: {}", e);
+            }
+        }
+        let mut note = existing.unwrap_or_else(|| Note {
+            id: id.clone(),
+            created_at: now_rfc3339(),
+            ..Default::default()
+        });
+        if note.content != content {
+            note.language = None;
+        }
+        note.title = auto_title(title, &content, config.auto_title);
+        note.content = content;
+        // Saving from the editor means the user has reviewed a recovered note
+        note.recovered = false;
+        note.cursor = note.cursor.map(|cursor| clamp_cursor(&note.content, cursor));
+        note.updated_at = now_rfc3339();
+        if note.language.is_none() && config.language_detection {
+            note.language = crate::language::detect(&note.content);
+        }
+        
+        // Save the note to disk
+        let result = save_note_to_disk(&note);
+        
+        if result.is_ok() {
+            index_note(&note);
+        }
+        
+        result
+    }
+    
+    // A note's content hash (see content_hash), for sync tools to cheaply tell whether a
+    // note changed
+    #[tauri::command]
+    pub fn note_content_hash(id: String) -> Result<String, NoteError> {
+        let note = get_note(id.clone()).map_err(|_| NoteError::NotFound(id))?;
+        Ok(content_hash(&note))
+    }
+    
+    // Notes written in `language` (an ISO 639-3 or 639-1 code or English name, see
+    // language::normalize_code). With language detection on, notes that haven't had their
+    // language detected yet are detected now and the result is saved, so each note is only
+    // detected once.
+    #[tauri::command]
+    pub fn list_notes_by_language(language: String) -> Result<Vec<NoteSummary>, NoteError> {
+        let code = crate::language::normalize_code(&language)
+            .ok_or_else(|| NoteError::InvalidInput(format!("Unknown language: {}", language)))?;
+        let detection = crate::config::load_config().language_detection;
+        let mut matching = Vec::new();
+        for note in list_notes(None) {
+            let Some(language) = effective_language(&note, detection) else {
+                continue;
+            };
+            if note.language.is_none() {
+                // Only the language is written, under the note's lock, so an edit saved
+                // since the note was listed isn't overwritten
+                let stored = update_note_locked(&note.id, |current| {
+                    if current.language.is_some() {
+                        return Ok(false);
+                    }
+                    current.language = if current.content == note.content {
+                        Some(language.clone())
+                    } else {
+                        crate::language::detect(&current.content)
+                    };
+                    Ok(current.language.is_some())
+                });
+                if let Err(e) = stored {
+                    eprintln!("Error saving detected language for note {}: {}", note.id, e);
+                }
+            }
+            if language == code {
+                matching.push(NoteSummary::from(&note));
+            }
+        }
+        Ok(matching)
+    }
+    
+    // The language list_notes_by_language files a note under: its stored one, else one
+    // detected from its content if `detection` is on
+    fn effective_language(note: &Note, detection: bool) -> Option<String> {
+        match &note.language {
+            Some(language) => Some(language.clone()),
+            None if detection && !note.content.trim().is_empty() => crate::language::detect(&note.content),
+            None => None,
+        }
+    }
+    
+    // A note's history as a timeline, oldest first and ending with the note as it is now.
+    // A note that has never been edited has just the one entry.
+    #[tauri::command]
+    pub fn note_changelog(id: String) -> Result<Vec<crate::history::ChangelogEntry>, NoteError> {
+        let note = get_note(id.clone()).map_err(|_| NoteError::NotFound(id.clone()))?;
+        Ok(crate::history::changelog(&crate::history::versions(&id), &note))
+    }
+    
+    // Add `text` to the end of a note without the caller sending the whole content, for quick
+    // capture and journaling. `separator` goes between the old content and the new text
+    // (a blank line by default) and `timestamp` starts the new text with the local date and
+    // time. Saved like save_note, so the size limit, history and indexing all apply. The note
+    // is read and saved under its lock, so appends arriving together all make it in.
+    #[tauri::command]
+    pub fn append_to_note(id: String, text: String, separator: Option<String>, timestamp: Option<bool>) -> Result<Note, NoteError> {
+        let config = crate::config::load_config();
+        let lock = note_lock(&id);
+        let _guard = lock.lock().unwrap_or_else(|e| e.into_inner());
+        let note = get_note(id.clone()).map_err(|_| NoteError::NotFound(id.clone()))?;
+        let mut content = note.content;
+        if !content.is_empty() {
+            content.push_str(separator.as_deref().unwrap_or("\n\n"));
+        }
+        if timestamp.unwrap_or(false) {
+            content.push_str(&chrono::Local::now().format("%Y-%m-%d %H:%M ").to_string());
+        }
+        content.push_str(&text);
+        check_note_size(&content, &config)?;
+        save_note_locked(id.clone(), note.title, content, &config)?;
+        get_note(id.clone()).map_err(|_| NoteError::NotFound(id))
+    }
+    
+    // Set (or clear with None) the autocomplete temperature used for one note
+    #[tauri::command]
+    pub fn set_note_completion_temperature(id: String, temperature: Option<f32>) -> Result<Note, NoteError> {
+        if let Some(temperature) = temperature {
+            if !(0.0..=2.0).contains(&temperature) {
+                return Err(NoteError::InvalidInput("temperature must be between 0.0 and 2.0".to_string()));
+            }
+        }
+        update_note_locked(&id, |note| {
+            note.completion_temperature = temperature;
+            Ok(true)
+        })
+    }
+    
+    // Remember where the editor cursor was, so the note reopens there. The offset is clamped
+    // to the content and moved back onto a char boundary; the clamped value is returned.
+    // This doesn't count as an edit, so updated_at and the index are left alone. The editor
+    // calls this while the user types, so it goes through the note's lock to avoid writing
+    // back content a concurrent save has just replaced.
+    #[tauri::command]
+    pub fn set_note_cursor(id: String, offset: usize) -> Result<usize, NoteError> {
+        let note = update_note_locked(&id, |note| {
+            let offset = clamp_cursor(&note.content, offset);
+            if note.cursor == Some(offset) {
+                return Ok(false);
+            }
+            note.cursor = Some(offset);
+            Ok(true)
+        })?;
+        Ok(note.cursor.unwrap_or_default())
+    }
+    
+    // Detect a note's language (ISO 639-3), caching the result on the note. Only the
+    // language is written, under the note's lock, so a concurrent save isn't overwritten.
+    #[tauri::command]
+    pub fn detect_language(id: String) -> Result<Option<String>, NoteError> {
+        let note = update_note_locked(&id, |note| {
+            if note.language.is_some() {
+                return Ok(false);
+            }
+            note.language = crate::language::detect(&format!("{} {}", note.title, note.content));
+            Ok(note.language.is_some())
+        })?;
+        Ok(note.language)
+    }
+    
+    // Longest metadata key accepted by set_note_metadata
+    const MAX_METADATA_KEY_LEN: usize = 64;
+    
+    // Set one metadata field on a note; an empty value removes the key
+    #[tauri::command]
+    pub fn set_note_metadata(id: String, key: String, value: String) -> Result<HashMap<String, String>, NoteError> {
+        let valid_key = !key.is_empty()
+            && key.len() <= MAX_METADATA_KEY_LEN
+            && key.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'));
+        if !valid_key {
+            return Err(NoteError::InvalidInput(format!(
+                "metadata keys must be 1-{} characters of letters, digits, '_', '-' or '.': {:?}",
+                MAX_METADATA_KEY_LEN, key
+            )));
+        }
+        let note = update_note_locked(&id, |note| {
+            if value.is_empty() {
+                note.metadata.remove(&key);
+            } else {
+                note.metadata.insert(key, value);
+            }
+            Ok(true)
+        })?;
+        Ok(note.metadata)
+    }
+    
+    // Get all metadata fields of a note
+    #[tauri::command]
+    pub fn get_note_metadata(id: String) -> Result<HashMap<String, String>, NoteError> {
+        get_note(id.clone())
+            .map(|note| note.metadata)
+            .map_err(|_| NoteError::NotFound(id))
+    }
+    
+    // Set or clear (`when: None`) a note's reminder. `interval_secs` makes it repeat. A time
+    // already in the past fires on the scheduler's next check.
+    #[tauri::command]
+    pub fn set_reminder(id: String, when: Option<String>, interval_secs: Option<u64>) -> Result<Note, NoteError> {
+        let at = when
+            .map(|when| crate::reminders::parse_time(&when).map_err(NoteError::InvalidInput))
+            .transpose()?;
+        if let Some(secs) = interval_secs {
+            crate::reminders::check_interval(secs).map_err(NoteError::InvalidInput)?;
+        }
+        update_note_locked(&id, |note| {
+            note.reminder_at = at.map(|at| at.to_rfc3339());
+            note.reminder_interval_secs = at.and(interval_secs);
+            Ok(true)
+        })
+    }
+    
+    // Remove a note's reminder, including any repeat
+    #[tauri::command]
+    pub fn clear_reminder(id: String) -> Result<Note, NoteError> {
+        set_reminder(id, None, None)
+    }
+    
+    // Fire and return every note whose reminder time has passed
+    #[tauri::command]
+    pub fn due_reminders() -> Vec<Note> {
+        crate::reminders::fire_due()
+    }
+    
+    // Notes whose reminder time has passed and that the scheduler hasn't fired yet. Unlike
+    // due_reminders this only looks; nothing is fired or cleared.
+    #[tauri::command]
+    pub fn list_due_reminders() -> Vec<NoteSummary> {
+        crate::reminders::list_due().iter().map(NoteSummary::from).collect()
+    }
+    
+    // Check that the notes folder exists and can be written to
+    #[tauri::command]
+    pub fn check_vault() -> Result<(), NoteError> {
+        check_vault_writable()
+    }
+    
+    // The problem the startup writability check found with the notes folder, if any
+    #[tauri::command]
+    pub fn startup_vault_error() -> Option<String> {
+        STARTUP_VAULT_ERROR.get().cloned()
+    }
+    
+    // Every setting currently in effect and where it came from, with secrets masked
+    #[tauri::command]
+    pub fn effective_config() -> crate::config::EffectiveConfig {
+        crate::config::effective_config()
+    }
+    
+    // Toggle deriving titles for untitled notes from their first line on save
+    #[tauri::command]
+    pub fn set_auto_title(enabled: bool) -> Result<(), String> {
+        let mut config = crate::config::load_config();
+        config.auto_title = enabled;
+        crate::config::save_config(&config)
+    }
+    
+    // Toggle automatic language detection on save
+    #[tauri::command]
+    pub fn set_language_detection(enabled: bool) -> Result<(), String> {
+        let mut config = crate::config::load_config();
+        config.language_detection = enabled;
+        crate::config::save_config(&config)
+    }
+    
+    // Build the embedding index from the notes on disk and report its state
+    #[tauri::command]
+    pub fn init_index() -> Result<IndexStatus, String> {
+        let notes = list_all_notes();
+        let mut manager = EMBEDDING_MANAGER.lock().map_err(|e| e.to_string())?;
+        manager.rebuild_index(&notes).map_err(|e| e.to_string())?;
+        Ok(manager.status())
+    }
+    
+    // Report whether the embedding index is ready and how many notes it holds
+    #[tauri::command]
+    pub fn index_status() -> Result<IndexStatus, String> {
+        let manager = EMBEDDING_MANAGER.lock().map_err(|e| e.to_string())?;
+        Ok(manager.status())
+    }
+    
+    // Report live/tombstoned slot counts so the UI can decide when to compact
+    #[tauri::command]
+    pub fn index_stats() -> Result<IndexStats, NoteError> {
+        let manager = EMBEDDING_MANAGER.lock()?;
+        Ok(manager.stats())
+    }
+    
+    // Rebuild the index from the live notes on disk, returning the tombstones reclaimed
+    #[tauri::command]
+    pub fn compact_index() -> Result<usize, NoteError> {
+        let notes = list_all_notes();
+        let mut manager = EMBEDDING_MANAGER.lock()?;
+        let reclaimed = manager.stats().tombstones;
+        manager.rebuild_index(&notes)?;
+        Ok(reclaimed)
+    }
+    
+    // Load the persisted index (rebuilding it if it's missing or corrupt), then reconcile it
+    // if notes were added or removed since it was saved
+    pub(crate) fn load_index() -> Result<ReconcileReport, NoteError> {
+        let notes = list_all_notes();
+        let config = crate::config::load_config();
+        let mut manager = EMBEDDING_MANAGER.lock()?;
+        manager.set_strategy(config.embedding_strategy);
+        manager.set_params(config.hnsw);
+        manager.load_or_rebuild(&index_path(), &notes)?;
+        if !manager.needs_reconcile(&notes) {
+            return Ok(ReconcileReport { added: 0, removed: 0, unchanged: notes.len() });
+        }
+        Ok(manager.reconcile(&notes)?)
+    }
+    
+    // Write the index to disk so the next startup doesn't have to re-embed everything
+    pub(crate) fn persist_index() -> Result<(), NoteError> {
+        let manager = EMBEDDING_MANAGER.lock()?;
+        Ok(manager.save_to_disk(&index_path())?)
+    }
+    
+    // Get how notes are split into vectors for semantic search
+    #[tauri::command]
+    pub fn get_embedding_strategy() -> Result<EmbeddingStrategy, NoteError> {
+        Ok(EMBEDDING_MANAGER.lock()?.strategy())
+    }
+    
+    // Change how notes are split into vectors, persist it, and rebuild the index to match
+    #[tauri::command]
+    pub fn set_embedding_strategy(strategy: EmbeddingStrategy) -> Result<IndexStatus, NoteError> {
+        strategy.validate().map_err(NoteError::InvalidInput)?;
+        let mut config = crate::config::load_config();
+        config.embedding_strategy = strategy;
+        crate::config::save_config(&config).map_err(NoteError::Io)?;
+        
+        let notes = list_all_notes();
+        let mut manager = EMBEDDING_MANAGER.lock()?;
+        manager.set_strategy(strategy);
+        manager.rebuild_index(&notes)?;
+        Ok(manager.status())
+    }
+    
+    // Get the HNSW graph parameters
+    #[tauri::command]
+    pub fn get_hnsw_params() -> Result<HnswParams, NoteError> {
+        Ok(EMBEDDING_MANAGER.lock()?.params())
+    }
+    
+    // Change the HNSW graph parameters, persist them, and rebuild the graph from the stored
+    // vectors (nothing is re-embedded)
+    #[tauri::command]
+    pub fn set_hnsw_params(params: HnswParams) -> Result<IndexStats, NoteError> {
+        params.validate().map_err(NoteError::InvalidInput)?;
+        let mut config = crate::config::load_config();
+        config.hnsw = params;
+        crate::config::save_config(&config).map_err(NoteError::Io)?;
+        
+        let mut manager = EMBEDDING_MANAGER.lock()?;
+        manager.set_params(params);
+        manager.rebuild_graph(0)?;
+        Ok(manager.stats())
+    }
+    
+    // Check that the loaded and saved embedding indexes were built by the current embedding
+    // model and dimension. A stale file never loads (see EmbeddingManager::load_or_rebuild),
+    // so searches only see current vectors; the file is replaced at the next persist.
+    #[tauri::command]
+    pub fn verify_index() -> Result<crate::embeddings::IndexVerification, NoteError> {
+        Ok(EMBEDDING_MANAGER.lock()?.verify(&index_path()))
+    }
+    
+    // Ids of notes on disk that have no embedding, and so can't turn up in semantic search
+    // until they're reindexed
+    #[tauri::command]
+    pub fn list_unindexed_notes() -> Result<Vec<String>, NoteError> {
+        let notes = list_all_notes();
+        let manager = EMBEDDING_MANAGER.lock()?;
+        Ok(manager.unindexed(&notes))
+    }
+    
+    // Embed one note again, adding it to the index if it was missing
+    #[tauri::command]
+    pub fn reindex_note(id: String) -> Result<IndexStatus, NoteError> {
+        let note = get_note(id.clone()).map_err(|_| NoteError::NotFound(id))?;
+        let mut manager = EMBEDDING_MANAGER.lock()?;
+        manager.update_note(&note)?;
+        Ok(manager.status())
+    }
+    
+    // Pause between notes embedded by precompute_embeddings, so an idle-time fill doesn't
+    // compete with the editor or hold the index lock for long stretches
+    const PRECOMPUTE_DELAY: std::time::Duration = std::time::Duration::from_millis(20);
+    
+    // Notes embedded between "embedding-progress" events
+    const PRECOMPUTE_PROGRESS_EVERY: usize = 25;
+    
+    // Set while precompute_embeddings is running, until its task has actually finished
+    static PRECOMPUTE_RUNNING: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+    // Set by cancel_precompute_embeddings to ask the running task to stop
+    static PRECOMPUTE_CANCELLED: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+    
+    // Clears PRECOMPUTE_RUNNING when the task ends, even if it panics, so a later run can start
+    struct PrecomputeRunning;
+    
+    impl Drop for PrecomputeRunning {
+        fn drop(&mut self) {
+            PRECOMPUTE_RUNNING.store(false, std::sync::atomic::Ordering::SeqCst);
+        }
+    }
+    
+    // Progress of precompute_embeddings, emitted as "embedding-progress" events
+    #[derive(Serialize, Clone)]
+    pub struct EmbeddingProgress {
+        pub embedded: usize,
+        // Notes that couldn't be embedded; they stay stale for the next run
+        pub failed: usize,
+        pub total: usize,
+        pub done: bool,
+        pub cancelled: bool,
+    }
+    
+    // Embed every note that's missing from the index or changed since it was indexed, in the
+    // background and a note at a time, so the first semantic search afterwards doesn't have
+    // to. Emits "embedding-progress" events and saves the index at the end. Returns false if
+    // a run is already in progress; cancel_precompute_embeddings stops one.
+    #[tauri::command]
+    pub fn precompute_embeddings(window: tauri::Window) -> bool {
+        use std::sync::atomic::Ordering;
+        if PRECOMPUTE_RUNNING.swap(true, Ordering::SeqCst) {
+            return false;
+        }
+        PRECOMPUTE_CANCELLED.store(false, Ordering::SeqCst);
+        std::thread::spawn(move || {
+            let _running = PrecomputeRunning;
+            let stale: Vec<Note> = match EMBEDDING_MANAGER.lock() {
+                Ok(manager) => list_all_notes().into_iter().filter(|note| manager.is_stale(note)).collect(),
+                Err(_) => vec![],
+            };
+            let total = stale.len();
+            let mut progress = EmbeddingProgress { embedded: 0, failed: 0, total, done: false, cancelled: false };
+            for note in &stale {
+                if PRECOMPUTE_CANCELLED.load(Ordering::SeqCst) {
+                    progress.cancelled = true;
+                    break;
+                }
+                match EMBEDDING_MANAGER.lock() {
+                    Ok(mut manager) => match manager.update_note(note) {
+                        Ok(()) => progress.embedded += 1,
+                        Err(e) => {
+                            eprintln!("Error embedding note {}: {}", note.id, e);
+                            progress.failed += 1;
+                        }
+                    },
+                    Err(e) => {
+                        eprintln!("Error embedding note {}: {}", note.id, e);
+                        break;
+                    }
+                }
+                if (progress.embedded + progress.failed).is_multiple_of(PRECOMPUTE_PROGRESS_EVERY) {
+                    let _ = window.emit("embedding-progress", progress.clone());
+                }
+                std::thread::sleep(PRECOMPUTE_DELAY);
+            }
+            if progress.embedded > 0 {
+                if let Err(e) = persist_index() {
+                    eprintln!("Error saving embedding index: {}", e);
+                }
+            }
+            progress.done = true;
+            let _ = window.emit("embedding-progress", progress);
+        });
+        true
+    }
+    
+    // Stop a running precompute_embeddings; notes embedded so far are kept. Returns false
+    // if none was running.
+    #[tauri::command]
+    pub fn cancel_precompute_embeddings() -> bool {
+        use std::sync::atomic::Ordering;
+        let running = PRECOMPUTE_RUNNING.load(Ordering::SeqCst);
+        if running {
+            PRECOMPUTE_CANCELLED.store(true, Ordering::SeqCst);
+        }
+        running
+    }
+    
+    // Sync the index with the notes on disk without a full rebuild
     #[tauri::command]
-    pub fn search_notes(query: String) -> Vec<Note> {
-        if query.is_empty() {
-            return list_notes();
+    pub fn reconcile_index() -> Result<ReconcileReport, NoteError> {
+        let notes = list_all_notes();
+        let mut manager = EMBEDDING_MANAGER.lock()?;
+        Ok(manager.reconcile(&notes)?)
+    }
+    
+    // Re-embed only notes that were added or edited since they were indexed, and drop
+    // deleted ones
+    #[tauri::command]
+    pub fn refresh_index() -> Result<RefreshReport, NoteError> {
+        let notes = list_all_notes();
+        let mut manager = EMBEDDING_MANAGER.lock()?;
+        Ok(manager.refresh(&notes)?)
+    }
+    
+    // Notes read between rebuild_text_index progress events
+    const TEXT_INDEX_PROGRESS_EVERY: usize = 100;
+    
+    // Progress of rebuild_text_index, emitted as "text-index-progress" events
+    #[derive(Serialize, Clone)]
+    pub struct TextIndexProgress {
+        pub indexed: usize,
+        pub total: usize,
+    }
+    
+    // Rebuild the full-text index from the files on disk, e.g. after they were edited outside
+    // the app. Runs on a worker thread and reports progress to the window. The new index is
+    // built on the side and swapped in (see text::rebuild), so saves carry on meanwhile.
+    #[tauri::command(async)]
+    pub fn rebuild_text_index(window: tauri::Window) -> Result<crate::text::TextIndexStats, NoteError> {
+        let _timer = crate::timing::CommandTimer::start("rebuild_text_index");
+        let paths: Vec<PathBuf> = read_dir(notes_dir()?)?.flatten().map(|entry| entry.path()).collect();
+        let total = paths.len();
+        let stats = crate::text::rebuild(|index| {
+            for (i, path) in paths.iter().enumerate() {
+                if let Ok(note) = read_note_file(path) {
+                    index.update(&note);
+                }
+                if (i + 1) % TEXT_INDEX_PROGRESS_EVERY == 0 {
+                    let _ = window.emit("text-index-progress", TextIndexProgress { indexed: i + 1, total });
+                }
+            }
+        });
+        let _ = window.emit("text-index-progress", TextIndexProgress { indexed: total, total });
+        Ok(stats)
+    }
+    
+    // Set (or reset to the defaults with None) the words the full-text index ignores, and
+    // rebuild the index with them
+    #[tauri::command]
+    pub fn set_stop_words(words: Option<Vec<String>>) -> Result<crate::text::TextIndexStats, NoteError> {
+        let mut config = crate::config::load_config();
+        config.stop_words = words;
+        crate::config::save_config(&config).map_err(NoteError::Io)?;
+        let stats = crate::text::rebuild(|index| list_all_notes().iter().for_each(|note| index.update(note)));
+        // Cluster labels leave out stop words, so the cached ones may be stale
+        *CLUSTER_CACHE.lock()? = None;
+        Ok(stats)
+    }
+    
+    // Helper function to keep the vector and full-text indexes in sync with a saved note
+    fn index_note(note: &Note) {
+        if let Ok(mut index) = crate::text::TEXT_INDEX.lock() {
+            index.update(note);
+        }
+        match EMBEDDING_MANAGER.lock() {
+            Ok(mut manager) => {
+                if let Err(e) = manager.update_note(note) {
+                    eprintln!("Error indexing note: {}", e);
+                }
+            }
+            Err(e) => eprintln!("Failed to acquire lock on embedding manager: {}", e),
+        }
+    }
+    
+    // Helper function to save a note to disk
+    pub(crate) fn save_note_to_disk(note: &Note) -> Result<(), NoteError> {
+        let format = crate::config::load_config().note_format;
+        let dir = notes_dir()?;
+        let mut path = dir.clone();
+        path.push(format!("{}.{}", note.id, format.extension()));
+        let serialized = format.serialize(note).map_err(NoteError::Io)?;
+        File::create(&path)
+            .and_then(|mut f| f.write_all(serialized.as_bytes()))
+            .map_err(|e| match e.kind() {
+                std::io::ErrorKind::PermissionDenied | std::io::ErrorKind::ReadOnlyFilesystem => {
+                    vault_not_writable(&dir, &e)
+                }
+                _ => NoteError::from(e),
+            })?;
+        // Only once the new version is on disk; invalidating earlier would let a read in
+        // between put the old version back in the cache
+        if let Ok(mut cache) = NOTE_CACHE.lock() {
+            cache.invalidate(&note.id);
         }
+        crate::reminders::track(note);
+        crate::cache::track_title(note);
         
-        // Perform basic text search
-        let query = query.to_lowercase();
-        let all_notes = list_notes();
-        all_notes
+        // Drop any copy in another format so the note migrates on its next save
+        Ok(crate::format::remove_other_copies(&dir, &note.id, format)?)
+    }
+    
+    // Set (or reset to the default with None) the largest note content save_note accepts
+    #[tauri::command]
+    pub fn set_max_note_bytes(limit: Option<usize>) -> Result<(), NoteError> {
+        if limit == Some(0) {
+            return Err(NoteError::InvalidInput("the note size limit must be above zero".to_string()));
+        }
+        let mut config = crate::config::load_config();
+        config.max_note_bytes = limit;
+        crate::config::save_config(&config).map_err(NoteError::Io)
+    }
+    
+    // Set the format used when writing notes
+    #[tauri::command]
+    pub fn set_note_format(format: NoteFormat) -> Result<(), String> {
+        let mut config = crate::config::load_config();
+        config.note_format = format;
+        crate::config::save_config(&config)
+    }
+    
+    // Whether a note has no content worth keeping, whatever its title
+    fn has_blank_content(note: &Note) -> bool {
+        note.content.trim().is_empty()
+    }
+    
+    // Abandoned notes: still titled with the placeholder and with no content.
+    // Notes the user gave a title are never considered empty.
+    fn is_abandoned(note: &Note) -> bool {
+        note.title == NEW_NOTE_TITLE && has_blank_content(note)
+    }
+    
+    // List abandoned "New Note" entries so the UI can offer a cleanup
+    #[tauri::command]
+    pub fn list_empty_notes() -> Vec<NoteSummary> {
+        list_notes(None).iter().filter(|note| is_abandoned(note)).map(NoteSummary::from).collect()
+    }
+    
+    // Delete abandoned "New Note" entries, returning how many were (or with `dry_run`,
+    // would be) deleted
+    #[tauri::command]
+    pub fn delete_empty_notes(dry_run: bool) -> usize {
+        let empty = list_empty_notes();
+        if dry_run {
+            return empty.len();
+        }
+        empty
             .into_iter()
-            .filter(|note| {
-                note.title.to_lowercase().contains(&query) || 
-                note.content.to_lowercase().contains(&query)
+            .filter(|summary| match delete_note(summary.id.clone()) {
+                Ok(()) => true,
+                Err(e) => {
+                    eprintln!("Error deleting empty note {}: {}", summary.id, e);
+                    false
+                }
             })
-            .collect()
+            .count()
     }
     
-    // Semantic search (simplified version - falls back to text search for now)
+    // Widest ±window notes_on_this_day accepts
+    const MAX_ON_THIS_DAY_WINDOW: u32 = 30;
+    
+    // Notes created on today's date (local time) in earlier years, or within `window_days`
+    // of it, newest year first. In non-leap years, notes from 29 February count as the 28th.
     #[tauri::command]
-    pub fn semantic_search(query: String, _distance_cutoff: Option<f32>) -> Vec<Note> {
-        // For now, just use the basic text search
-        // In the future, this could be enhanced with embeddings or other semantic techniques
-        search_notes(query)
+    pub fn notes_on_this_day(window_days: Option<u32>) -> Vec<NoteSummary> {
+        let window = window_days.unwrap_or(0).min(MAX_ON_THIS_DAY_WINDOW) as i64;
+        let today = chrono::Local::now().date_naive();
+        let mut matches: Vec<(chrono::DateTime<chrono::FixedOffset>, NoteSummary)> = list_notes(None)
+            .iter()
+            .filter_map(|note| {
+                let created = chrono::DateTime::parse_from_rfc3339(&note.created_at).ok()?;
+                let date = created.with_timezone(&chrono::Local).date_naive();
+                on_this_day(date, today, window).then(|| (created, NoteSummary::from(note)))
+            })
+            .collect();
+        matches.sort_by(|(a, _), (b, _)| b.cmp(a));
+        matches.into_iter().map(|(_, summary)| summary).collect()
+    }
+    
+    // Whether a note created on `created` comes up in notes_on_this_day on `today`: it has an
+    // anniversary within `window` days of today, at least a year after it was written
+    fn on_this_day(created: chrono::NaiveDate, today: chrono::NaiveDate, window: i64) -> bool {
+        use chrono::Datelike;
+        if created.year() >= today.year() {
+            return false;
+        }
+        // Put the anniversary in the years around today so windows can span New Year, but
+        // never in the year the note was written: last December isn't a year ago in January
+        (today.year() - 1..=today.year() + 1).filter(|&year| year > created.year()).any(|year| {
+            let anniversary = chrono::NaiveDate::from_ymd_opt(year, created.month(), created.day())
+                .or_else(|| chrono::NaiveDate::from_ymd_opt(year, created.month(), created.day() - 1));
+            anniversary.is_some_and(|day| (day - today).num_days().abs() <= window)
+        })
     }
     
-    // List all notes
+    // Notes with blank content, whatever their title, oldest first. Trashed notes are left out.
     #[tauri::command]
-    pub fn list_notes() -> Vec<Note> {
-        let dir = notes_dir();
-        let mut notes = vec![];
-        if let Ok(entries) = read_dir(dir) {
-            for entry in entries.flatten() {
-                if let Ok(mut file) = File::open(entry.path()) {
-                    let mut contents = String::new();
-                    if file.read_to_string(&mut contents).is_ok() {
-                        if let Ok(note) = serde_json::from_str::<Note>(&contents) {
-                            notes.push(note);
-                        }
-                    }
-                }
+    pub fn empty_notes() -> Vec<Note> {
+        list_notes_sorted(Some(SortOrder::OldestFirst))
+            .into_iter()
+            .filter(|note| has_blank_content(note) && note.state != NoteState::Trashed)
+            .collect()
+    }
+    
+    // Groups of notes with exactly the same title and content, byte for byte, e.g.
+    // copy-paste dupes from an import or sync conflict. Notes that only differ in whitespace
+    // or line endings aren't grouped (their content_hash matches). Each group lists ids
+    // oldest first, so the first is the likely original. Trashed notes are left out.
+    #[tauri::command]
+    pub fn exact_duplicates() -> Vec<Vec<String>> {
+        let mut groups: HashMap<String, Vec<String>> = HashMap::new();
+        for note in list_notes_sorted(Some(SortOrder::OldestFirst)) {
+            if note.state == NoteState::Trashed {
+                continue;
             }
+            groups.entry(exact_content_hash(&note)).or_default().push(note.id);
         }
-        notes.sort_by(|a, b| b.id.cmp(&a.id)); // newest first
-        notes
+        let mut duplicates: Vec<Vec<String>> = groups.into_values().filter(|ids| ids.len() > 1).collect();
+        duplicates.sort();
+        duplicates
     }
     
-    // Create a new note
+    // Trash empty notes that haven't been touched for `days` days, returning their ids.
+    // Run at startup when auto_trash_empty_after_days is set.
+    pub(crate) fn trash_stale_empty_notes(days: u32) -> Vec<String> {
+        // Out-of-range ages can't come from the setter, but an edited config file could hold one
+        let Some(cutoff) = chrono::Duration::try_days(days as i64).and_then(|age| chrono::Utc::now().checked_sub_signed(age)) else {
+            return vec![];
+        };
+        empty_notes()
+            .into_iter()
+            .filter(|note| {
+                chrono::DateTime::parse_from_rfc3339(&note.updated_at).is_ok_and(|updated| updated < cutoff)
+            })
+            .filter_map(|note| match set_note_state(note.id.clone(), NoteState::Trashed) {
+                Ok(note) => Some(note.id),
+                Err(e) => {
+                    eprintln!("Error trashing empty note {}: {}", note.id, e);
+                    None
+                }
+            })
+            .collect()
+    }
+    
+    // Oldest age set_auto_trash_empty_after_days accepts, about ten years
+    const MAX_AUTO_TRASH_DAYS: u32 = 3650;
+    
+    // Set (or turn off with None) how old an empty note gets before it's trashed on startup
     #[tauri::command]
-    pub fn create_note() -> Note {
+    pub fn set_auto_trash_empty_after_days(days: Option<u32>) -> Result<(), String> {
+        if days.is_some_and(|days| days > MAX_AUTO_TRASH_DAYS) {
+            return Err(format!("days must be at most {}", MAX_AUTO_TRASH_DAYS));
+        }
+        let mut config = crate::config::load_config();
+        config.auto_trash_empty_after_days = days;
+        crate::config::save_config(&config)
+    }
+    
+    // Delete a note. The file goes first, under the note's lock so a save can't write it
+    // back, and the note is only dropped from the indexes once it's really gone.
+    #[tauri::command]
+    pub fn delete_note(id: String) -> Result<(), String> {
+        let lock = note_lock(&id);
+        let _guard = lock.lock().unwrap_or_else(|e| e.into_inner());
+        let path = find_note_file(&id).ok_or_else(|| format!("Note not found: {}", id))?;
+        remove_file(&path).map_err(|e| e.to_string())?;
+        
+        // Create a temporary note object with the ID to remove from the vector index
         let note = Note {
-            id: Uuid::new_v4().to_string(),
-            title: "New Note".to_string(),
-            content: "".to_string(),
+            id: id.clone(),
+            ..Default::default()
         };
         
-        // Save the note to disk
-        if let Err(e) = save_note_to_disk(&note) {
-            eprintln!("Error saving note: {}", e);
+        if let Ok(mut manager) = EMBEDDING_MANAGER.lock() {
+            // NotFound just means the note was never indexed
+            let _ = manager.remove_note(&note);
         }
         
-        // Vector indexing removed
+        if let Ok(mut cache) = NOTE_CACHE.lock() {
+            cache.invalidate(&id);
+        }
+        if let Ok(mut index) = crate::text::TEXT_INDEX.lock() {
+            index.remove(&id);
+        }
+        crate::reminders::untrack(&id);
+        crate::cache::forget_title(&id);
         
-        note
+        if let Err(e) = crate::order::forget(&id) {
+            eprintln!("Error updating note order: {}", e);
+        }
+        if let Err(e) = crate::history::forget(&id) {
+            eprintln!("Error removing note history: {}", e);
+        }
+        Ok(())
     }
     
-    // Save a note
+    // Recent command durations, oldest first, for diagnosing slow operations
     #[tauri::command]
-    pub fn save_note(id: String, title: String, content: String) -> Result<(), String> {
-        let note = Note { id: id.clone(), title, content };
+    pub fn get_command_timings() -> Vec<crate::timing::CommandTiming> {
+        crate::timing::recent()
+    }
+    
+    #[cfg(test)]
+    mod tests {
+        use super::*;
         
-        // Save the note to disk
-        let result = save_note_to_disk(&note);
+        const ENGLISH: &str = "This is a note about the meeting we had yesterday afternoon. We agreed that the new release should wait until the documentation has been reviewed and the remaining bugs are fixed.";
         
-        // Vector indexing removed
+        fn titles_by_length(min_words: Option<usize>, max_words: Option<usize>) -> Vec<String> {
+            let notes = [("Three", "one two three"), ("Empty", ""), ("One", "one"), ("Also three", "a\nb  c"), ("Five", "a b c d e")]
+                .map(|(title, content)| Note { title: title.to_string(), content: content.to_string(), ..Default::default() });
+            notes_by_length(notes.into_iter(), min_words, max_words).into_iter().map(|summary| summary.title).collect()
+        }
         
-        result
-    }
-    
-    // Helper function to save a note to disk
-    fn save_note_to_disk(note: &Note) -> Result<(), String> {
-        let dir = notes_dir();
-        let mut path = dir;
-        path.push(format!("{}.json", note.id));
-        File::create(&path)
-            .and_then(|mut f| f.write_all(serde_json::to_string(&note).unwrap().as_bytes()))
-            .map_err(|e| e.to_string())
-    }
-    
-    // Delete a note
-    #[tauri::command]
-    pub fn delete_note(id: String) -> Result<(), String> {
-        // Create a temporary note object with the ID to remove from the vector index
-        let _note = Note {
-            id: id.clone(),
-            title: String::new(),
-            content: String::new(),
-        };
+        #[test]
+        fn length_bounds_are_inclusive() {
+            assert_eq!(titles_by_length(Some(1), Some(3)), ["One", "Also three", "Three"]);
+            assert_eq!(titles_by_length(Some(3), Some(3)), ["Also three", "Three"]);
+            assert!(titles_by_length(Some(4), Some(2)).is_empty());
+        }
+        
+        #[test]
+        fn length_bounds_can_be_left_open() {
+            assert_eq!(titles_by_length(None, Some(1)), ["Empty", "One"]);
+            assert_eq!(titles_by_length(Some(4), None), ["Five"]);
+            assert_eq!(titles_by_length(None, None).len(), 5);
+        }
+        
+        #[test]
+        fn trashed_notes_have_no_length() {
+            let note = |title: &str, state: NoteState| Note { title: title.to_string(), content: "a b".to_string(), state, ..Default::default() };
+            let notes = [note("Kept", NoteState::Active), note("Binned", NoteState::Trashed), note("Shelved", NoteState::Archived)];
+            let titles: Vec<String> = notes_by_length(notes.into_iter(), None, None).into_iter().map(|summary| summary.title).collect();
+            assert_eq!(titles, ["Kept", "Shelved"]);
+        }
+        
+        #[test]
+        fn counts_agree_with_full_results() {
+            let note = |id: &str, title: &str, content: &str, state: NoteState| Note {
+                id: id.to_string(),
+                title: title.to_string(),
+                content: content.to_string(),
+                state,
+                updated_at: format!("2024-01-0{}T00:00:00Z", id),
+                ..Default::default()
+            };
+            let notes = [
+                note("1", "Groceries", "milk and bread", NoteState::Active),
+                note("2", "Meeting notes", "bread budget", NoteState::Active),
+                note("3", "Old groceries", "milk", NoteState::Trashed),
+                note("4", "Garden", "grow more", NoteState::Archived),
+            ];
+            let count = |query: &str, mode: SearchMode| {
+                notes.iter().filter(|note| counts_as_match(note, query, &query.trim().to_lowercase(), mode)).count()
+            };
+            for query in ["milk", "bread", "BREAD", "missing", ""] {
+                let results = notes.iter().filter(|note| note.state != NoteState::Trashed && (query.is_empty() || note_matches(note, query))).count();
+                assert_eq!(count(query, SearchMode::Text), results, "text search for {:?}", query);
+                assert_eq!(count(query, SearchMode::Semantic), results, "semantic search for {:?}", query);
+            }
+            let titles = crate::cache::TitleIndex::from_notes(&notes).recent_first();
+            for query in ["gro", "g", "notes", "grc", "zzz"] {
+                assert_eq!(count(query, SearchMode::Fuzzy), rank_titles(titles.clone(), query).len(), "fuzzy search for {:?}", query);
+            }
+        }
+        
+        #[test]
+        fn only_untitled_blank_notes_are_abandoned() {
+            let note = |title: &str, content: &str| Note { title: title.to_string(), content: content.to_string(), ..Default::default() };
+            assert!(is_abandoned(&note(NEW_NOTE_TITLE, "")));
+            assert!(is_abandoned(&note(NEW_NOTE_TITLE, " \n\t")));
+            assert!(!is_abandoned(&note(NEW_NOTE_TITLE, "milk")));
+            // A title the user chose keeps the note, even with nothing in it
+            assert!(!is_abandoned(&note("Groceries", "")));
+            assert!(!is_abandoned(&note("new note", "")));
+            assert!(!is_abandoned(&note("Groceries", "milk")));
+            assert!(has_blank_content(&note("Groceries", "  ")));
+        }
+        
+        #[test]
+        fn stored_language_wins_over_detection() {
+            let note = Note { language: Some("fra".to_string()), content: ENGLISH.to_string(), ..Default::default() };
+            assert_eq!(effective_language(&note, true).as_deref(), Some("fra"));
+            assert_eq!(effective_language(&note, false).as_deref(), Some("fra"));
+        }
+        
+        #[test]
+        fn detects_only_when_detection_is_on() {
+            let note = Note { content: ENGLISH.to_string(), ..Default::default() };
+            assert_eq!(effective_language(&note, true).as_deref(), Some("eng"));
+            assert_eq!(effective_language(&note, false), None);
+            let blank = Note { content: "  \n".to_string(), ..Default::default() };
+            assert_eq!(effective_language(&blank, true), None);
+        }
+        
+        #[test]
+        fn title_matches_rank_prefix_word_substring_then_fuzzy() {
+            assert_eq!(title_match_rank("meeting notes", "mee"), Some((0, 0)));
+            assert_eq!(title_match_rank("weekly meeting", "mee"), Some((1, 0)));
+            assert_eq!(title_match_rank("summeeting", "mee"), Some((2, 3)));
+            assert_eq!(title_match_rank("my exam", "mem"), Some((3, 4)));
+            assert_eq!(title_match_rank("notes", "xyz"), None);
+        }
+        
+        #[test]
+        fn index_lines_link_only_the_listed_note() {
+            let note = Note {
+                title: "Groceries".to_string(),
+                content: "Ask [[Sam]] about\n\nthe #shopping list".to_string(),
+                ..Default::default()
+            };
+            let line = index_line(&note);
+            assert_eq!(line, "- [[Groceries]] — Ask Sam about the list");
+            assert_eq!(crate::markdown::wiki_links(&line), ["Groceries"]);
+            assert!(crate::markdown::tags(&line).is_empty());
+        }
+        
+        #[test]
+        fn index_snippets_are_cut_with_an_ellipsis() {
+            let note = Note { title: "Long".to_string(), content: "word ".repeat(40), ..Default::default() };
+            let line = index_line(&note);
+            assert!(line.ends_with('…'));
+            assert_eq!(line.chars().count(), "- [[Long]] — ".chars().count() + INDEX_SNIPPET_CHARS + 1);
+        }
+        
+        fn vault_to_index() -> Vec<Note> {
+            let note = |id: &str, content: &str, state: NoteState| Note {
+                id: id.to_string(),
+                title: id.to_string(),
+                content: content.to_string(),
+                state,
+                ..Default::default()
+            };
+            vec![
+                note("Standup", "#work daily", NoteState::Active),
+                note("Holiday", "beach", NoteState::Pinned),
+                note("Old plan", "#work scrapped", NoteState::Trashed),
+                note("Review", "#Work/q3 and #work", NoteState::Archived),
+            ]
+        }
+        
+        fn indexed_titles(content: &str) -> Vec<String> {
+            content.lines().flat_map(crate::markdown::wiki_links).collect()
+        }
+        
+        #[test]
+        fn tagged_index_lists_only_tagged_notes_outside_the_trash() {
+            let notes = vault_to_index();
+            let (existing, content) = plan_index_note(&notes, Some("work"));
+            assert!(existing.is_none());
+            assert_eq!(indexed_titles(&content), ["Standup", "Review"]);
+        }
+        
+        #[test]
+        fn unfiltered_index_lists_every_note_but_index_notes() {
+            let mut notes = vault_to_index();
+            let mut tag_index = Note { id: "tag-index".to_string(), title: "Index: #work".to_string(), ..Default::default() };
+            tag_index.metadata.insert(INDEX_NOTE_KEY.to_string(), index_marker(Some("work")));
+            notes.push(tag_index);
+            let (existing, content) = plan_index_note(&notes, None);
+            // The #work index isn't the index of every note
+            assert!(existing.is_none());
+            assert_eq!(indexed_titles(&content), ["Standup", "Holiday", "Review"]);
+        }
+        
+        #[test]
+        fn rebuilding_reuses_the_existing_index_note() {
+            let mut notes = vault_to_index();
+            let (_, content) = plan_index_note(&notes, Some("work"));
+            let mut index = Note { id: "index".to_string(), title: "Index: #work".to_string(), content, ..Default::default() };
+            index.metadata.insert(INDEX_NOTE_KEY.to_string(), index_marker(Some("work")));
+            notes.insert(0, index);
+            let (existing, content) = plan_index_note(&notes, Some("work"));
+            assert_eq!(existing.map(|note| note.id.as_str()), Some("index"));
+            assert_eq!(indexed_titles(&content), ["Standup", "Review"]);
+        }
+        
+        #[test]
+        fn colliding_ids_are_regenerated() {
+            let mut ids = ["a", "a", "b", "c"].into_iter().map(str::to_string);
+            let id = unused_id(|| ids.next().unwrap(), |id| id == "a");
+            assert_eq!(id, "b");
+            assert_eq!(ids.next().as_deref(), Some("c"));
+        }
+        
+        #[test]
+        fn groups_drop_empty_clusters_and_put_the_largest_first() {
+            let notes: Vec<Note> = (0..4).map(|i| Note { id: i.to_string(), ..Default::default() }).collect();
+            let groups = group_by_cluster(&notes, &[2, 0, 2, 2], 3);
+            let ids: Vec<Vec<&str>> = groups.iter().map(|group| group.iter().map(|note| note.id.as_str()).collect()).collect();
+            assert_eq!(ids, [vec!["0", "2", "3"], vec!["1"]]);
+        }
+        
+        #[test]
+        fn streamed_notes_leave_out_the_trash_like_list_notes() {
+            let dir = tempfile::tempdir().unwrap();
+            for (id, state) in [("kept", NoteState::Active), ("pinned", NoteState::Pinned), ("binned", NoteState::Trashed)] {
+                let note = Note { id: id.to_string(), state, ..Default::default() };
+                std::fs::write(dir.path().join(format!("{}.json", id)), NoteFormat::Json.serialize(&note).unwrap()).unwrap();
+            }
+            let streamed = |state: Option<NoteState>| {
+                let mut ids = Vec::new();
+                let sent = stream_notes(dir.path(), 1, state, |batch| {
+                    ids.extend(batch.notes.into_iter().map(|note| note.id));
+                    Ok(())
+                })
+                .unwrap();
+                assert_eq!(sent, ids.len());
+                ids.sort();
+                ids
+            };
+            assert_eq!(streamed(None), ["kept", "pinned"]);
+            assert_eq!(streamed(Some(NoteState::Trashed)), ["binned"]);
+        }
         
-        // Vector indexing removed
+        fn date(s: &str) -> chrono::NaiveDate {
+            s.parse().unwrap()
+        }
+        
+        #[test]
+        fn last_december_is_not_on_this_day_in_january() {
+            let today = date("2025-01-02");
+            assert!(!on_this_day(date("2024-12-30"), today, 7));
+            assert!(!on_this_day(date("2025-01-01"), today, 7));
+            // A year earlier it is, its anniversary falling last December
+            assert!(on_this_day(date("2023-12-30"), today, 7));
+            assert!(!on_this_day(date("2023-12-30"), today, 2));
+            assert!(on_this_day(date("2024-01-02"), today, 0));
+        }
+        
+        #[test]
+        fn leap_day_notes_come_up_on_the_28th() {
+            assert!(on_this_day(date("2024-02-29"), date("2025-02-28"), 0));
+            assert!(!on_this_day(date("2024-02-29"), date("2025-03-01"), 0));
+        }
+        
+        fn migrated(dir: &Path, dry_run: bool) -> (usize, usize, usize, usize) {
+            let report = migrate_dir(dir, dry_run).unwrap();
+            assert!(report.failed.is_empty(), "{:?}", report.failed);
+            (report.scanned, report.migrated, report.up_to_date, report.skipped_recovered)
+        }
+        
+        #[test]
+        fn legacy_note_files_are_rewritten_to_the_current_schema() {
+            let dir = tempfile::tempdir().unwrap();
+            let legacy = dir.path().join("old.json");
+            std::fs::write(&legacy, r#"{"id": "old", "title": "Old", "content": "From before", "pinned": true}"#).unwrap();
+            let current = Note {
+                id: "new".to_string(),
+                title: "New".to_string(),
+                created_at: "2024-01-01T00:00:00+00:00".to_string(),
+                updated_at: "2024-01-02T00:00:00+00:00".to_string(),
+                ..Default::default()
+            };
+            std::fs::write(dir.path().join("new.json"), NoteFormat::Json.serialize(&current).unwrap()).unwrap();
+            std::fs::write(dir.path().join("notes.txt"), "not a note").unwrap();
+            
+            // A dry run reports the legacy file without touching it
+            let before = std::fs::read_to_string(&legacy).unwrap();
+            assert_eq!(migrated(dir.path(), true), (2, 1, 1, 0));
+            assert_eq!(std::fs::read_to_string(&legacy).unwrap(), before);
+            
+            assert_eq!(migrated(dir.path(), false), (2, 1, 1, 0));
+            let rewritten: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&legacy).unwrap()).unwrap();
+            assert_eq!(rewritten["content"], "From before");
+            // The legacy flag is folded into the stored state
+            assert!(rewritten.get("pinned").is_none());
+            assert!(!rewritten["created_at"].as_str().unwrap().is_empty());
+            assert_eq!(read_note_file(&legacy).unwrap().state, NoteState::Pinned);
+            assert!(!dir.path().join("old.json.tmp").exists());
+            
+            // Once migrated there's nothing left to do
+            assert_eq!(migrated(dir.path(), false), (2, 0, 2, 0));
+        }
+        
+        #[test]
+        fn recovered_notes_are_left_for_review() {
+            let dir = tempfile::tempdir().unwrap();
+            std::fs::write(dir.path().join("cut.json"), r#"{"id": "cut", "title": "Cut off", "content": "Half a sent"#).unwrap();
+            let before = std::fs::read_to_string(dir.path().join("cut.json")).unwrap();
+            assert_eq!(migrated(dir.path(), false), (1, 0, 0, 1));
+            assert_eq!(std::fs::read_to_string(dir.path().join("cut.json")).unwrap(), before);
+        }
+        
+        #[test]
+        fn cancel_stops_a_running_search() {
+            let mut searches = SearchRegistry::default();
+            assert!(searches.start("q"));
+            assert!(searches.is_active("q"));
+            assert!(searches.cancel("q".to_string()));
+            assert!(!searches.is_active("q"));
+        }
         
-        // Delete the note file
-        let dir = notes_dir();
-        let mut path = dir;
-        path.push(format!("{}.json", id));
-        remove_file(&path).map_err(|e| e.to_string())
+        #[test]
+        fn cancel_before_start_stops_the_search_once() {
+            let mut searches = SearchRegistry::default();
+            assert!(!searches.cancel("q".to_string()));
+            assert!(!searches.start("q"));
+            assert!(!searches.is_active("q"));
+            // The early cancel is used up; the id can be searched again
+            assert!(searches.start("q"));
+        }
+        
+        #[test]
+        fn early_cancels_are_bounded() {
+            let mut searches = SearchRegistry::default();
+            for i in 0..=MAX_EARLY_CANCELS {
+                searches.cancel(i.to_string());
+            }
+            assert_eq!(searches.cancelled_early.len(), MAX_EARLY_CANCELS);
+            assert!(searches.start("0"));
+            assert!(!searches.start("1"));
+        }
     }
 }
 
 // Create a new module for completion commands
 mod completion {
-    use crate::completion_client::GeminiClient;
+    use crate::completion_client::{ConnectivityReport, GeminiClient, ModelInfo};
+    use crate::completion_client::gemini_client::{autocomplete_instruction, with_suffix_hint, ASK_INSTRUCTION, CHAT_INSTRUCTION, GROUNDING_INSTRUCTION, REWRITE_INSTRUCTION, STRUCTURED_INSTRUCTION, TAG_INSTRUCTION};
     use crate::completion_client::common::RequestMessage;
-    use std::sync::Mutex;
+    use crate::completion_client::provider::{collect_stream, CompletionProvider};
+    use crate::completion_client::cache::{CompletionCache, CompletionCacheSettings, CompletionKey};
+    use crate::completion_client::postprocess::{postprocess_completion, tokens_for_words, truncate_to_words, PostProcessOptions};
+    use crate::config::{self, GenerationDefaults, GENERATION_FEATURES};
+    use crate::NoteError;
+    use std::collections::HashMap;
+    use std::sync::{Mutex, MutexGuard};
     use std::sync::Arc;
     use log::{info, error};
     use once_cell::sync::Lazy;
+    use std::time::{Duration, Instant};
 
     // Define the environment variable name for the Gemini API key
-    const GEMINI_API_KEY_ENV: &str = "GEMINI_API_KEY";
+    pub(crate) const GEMINI_API_KEY_ENV: &str = "GEMINI_API_KEY";
 
     // Create a global Gemini client with an API key
     static CLIENT: Lazy<Arc<Mutex<GeminiClient>>> = Lazy::new(|| {
@@ -165,58 +2848,466 @@ mod completion {
         Arc::new(Mutex::new(GeminiClient::new(api_key)))
     });
 
-    // Get a text completion
+    // How long a fetched model list is reused before hitting the API again
+    const MODELS_CACHE_TTL: Duration = Duration::from_secs(300);
+
+    // When the model list was fetched, and the list
+    type FetchedModels = (Instant, Vec<ModelInfo>);
+    
+    static MODELS_CACHE: Lazy<Mutex<Option<FetchedModels>>> = Lazy::new(|| Mutex::new(None));
+
+    // Recent autocomplete results, so repeated prompts don't re-hit the API
+    static COMPLETION_CACHE: Lazy<Mutex<CompletionCache>> =
+        Lazy::new(|| Mutex::new(CompletionCache::new(&config::load_config().completion_cache)));
+
+    // Parameters of the most recent get_completion call, for retry_last_completion
+    #[derive(Clone)]
+    struct LastCompletion {
+        prompt: String,
+        max_tokens: Option<i32>,
+        temperature: Option<f32>,
+        postprocess: Option<PostProcessOptions>,
+        max_words: Option<usize>,
+        note_id: Option<String>,
+    }
+
+    static LAST_COMPLETION: Lazy<Mutex<Option<LastCompletion>>> = Lazy::new(|| Mutex::new(None));
+
+    // Latest history of each chat session, including the last reply, keyed by session id.
+    // Kept in memory only; save_chat_as_note is how a conversation outlives the app.
+    static CHAT_SESSIONS: Lazy<Mutex<HashMap<String, Vec<RequestMessage>>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+    // Lock the global client, recovering from poisoning. A panic while the lock was held
+    // can't leave the client half-updated (requests only read it), so it's safe to reuse
+    // rather than letting one transient panic disable completions until restart.
+    fn lock_client() -> MutexGuard<'static, GeminiClient> {
+        CLIENT.lock().unwrap_or_else(|poisoned| {
+            error!("GeminiClient mutex was poisoned by a panic; recovering");
+            CLIENT.clear_poison();
+            poisoned.into_inner()
+        })
+    }
+    
+    // A copy of the global client to make requests with. Requests can sleep through
+    // rate-limit retries, so they must not hold the lock while they run; the copy shares
+    // the HTTP connection pool.
+    fn client() -> GeminiClient {
+        lock_client().clone()
+    }
+
+    // Get a text completion. `max_words`, when given, sizes the token budget and is
+    // enforced as a hard cap on the returned text. `note_id` lets the note's frozen
+    // context (if enabled) be sent along with the prompt.
     #[tauri::command]
-    pub fn get_completion(prompt: String, max_tokens: i32, temperature: f32) -> Result<String, String> {
+    pub fn get_completion(prompt: String, max_tokens: Option<i32>, temperature: Option<f32>, postprocess: Option<PostProcessOptions>, max_words: Option<usize>, note_id: Option<String>) -> Result<String, NoteError> {
         // Print directly to stdout for debugging
         println!("[FRONTEND_DEBUG] Tauri command: get_completion called with prompt: '{}'", prompt);
-        println!("[FRONTEND_DEBUG] max_tokens: {}, temperature: {}", max_tokens, temperature);
-        info!("Tauri command: get_completion called with prompt: '{}', max_tokens: {}, temperature: {}", prompt, max_tokens, temperature);
+        println!("[FRONTEND_DEBUG] max_tokens: {:?}, temperature: {:?}, max_words: {:?}", max_tokens, temperature, max_words);
+        info!("Tauri command: get_completion called with prompt: '{}', max_tokens: {:?}, temperature: {:?}", prompt, max_tokens, temperature);
+        
+        if let Ok(mut last) = LAST_COMPLETION.lock() {
+            *last = Some(LastCompletion {
+                prompt: prompt.clone(),
+                max_tokens,
+                temperature,
+                postprocess,
+                max_words,
+                note_id: note_id.clone(),
+            });
+        }
+        
+        let max_tokens = max_tokens.or(max_words.map(tokens_for_words));
+        
+        let app_config = config::load_config();
+        if !app_config.autocomplete.allows(&prompt) {
+            println!("[FRONTEND_DEBUG] Prompt is shorter than the minimum prefix, skipping completion");
+            return Ok(String::new());
+        }
+        let note = note_id.and_then(|id| crate::commands::get_note(id).ok());
+        let AutocompleteRequest { instruction, context: note_context, prompt: request_prompt, generation_config } =
+            autocomplete_request(&prompt, None, note.as_ref(), &app_config, max_tokens, temperature);
+        
+        // Serve repeated prompts from the cache when the settings allow it
+        let cache_settings = app_config.completion_cache;
+        let cache_ttl = generation_config.temperature.and_then(|t| cache_settings.ttl_for(t));
+        let cache_key = cache_ttl.map(|_| {
+            CompletionKey::new(
+                &app_config.model(),
+                &GeminiClient::full_instruction(&instruction),
+                &request_prompt,
+                note_context.as_deref(),
+                &generation_config,
+            )
+        });
+        if let Some(key) = &cache_key {
+            let cached = COMPLETION_CACHE.lock().ok().and_then(|mut cache| {
+                cache.configure(&cache_settings);
+                cache.get(key)
+            });
+            if let Some(text) = cached {
+                println!("[FRONTEND_DEBUG] Using cached completion: '{}'", text);
+                return Ok(finish_completion(&prompt, &text, postprocess, max_words));
+            }
+        }
+        
+        // Get the client
+        println!("[FRONTEND_DEBUG] Acquiring lock on GeminiClient");
+        let client = client();
+        println!("[FRONTEND_DEBUG] Successfully acquired lock on GeminiClient");
+        
+        // Check if the API key is configured
+        if client.api_key().is_empty() {
+            let error_msg = "Gemini API key not configured. Set the GEMINI_API_KEY environment variable.";
+            println!("[FRONTEND_DEBUG] {}", error_msg);
+            error!("API key is empty! Please set the GEMINI_API_KEY environment variable.");
+            return Err(NoteError::Completion(error_msg.to_string()));
+        }
+        
+        println!("[FRONTEND_DEBUG] API key is present, calling get_completion");
+        info!("API key is configured, making request to Gemini API");
+        
+        // Make the request and log the result
+        println!("[FRONTEND_DEBUG] Calling client.get_completion");
+        let result = client
+            .complete(&instruction, request_prompt, note_context.as_deref(), generation_config)
+            .and_then(collect_stream);
+        
+        match result {
+            Ok(text) => {
+                println!("[FRONTEND_DEBUG] Successfully got completion: '{}'", text);
+                info!("Successfully got completion: '{}'", text);
+                if let (Some(key), Some(ttl), Ok(mut cache)) = (cache_key, cache_ttl, COMPLETION_CACHE.lock()) {
+                    cache.insert(key, text.clone(), ttl);
+                }
+                Ok(finish_completion(&prompt, &text, postprocess, max_words))
+            },
+            Err(e) => {
+                println!("[FRONTEND_DEBUG] Error getting completion: {}", e);
+                error!("Error getting completion: {}", e);
+                // Keeps a rate limit distinct so the UI can tell the user when to retry
+                Err(e.into())
+            },
+        }
+    }
+    
+    // The parts of an autocomplete request that depend on the note and settings
+    struct AutocompleteRequest {
+        instruction: String,
+        context: Option<String>,
+        prompt: String,
+        generation_config: crate::completion_client::GenerationConfig,
+    }
+    
+    // Work out what autocomplete sends for `prompt`, typed in `note`: the instruction
+    // (language-matched if enabled, with a hint of the text after the cursor if given), the
+    // note's frozen context, the prompt wrapped in the note's template or the global one, and
+    // the generation settings, where the caller's values win over the note's temperature and
+    // then the autocomplete defaults. get_completion, autocomplete_at and the preview and
+    // debug commands all build their requests here, so what they show is what gets sent.
+    // Post-processing still compares the completion against the user's own text, not the
+    // templated prompt.
+    fn autocomplete_request(
+        prompt: &str,
+        suffix: Option<&str>,
+        note: Option<&crate::Note>,
+        app_config: &config::AppConfig,
+        max_tokens: Option<i32>,
+        temperature: Option<f32>,
+    ) -> AutocompleteRequest {
+        let context = match note {
+            Some(note) if app_config.frozen_context_enabled => crate::context::completion_context(
+                note,
+                app_config.strip_code_from_context,
+                &app_config.context_format,
+                &app_config.context_budget,
+            ),
+            _ => None,
+        };
+        
+        let template = note
+            .and_then(|note| note.metadata.get(config::PROMPT_TEMPLATE_KEY))
+            .filter(|template| config::validate_prompt_template(template).is_ok())
+            .or(app_config.prompt_template.as_ref());
+        let request_prompt = match template {
+            Some(template) => config::apply_prompt_template(template, prompt),
+            None => prompt.to_string(),
+        };
+        
+        // Use the note's cached language, else detect it from the prompt. Low-confidence
+        // detection gives None and the default instruction.
+        let language = if app_config.language_matched_prompt {
+            note.and_then(|note| note.language.clone()).or_else(|| crate::language::detect(prompt))
+        } else {
+            None
+        };
+        let instruction = autocomplete_instruction(language.as_deref());
+        let instruction = match suffix {
+            Some(suffix) => with_suffix_hint(&instruction, suffix),
+            None => instruction,
+        };
+        
+        let temperature = temperature.or(note.and_then(|note| note.completion_temperature));
+        let generation_config = config::generation_defaults("autocomplete").to_generation_config(max_tokens, temperature);
+        AutocompleteRequest { instruction, context, prompt: request_prompt, generation_config }
+    }
+    
+    // How much text after the cursor autocomplete sends as a hint
+    const SUFFIX_HINT_CHARS: usize = 120;
+    
+    // Split `text` at byte `cursor` into the prompt (the `window_chars` characters before the
+    // cursor, or all of them) and the hint of what follows, if there's anything but whitespace
+    fn cursor_window(text: &str, cursor: usize, window_chars: Option<usize>) -> (&str, Option<&str>) {
+        let (before, after) = text.split_at(cursor);
+        let window_start = window_chars
+            .filter(|&chars| chars > 0)
+            .and_then(|chars| before.char_indices().rev().nth(chars - 1))
+            .map_or(0, |(i, _)| i);
+        let suffix_end = after.char_indices().nth(SUFFIX_HINT_CHARS).map_or(after.len(), |(i, _)| i);
+        let suffix = Some(after[..suffix_end].trim_end()).filter(|s| !s.trim().is_empty());
+        (&before[window_start..], suffix)
+    }
+    
+    // Rough characters per token, for estimates where the exact count doesn't matter
+    const CHARS_PER_TOKEN: usize = 4;
+    
+    // The text autocomplete would send, as shown by preview_completion_context
+    #[derive(serde::Serialize)]
+    pub struct CompletionContextPreview {
+        // System instruction, including the global prompt prefix and suffix and the hint of
+        // the text after the cursor
+        pub instruction: String,
+        // The note's frozen context as sent, after code stripping and the context budget
+        pub context: Option<String>,
+        // Text before the cursor, windowed and templated
+        pub prompt: String,
+        // Everything above in the order it's sent
+        pub text: String,
+        pub estimated_tokens: usize,
+    }
+    
+    // Show exactly what autocomplete_at would send for a cursor at byte `cursor_offset` in a
+    // note, with every context setting applied, so a poor suggestion can be traced to its
+    // input. `window_chars` limits the prompt to that many characters before the cursor, as
+    // in autocomplete_at; without it the whole text before the cursor is used. Nothing is sent.
+    #[tauri::command]
+    pub fn preview_completion_context(id: String, cursor_offset: usize, window_chars: Option<usize>) -> Result<CompletionContextPreview, NoteError> {
+        let note = crate::commands::get_note(id.clone()).map_err(|_| NoteError::NotFound(id))?;
+        let (prefix, suffix) = cursor_window(&note.content, crate::clamp_cursor(&note.content, cursor_offset), window_chars);
+        let request = autocomplete_request(prefix, suffix, Some(&note), &config::load_config(), None, None);
+        let body = GeminiClient::build_request(&request.instruction, request.prompt.clone(), request.context.as_deref(), request.generation_config);
+        let parts: Vec<String> = body
+            .contents
+            .into_iter()
+            .flat_map(|content| content.parts.unwrap_or_default())
+            .filter_map(|part| part.text)
+            .collect();
+        let text = parts.join("\n\n");
+        Ok(CompletionContextPreview {
+            instruction: parts.first().cloned().unwrap_or_default(),
+            context: request.context,
+            prompt: request.prompt,
+            estimated_tokens: text.chars().count().div_ceil(CHARS_PER_TOKEN),
+            text,
+        })
+    }
+    
+    // Show the request get_completion would send for `prompt`, typed in the note `note_id` if
+    // given (URL, headers and body), without sending it. The API key is redacted.
+    #[tauri::command]
+    pub fn debug_build_request(prompt: String, max_tokens: Option<i32>, temperature: Option<f32>, note_id: Option<String>) -> Result<serde_json::Value, String> {
+        let note = note_id.and_then(|id| crate::commands::get_note(id).ok());
+        let AutocompleteRequest { instruction, context, prompt, generation_config } =
+            autocomplete_request(&prompt, None, note.as_ref(), &config::load_config(), max_tokens, temperature);
+        let request = GeminiClient::build_request(&instruction, prompt, context.as_deref(), generation_config);
+        Ok(serde_json::json!({
+            "url": client().generate_url(),
+            "headers": { "x-goog-api-key": "<redacted>" },
+            "body": serde_json::to_value(&request).map_err(|e| e.to_string())?,
+        }))
+    }
+    
+    // Ask for a JSON answer following `schema` (an OpenAPI-style schema as accepted by
+    // Gemini's responseSchema) and return it parsed. The answer is checked against the
+    // schema unless `validate` is false, failing with SchemaViolation. With `repair`, an
+    // answer that fails the check is sent back once with the problem for the model to fix.
+    #[tauri::command]
+    pub fn get_structured_completion(prompt: String, schema: serde_json::Value, validate: Option<bool>, repair: Option<bool>) -> Result<serde_json::Value, NoteError> {
+        info!("Tauri command: get_structured_completion called with prompt: '{}'", prompt);
+        let generation_config = || {
+            let mut generation_config = config::generation_defaults("structured").to_generation_config(None, None);
+            generation_config.response_mime_type = Some("application/json".to_string());
+            generation_config.response_schema = Some(schema.clone());
+            generation_config
+        };
         
-        // Get the client
-        println!("[FRONTEND_DEBUG] Acquiring lock on GeminiClient");
-        let client_result = CLIENT.lock();
-        if let Err(e) = client_result {
-            let error_msg = format!("Failed to acquire lock on GeminiClient: {}", e);
-            println!("[FRONTEND_DEBUG] {}", error_msg);
-            return Err(error_msg);
+        let client = client();
+        if client.api_key().is_empty() {
+            return Err(NoteError::Completion("Gemini API key not configured. Set the GEMINI_API_KEY environment variable.".to_string()));
         }
+        let validate = validate.unwrap_or(true);
+        let request = |prompt: String| -> Result<serde_json::Value, NoteError> {
+            let raw = client
+                .generate(STRUCTURED_INSTRUCTION, prompt, None, generation_config())
+                .map_err(NoteError::from)?;
+            parse_structured(&raw, &schema, validate)
+        };
+        match request(prompt.clone()) {
+            Err(NoteError::SchemaViolation { path, message }) if repair.unwrap_or(false) => {
+                info!("Structured output failed the schema at {} ({}), asking for a repair", path, message);
+                request(format!(
+                    "{}\n\nYour previous answer was invalid: at {}, {}. Answer again with JSON that matches the schema.",
+                    prompt, path, message
+                ))
+            }
+            result => result,
+        }
+    }
+    
+    // Parse a structured answer, checking it against `schema` when `validate` is set.
+    // Unparseable JSON counts as a violation of the whole document.
+    fn parse_structured(raw: &str, schema: &serde_json::Value, validate: bool) -> Result<serde_json::Value, NoteError> {
+        let value: serde_json::Value = serde_json::from_str(raw.trim()).map_err(|e| NoteError::SchemaViolation {
+            path: "$".to_string(),
+            message: format!("malformed JSON ({}): {}", e, raw),
+        })?;
+        if validate {
+            check_schema(&value, schema, "$").map_err(|(path, message)| NoteError::SchemaViolation { path, message })?;
+        }
+        Ok(value)
+    }
+    
+    // Check the parts of `schema` Gemini enforces loosely: types, required properties,
+    // enums, and array items. A failure gives the path of the bad value and what's wrong.
+    fn check_schema(value: &serde_json::Value, schema: &serde_json::Value, path: &str) -> Result<(), (String, String)> {
+        use serde_json::Value;
+        let expected = schema.get("type").and_then(Value::as_str).map(str::to_lowercase);
+        let matches = match expected.as_deref() {
+            Some("object") => value.is_object(),
+            Some("array") => value.is_array(),
+            Some("string") => value.is_string(),
+            Some("integer") => value.is_i64() || value.is_u64(),
+            Some("number") => value.is_number(),
+            Some("boolean") => value.is_boolean(),
+            _ => true,
+        };
+        if !matches {
+            return Err((path.to_string(), format!("should be of type {}", expected.unwrap_or_default())));
+        }
+        if let Some(allowed) = schema.get("enum").and_then(Value::as_array) {
+            if !allowed.contains(value) {
+                return Err((path.to_string(), "is not one of the allowed values".to_string()));
+            }
+        }
+        if let Some(object) = value.as_object() {
+            for key in schema.get("required").and_then(Value::as_array).into_iter().flatten().filter_map(Value::as_str) {
+                if !object.contains_key(key) {
+                    return Err((path.to_string(), format!("is missing required property '{}'", key)));
+                }
+            }
+            if let Some(properties) = schema.get("properties").and_then(Value::as_object) {
+                for (key, property_schema) in properties {
+                    if let Some(property) = object.get(key) {
+                        check_schema(property, property_schema, &format!("{}.{}", path, key))?;
+                    }
+                }
+            }
+        }
+        if let (Some(items), Some(item_schema)) = (value.as_array(), schema.get("items")) {
+            for (i, item) in items.iter().enumerate() {
+                check_schema(item, item_schema, &format!("{}[{}]", path, i))?;
+            }
+        }
+        Ok(())
+    }
+    
+    // Autocomplete at a cursor inside a larger text, sending only the `window_chars`
+    // characters before the cursor (plus a short hint of what follows) instead of the whole
+    // note. With `note_id`, the note's template, language, frozen context and temperature
+    // apply as they do for get_completion.
+    #[tauri::command]
+    pub fn autocomplete_at(full_text: String, cursor_byte: usize, window_chars: usize, max_tokens: Option<i32>, temperature: Option<f32>, note_id: Option<String>) -> Result<String, NoteError> {
+        if !full_text.is_char_boundary(cursor_byte) {
+            return Err(NoteError::InvalidInput(format!("cursor_byte {} is not on a character boundary", cursor_byte)));
+        }
+        if window_chars == 0 {
+            return Err(NoteError::InvalidInput("window_chars must be positive".to_string()));
+        }
+        let (prefix, suffix) = cursor_window(&full_text, cursor_byte, Some(window_chars));
+        let app_config = config::load_config();
+        if !app_config.autocomplete.allows(prefix) {
+            return Ok(String::new());
+        }
+        info!("Tauri command: autocomplete_at called with cursor_byte: {}, window_chars: {}", cursor_byte, window_chars);
         
-        let client = client_result.unwrap();
-        println!("[FRONTEND_DEBUG] Successfully acquired lock on GeminiClient");
-        
-        // Check if the API key is configured
+        let note = note_id.and_then(|id| crate::commands::get_note(id).ok());
+        let AutocompleteRequest { instruction, context, prompt, generation_config } =
+            autocomplete_request(prefix, suffix, note.as_ref(), &app_config, max_tokens, temperature);
+        let client = client();
         if client.api_key().is_empty() {
-            let error_msg = "Gemini API key not configured. Set the GEMINI_API_KEY environment variable.";
-            println!("[FRONTEND_DEBUG] {}", error_msg);
-            error!("API key is empty! Please set the GEMINI_API_KEY environment variable.");
-            return Err(error_msg.to_string());
+            return Err(NoteError::Completion("Gemini API key not configured. Set the GEMINI_API_KEY environment variable.".to_string()));
         }
+        let raw = client
+            .complete(&instruction, prompt, context.as_deref(), generation_config)
+            .and_then(collect_stream)
+            .map_err(NoteError::from)?;
         
-        println!("[FRONTEND_DEBUG] API key is present, calling get_completion");
-        info!("API key is configured, making request to Gemini API");
-        
-        // Make the request and log the result
-        println!("[FRONTEND_DEBUG] Calling client.get_completion");
-        let result = client.get_completion(prompt, max_tokens, temperature);
-        
-        match &result {
-            Ok(text) => {
-                println!("[FRONTEND_DEBUG] Successfully got completion: '{}'", text);
-                info!("Successfully got completion: '{}'", text);
-                Ok(text.clone())
-            },
-            Err(e) => {
-                println!("[FRONTEND_DEBUG] Error getting completion: {}", e);
-                error!("Error getting completion: {}", e);
-                Err(e.to_string())
-            },
+        let mut completion = postprocess_completion(prefix, &raw, &PostProcessOptions::default());
+        // Per the autocomplete instruction, text continuing a word that isn't finished yet
+        // starts with a space so the two don't run together
+        let mid_word = prefix.ends_with(|c: char| !c.is_whitespace() && !c.is_ascii_punctuation());
+        if mid_word && completion.starts_with(char::is_alphanumeric) {
+            completion.insert(0, ' ');
+        }
+        Ok(completion)
+    }
+    
+    // Re-send the most recent get_completion request, e.g. after a network error
+    #[tauri::command]
+    pub fn retry_last_completion() -> Result<String, NoteError> {
+        let last = LAST_COMPLETION
+            .lock()?
+            .clone()
+            .ok_or_else(|| NoteError::InvalidInput("No completion request to retry".to_string()))?;
+        println!("[FRONTEND_DEBUG] Retrying last completion for prompt: '{}'", last.prompt);
+        get_completion(last.prompt, last.max_tokens, last.temperature, last.postprocess, last.max_words, last.note_id)
+    }
+    
+    // Clean up a raw completion and apply the word cap
+    fn finish_completion(prompt: &str, raw: &str, postprocess: Option<PostProcessOptions>, max_words: Option<usize>) -> String {
+        let mut cleaned = postprocess_completion(prompt, raw, &postprocess.unwrap_or_default());
+        if let Some(max_words) = max_words {
+            cleaned = truncate_to_words(&cleaned, max_words);
+        }
+        println!("[FRONTEND_DEBUG] Post-processed completion: '{}'", cleaned);
+        cleaned
+    }
+    
+    // Drop all cached completions, returning how many were removed
+    #[tauri::command]
+    pub fn clear_completion_cache() -> Result<usize, String> {
+        let mut cache = COMPLETION_CACHE.lock().map_err(|e| e.to_string())?;
+        Ok(cache.clear())
+    }
+    
+    // Update the completion cache size, TTL and caching policy
+    #[tauri::command]
+    pub fn set_completion_cache_settings(settings: CompletionCacheSettings) -> Result<(), String> {
+        settings.validate()?;
+        let mut app_config = config::load_config();
+        app_config.completion_cache = settings;
+        config::save_config(&app_config)?;
+        if let Ok(mut cache) = COMPLETION_CACHE.lock() {
+            cache.configure(&settings);
         }
+        Ok(())
     }
     
+    // Most follow-up requests auto_continue makes for one reply, to bound its cost
+    const MAX_CONTINUATIONS: usize = 4;
+    
     // Get a chat completion (simplified to use get_completion)
     #[tauri::command]
-    pub fn chat_completion(messages: Vec<RequestMessage>) -> Result<String, String> {
+    pub fn chat_completion(messages: Vec<RequestMessage>, max_tokens: Option<i32>, temperature: Option<f32>, auto_continue: Option<bool>, session_id: Option<String>) -> Result<String, NoteError> {
         println!("[FRONTEND_DEBUG] Tauri command: chat_completion called with {} messages", messages.len());
         info!("Tauri command: chat_completion called with {} messages", messages.len());
         
@@ -227,21 +3318,14 @@ mod completion {
         
         // Get the client
         println!("[FRONTEND_DEBUG] Acquiring lock on GeminiClient for chat_completion");
-        let client_result = CLIENT.lock();
-        if let Err(e) = client_result {
-            let error_msg = format!("Failed to acquire lock on GeminiClient: {}", e);
-            println!("[FRONTEND_DEBUG] {}", error_msg);
-            return Err(error_msg);
-        }
-        
-        let client = client_result.unwrap();
+        let client = client();
         println!("[FRONTEND_DEBUG] Successfully acquired lock on GeminiClient");
         
         // Check if the API key is configured
         if client.api_key().is_empty() {
             let error_msg = "Gemini API key not configured. Set the GEMINI_API_KEY environment variable.";
             println!("[FRONTEND_DEBUG] {}", error_msg);
-            return Err(error_msg.to_string());
+            return Err(NoteError::Completion(error_msg.to_string()));
         }
         
         // Extract the last user message to use as prompt
@@ -255,27 +3339,403 @@ mod completion {
         if prompt.is_empty() {
             let error_msg = "No user message found in the conversation";
             println!("[FRONTEND_DEBUG] {}", error_msg);
-            return Err(error_msg.to_string());
+            return Err(NoteError::InvalidInput(error_msg.to_string()));
         }
         
         println!("[FRONTEND_DEBUG] Extracted prompt: '{}'", prompt);
         
-        // Call the get_completion method instead
-        println!("[FRONTEND_DEBUG] Calling get_completion with prompt");
-        let result = client.get_completion(prompt, 30, 0.7);
+        println!("[FRONTEND_DEBUG] Generating chat reply");
+        let generation_config = config::generation_defaults("chat").to_generation_config(max_tokens, temperature);
+        // With auto_continue, replies cut off by the token limit are continued until done
+        let result = if auto_continue.unwrap_or(false) {
+            client.generate_continued(CHAT_INSTRUCTION, prompt, None, generation_config, MAX_CONTINUATIONS)
+        } else {
+            client.generate(CHAT_INSTRUCTION, prompt, None, generation_config)
+        };
         
-        match &result {
+        match result {
             Ok(text) => {
                 println!("[FRONTEND_DEBUG] chat_completion success: '{}'", text);
-                Ok(text.clone())
+                // The frontend sends the whole conversation each time, so replace the history
+                if let (Some(session_id), Ok(mut sessions)) = (session_id, CHAT_SESSIONS.lock()) {
+                    let mut history = messages;
+                    history.push(RequestMessage { role: "assistant".to_string(), content: text.clone() });
+                    sessions.insert(session_id, history);
+                }
+                Ok(text)
             },
             Err(e) => {
                 println!("[FRONTEND_DEBUG] chat_completion error: {}", e);
-                Err(e.to_string())
+                Err(e.into())
             },
         }
     }
 
+    // Save a chat session as a note with one labelled section per message. Without a title,
+    // the first user message provides one.
+    #[tauri::command]
+    pub fn save_chat_as_note(session_id: String, title: Option<String>) -> Result<crate::Note, NoteError> {
+        let history = CHAT_SESSIONS
+            .lock()?
+            .get(&session_id)
+            .cloned()
+            .ok_or_else(|| NoteError::InvalidInput(format!("Unknown chat session: {}", session_id)))?;
+        if history.is_empty() {
+            return Err(NoteError::InvalidInput(format!("Chat session {} has no messages", session_id)));
+        }
+        
+        let content = history
+            .iter()
+            .map(|message| {
+                let role = match message.role.as_str() {
+                    "user" => "You",
+                    "assistant" | "model" => "Assistant",
+                    "system" => "System",
+                    other => other,
+                };
+                format!("**{}:**\n\n{}", role, message.content.trim())
+            })
+            .collect::<Vec<_>>()
+            .join("\n\n");
+        let title = title
+            .map(|title| title.trim().to_string())
+            .filter(|title| !title.is_empty())
+            .or_else(|| {
+                history
+                    .iter()
+                    .find(|message| message.role == "user")
+                    .and_then(|message| crate::title_from_content(&message.content))
+            })
+            .unwrap_or_else(|| "Chat".to_string());
+        crate::commands::insert_note(title, content)
+    }
+    
+    // Notes per cluster shown to the model when naming it, and how much of each
+    const TAG_SAMPLE_NOTES: usize = 8;
+    const TAG_SAMPLE_CHARS: usize = 300;
+    
+    // A group of similar notes with a suggested tag
+    #[derive(serde::Serialize)]
+    pub struct TagCluster {
+        pub tag: String,
+        pub note_ids: Vec<String>,
+    }
+    
+    // Cluster the vault into `k` groups by embedding and ask the model to name each one.
+    // Only a small sample of each cluster is sent to the model.
+    #[tauri::command]
+    pub fn suggest_tag_clusters(k: usize) -> Result<Vec<TagCluster>, NoteError> {
+        if k == 0 {
+            return Err(NoteError::InvalidInput("k must be positive".to_string()));
+        }
+        let notes = crate::commands::list_notes(None);
+        let groups = crate::commands::cluster_notes(&notes, k)?;
+        
+        let generation_config = || config::generation_defaults("summarize").to_generation_config(Some(16), Some(0.2));
+        let client = client();
+        if client.api_key().is_empty() {
+            return Err(NoteError::Completion("Gemini API key not configured. Set the GEMINI_API_KEY environment variable.".to_string()));
+        }
+        let mut clusters = Vec::new();
+        for group in groups {
+            let sample: Vec<String> = group
+                .iter()
+                .take(TAG_SAMPLE_NOTES)
+                .map(|note| {
+                    let excerpt: String = note.content.chars().take(TAG_SAMPLE_CHARS).collect();
+                    format!("- {}: {}", note.title, excerpt.replace('\n', " "))
+                })
+                .collect();
+            let raw = client
+                .generate(TAG_INSTRUCTION, sample.join("\n"), None, generation_config())
+                .map_err(NoteError::from)?;
+            let tag = raw
+                .trim()
+                .trim_matches(|c: char| c == '#' || c == '"' || c == '\'' || c == '`')
+                .to_lowercase()
+                .split_whitespace()
+                .collect::<Vec<_>>()
+                .join("-");
+            clusters.push(TagCluster { tag, note_ids: group.iter().map(|note| note.id.clone()).collect() });
+        }
+        Ok(clusters)
+    }
+    
+    // Check DNS, TCP and HTTPS reachability of the Gemini API, timing each step
+    #[tauri::command]
+    pub fn diagnose_connectivity() -> ConnectivityReport {
+        info!("Tauri command: diagnose_connectivity called");
+        client().diagnose_connectivity()
+    }
+    
+    // Open (or refresh) the connection to the Gemini API ahead of time so the first
+    // autocomplete after a pause doesn't pay for the TLS handshake. Returns how long it took.
+    #[tauri::command]
+    pub fn warm_completion() -> Result<u64, NoteError> {
+        // Clone the client so completions aren't held up behind the ping
+        let client = client();
+        if client.api_key().is_empty() {
+            return Err(NoteError::Completion("Gemini API key not configured. Set the GEMINI_API_KEY environment variable.".to_string()));
+        }
+        let start = Instant::now();
+        client.warm()?;
+        Ok(start.elapsed().as_millis() as u64)
+    }
+    
+    // Shortest keep-alive interval accepted, so the pings can't turn into a flood
+    const MIN_KEEP_ALIVE_SECS: u64 = 15;
+    
+    // How often the keep-alive thread checks whether pinging has been turned on
+    const KEEP_ALIVE_IDLE_CHECK: Duration = Duration::from_secs(60);
+    
+    // Set (or turn off with None) how often the connection is warmed in the background
+    #[tauri::command]
+    pub fn set_keep_alive_interval(secs: Option<u64>) -> Result<(), String> {
+        if secs.is_some_and(|secs| secs < MIN_KEEP_ALIVE_SECS) {
+            return Err(format!("keep-alive interval must be at least {} seconds", MIN_KEEP_ALIVE_SECS));
+        }
+        let mut app_config = config::load_config();
+        app_config.keep_alive_interval_secs = secs;
+        config::save_config(&app_config)
+    }
+    
+    // Warm the connection every keep_alive_interval_secs while that's set. The setting is
+    // re-read each round, so turning it on or off takes effect without a restart.
+    pub(crate) fn start_keep_alive() {
+        std::thread::spawn(|| loop {
+            match config::load_config().keep_alive_interval_secs {
+                Some(secs) => {
+                    if let Err(e) = warm_completion() {
+                        eprintln!("Error warming completion connection: {}", e);
+                    }
+                    std::thread::sleep(Duration::from_secs(secs.max(MIN_KEEP_ALIVE_SECS)));
+                }
+                None => std::thread::sleep(KEEP_ALIVE_IDLE_CHECK),
+            }
+        });
+    }
+    
+    // Rewrite a selected passage according to a natural-language instruction
+    // (e.g. "make this more formal"), returning only the rewritten passage
+    #[tauri::command]
+    pub fn rewrite_selection(text: String, instruction: String) -> Result<String, String> {
+        info!("Tauri command: rewrite_selection called with instruction: '{}'", instruction);
+        if text.trim().is_empty() {
+            return Err("Nothing selected to rewrite".to_string());
+        }
+        
+        let client = client();
+        if client.api_key().is_empty() {
+            return Err("Gemini API key not configured. Set the GEMINI_API_KEY environment variable.".to_string());
+        }
+        
+        let prompt = format!("Instruction: {}\n\nPassage:\n{}", instruction.trim(), text.trim());
+        let generation_config = config::generation_defaults("rewrite").to_generation_config(None, None);
+        let rewritten = client
+            .generate(REWRITE_INSTRUCTION, prompt, None, generation_config)
+            .map_err(|e| {
+                error!("Error rewriting selection: {}", e);
+                e.to_string()
+            })?;
+        
+        // Keep the selection's surrounding whitespace so it drops back in place cleanly
+        let leading = &text[..text.len() - text.trim_start().len()];
+        let trailing = &text[text.trim_end().len()..];
+        Ok(format!("{}{}{}", leading, rewritten.trim(), trailing))
+    }
+
+    // Notes ask_notes reads when the caller doesn't say how many
+    const ASK_NOTES_DEFAULT_K: usize = 5;
+    
+    // Answer a question from the notes most related to it. The notes go into context best
+    // match first, trimmed to the configured context budget. With chat grounding on, the
+    // model may only use those notes and cites them by id.
+    #[tauri::command]
+    pub fn ask_notes(question: String, k: Option<usize>) -> Result<String, NoteError> {
+        info!("Tauri command: ask_notes called with question: '{}'", question);
+        if question.trim().is_empty() {
+            return Err(NoteError::InvalidInput("question is empty".to_string()));
+        }
+        let ids = crate::EMBEDDING_MANAGER.lock()?.search(&question, k.unwrap_or(ASK_NOTES_DEFAULT_K), None)?;
+        let notes: Vec<crate::Note> = ids.into_iter().filter_map(|id| crate::commands::get_note(id).ok()).collect();
+        
+        let app_config = config::load_config();
+        // Grounded answers cite notes by id, so each heading carries one
+        let titles: Vec<String> = notes
+            .iter()
+            .map(|note| if app_config.chat_grounding { format!("{} [{}]", note.title, note.id) } else { note.title.clone() })
+            .collect();
+        let ranked: Vec<(&str, &str)> = titles.iter().zip(&notes).map(|(title, note)| (title.as_str(), note.content.as_str())).collect();
+        let context = app_config.context_format.render_within(&ranked, &app_config.context_budget);
+        let instruction = if app_config.chat_grounding {
+            format!("{} {}", ASK_INSTRUCTION, GROUNDING_INSTRUCTION)
+        } else {
+            ASK_INSTRUCTION.to_string()
+        };
+        
+        let client = client();
+        if client.api_key().is_empty() {
+            return Err(NoteError::Completion("Gemini API key not configured. Set the GEMINI_API_KEY environment variable.".to_string()));
+        }
+        let generation_config = config::generation_defaults("chat").to_generation_config(None, None);
+        let context = (!context.is_empty()).then_some(context.as_str());
+        Ok(client.generate(&instruction, question, context, generation_config)?)
+    }
+    
+    // Turn on or off restricting ask_notes to answers found in the user's notes
+    #[tauri::command]
+    pub fn set_chat_grounding(enabled: bool) -> Result<(), String> {
+        let mut app_config = config::load_config();
+        app_config.chat_grounding = enabled;
+        config::save_config(&app_config)
+    }
+    
+    // Get the generation defaults used by a feature (autocomplete, chat, ...)
+    #[tauri::command]
+    pub fn get_generation_defaults(feature: String) -> Result<GenerationDefaults, String> {
+        if !GENERATION_FEATURES.contains(&feature.as_str()) {
+            return Err(format!("Unknown feature '{}'", feature));
+        }
+        Ok(config::generation_defaults(&feature))
+    }
+    
+    // Persist the generation defaults for a feature
+    #[tauri::command]
+    pub fn set_generation_defaults(feature: String, defaults: GenerationDefaults) -> Result<(), String> {
+        if !GENERATION_FEATURES.contains(&feature.as_str()) {
+            return Err(format!("Unknown feature '{}'", feature));
+        }
+        defaults.validate()?;
+        let mut app_config = config::load_config();
+        app_config.generation.insert(feature, defaults);
+        config::save_config(&app_config)
+    }
+
+    // Toggle sending a note's `---context---` section with autocomplete requests
+    #[tauri::command]
+    pub fn set_frozen_context_enabled(enabled: bool) -> Result<(), String> {
+        let mut app_config = config::load_config();
+        app_config.frozen_context_enabled = enabled;
+        config::save_config(&app_config)
+    }
+    
+    // Toggle leaving fenced code out of completion context
+    #[tauri::command]
+    pub fn set_strip_code_from_context(enabled: bool) -> Result<(), String> {
+        let mut app_config = config::load_config();
+        app_config.strip_code_from_context = enabled;
+        config::save_config(&app_config)
+    }
+    
+    // Set how note text is laid out when sent to the model as context
+    #[tauri::command]
+    pub fn set_context_format(format: crate::context::ContextFormat) -> Result<(), String> {
+        format.validate()?;
+        let mut app_config = config::load_config();
+        app_config.context_format = format;
+        config::save_config(&app_config)
+    }
+    
+    // Set how much note text completions may send as context, per note and in total
+    #[tauri::command]
+    pub fn set_context_budget(budget: crate::context::ContextBudget) -> Result<(), String> {
+        budget.validate()?;
+        let mut app_config = config::load_config();
+        app_config.context_budget = budget;
+        config::save_config(&app_config)
+    }
+    
+    // Set (or clear with None) the template every autocomplete prompt is wrapped in. Notes
+    // can override it with a `prompt_template` metadata field.
+    #[tauri::command]
+    pub fn set_prompt_template(template: Option<String>) -> Result<(), String> {
+        let template = template.filter(|template| !template.trim().is_empty());
+        if let Some(template) = &template {
+            config::validate_prompt_template(template)?;
+        }
+        let mut app_config = config::load_config();
+        app_config.prompt_template = template;
+        config::save_config(&app_config)
+    }
+    
+    // The style instructions applied around every completion's system instruction
+    #[tauri::command]
+    pub fn get_global_prompt() -> config::GlobalPrompt {
+        config::GlobalPrompt::from_app_config(&config::load_config())
+    }
+    
+    // Set (or clear with None) the global prompt prefix and suffix. These sit around each
+    // command's own instruction and are kept separate from the prompt template.
+    #[tauri::command]
+    pub fn set_global_prompt(prefix: Option<String>, suffix: Option<String>) -> Result<config::GlobalPrompt, String> {
+        let mut app_config = config::load_config();
+        app_config.global_prompt_prefix = prefix.filter(|prefix| !prefix.trim().is_empty());
+        app_config.global_prompt_suffix = suffix.filter(|suffix| !suffix.trim().is_empty());
+        config::save_config(&app_config)?;
+        Ok(config::GlobalPrompt::from_app_config(&app_config))
+    }
+    
+    // Turn thinking on or off for every completion but autocomplete (chat, rewrite,
+    // structured, summarize). With it on the model reasons before answering (see
+    // config::THINKING_BUDGET), which is slower but gives better answers. The budget is
+    // added on top of each feature's max_tokens, so answers aren't cut short by it.
+    #[tauri::command]
+    pub fn set_thinking_enabled(enabled: bool) -> Result<(), String> {
+        let mut app_config = config::load_config();
+        app_config.thinking_enabled = enabled;
+        config::save_config(&app_config)
+    }
+    
+    // Turn on or off asking autocomplete to continue in the note's own language
+    #[tauri::command]
+    pub fn set_language_matched_prompt(enabled: bool) -> Result<(), String> {
+        let mut app_config = config::load_config();
+        app_config.language_matched_prompt = enabled;
+        config::save_config(&app_config)
+    }
+    
+    // Get when autocomplete fires
+    #[tauri::command]
+    pub fn get_autocomplete_settings() -> config::AutocompleteSettings {
+        config::load_config().autocomplete
+    }
+    
+    // Set the autocomplete debounce and minimum prefix length
+    #[tauri::command]
+    pub fn set_autocomplete_settings(debounce_ms: u64, min_prefix_len: usize) -> Result<(), String> {
+        let settings = config::AutocompleteSettings { debounce_ms, min_prefix_len };
+        settings.validate()?;
+        let mut app_config = config::load_config();
+        app_config.autocomplete = settings;
+        config::save_config(&app_config)
+    }
+
+    // List the Gemini models available to this API key, for the settings model picker
+    #[tauri::command]
+    pub fn list_models() -> Result<Vec<ModelInfo>, String> {
+        if let Ok(cache) = MODELS_CACHE.lock() {
+            if let Some((fetched_at, models)) = cache.as_ref() {
+                if fetched_at.elapsed() < MODELS_CACHE_TTL {
+                    return Ok(models.clone());
+                }
+            }
+        }
+        
+        let client = client();
+        if client.api_key().is_empty() {
+            return Err("Gemini API key not configured. Set the GEMINI_API_KEY environment variable.".to_string());
+        }
+        let models = client.list_models().map_err(|e| {
+            error!("Error listing models: {}", e);
+            e.to_string()
+        })?;
+        
+        if let Ok(mut cache) = MODELS_CACHE.lock() {
+            *cache = Some((Instant::now(), models.clone()));
+        }
+        Ok(models)
+    }
+
     // Check if Gemini API is configured and working
     #[tauri::command]
     pub fn check_server_status() -> Result<bool, String> {
@@ -284,14 +3744,7 @@ mod completion {
         
         // Get the client
         println!("[FRONTEND_DEBUG] Acquiring lock on GeminiClient for status check");
-        let client_result = CLIENT.lock();
-        if let Err(e) = client_result {
-            let error_msg = format!("Failed to acquire lock on GeminiClient: {}", e);
-            println!("[FRONTEND_DEBUG] {}", error_msg);
-            return Err(error_msg);
-        }
-        
-        let client = client_result.unwrap();
+        let client = client();
         println!("[FRONTEND_DEBUG] Successfully acquired lock on GeminiClient");
         
         // Check if the API key is configured
@@ -320,29 +3773,406 @@ mod completion {
             }
         }
     }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn client_survives_a_panic_while_locked() {
+            let _ = std::thread::spawn(|| {
+                let _client = lock_client();
+                panic!("simulated panic while holding the client");
+            })
+            .join();
+            drop(lock_client());
+            assert!(!CLIENT.is_poisoned());
+            // Later requests get a working copy too
+            let _ = client();
+        }
+
+        #[test]
+        fn completions_never_exceed_the_word_cap() {
+            let outputs = [
+                " jumps over the lazy dog and keeps going",
+                "\"jumps over\nthe   lazy dog\"",
+                "The quick brown fox jumps over the lazy dog",
+                "one",
+                "",
+            ];
+            for raw in outputs {
+                for max_words in 0..6 {
+                    let text = finish_completion("The quick brown fox", raw, None, Some(max_words));
+                    assert!(text.split_whitespace().count() <= max_words, "{:?} capped at {} gave {:?}", raw, max_words, text);
+                }
+            }
+            assert_eq!(finish_completion("The fox", " jumps over the dog", None, Some(2)), " jumps over");
+        }
+
+        #[test]
+        fn word_budget_leaves_room_for_every_word() {
+            for words in 1..10 {
+                assert!(tokens_for_words(words) as f32 >= words as f32 * crate::completion_client::postprocess::TOKENS_PER_WORD);
+                assert!(tokens_for_words(words + 1) > tokens_for_words(words));
+            }
+        }
+
+        #[test]
+        fn cursor_window_takes_the_window_and_a_suffix_hint() {
+            let text = "one two three|four five";
+            let cursor = text.find('|').unwrap();
+            assert_eq!(cursor_window(text, cursor, Some(5)), ("three", Some("|four five")));
+            assert_eq!(cursor_window(text, cursor, None).0, "one two three");
+            assert_eq!(cursor_window("héllo   ", 6, Some(2)), ("lo", None));
+        }
+
+        #[test]
+        fn language_matched_instruction_follows_the_setting() {
+            let note = crate::Note { language: Some("deu".to_string()), ..Default::default() };
+            let on = config::AppConfig { language_matched_prompt: true, ..Default::default() };
+            let request = autocomplete_request("Wir gehen", None, Some(&note), &on, None, None);
+            assert!(request.instruction.contains("(deu)"));
+            let off = config::AppConfig { language_matched_prompt: false, ..Default::default() };
+            let request = autocomplete_request("Wir gehen", None, Some(&note), &off, None, None);
+            assert!(!request.instruction.contains("(deu)"));
+            // Too little text to detect reliably falls back to the default instruction
+            let request = autocomplete_request("ok", None, None, &on, None, None);
+            assert!(!request.instruction.contains(" is writing in "));
+        }
+        
+        #[test]
+        fn suffix_hint_goes_into_the_instruction() {
+            let app_config = config::AppConfig::default();
+            let request = autocomplete_request("The cat", Some("on the mat."), None, &app_config, None, None);
+            assert!(request.instruction.contains("on the mat."));
+            assert_eq!(request.prompt, "The cat");
+            let request = autocomplete_request("The cat", None, None, &app_config, None, None);
+            assert!(!request.instruction.contains("after the cursor"));
+        }
+
+        #[test]
+        fn note_temperature_and_template_apply_unless_overridden() {
+            let app_config = config::AppConfig::default();
+            let mut note = crate::Note { completion_temperature: Some(0.1), ..Default::default() };
+            note.metadata.insert(config::PROMPT_TEMPLATE_KEY.to_string(), "Tersely: {prompt}".to_string());
+            let request = autocomplete_request("The cat", None, Some(&note), &app_config, Some(8), None);
+            assert_eq!(request.generation_config.temperature, Some(0.1));
+            assert_eq!(request.generation_config.max_output_tokens, Some(8));
+            assert_eq!(request.prompt, "Tersely: The cat");
+            let request = autocomplete_request("The cat", None, Some(&note), &app_config, None, Some(0.9));
+            assert_eq!(request.generation_config.temperature, Some(0.9));
+        }
+
+        fn flashcard_schema() -> serde_json::Value {
+            serde_json::json!({
+                "type": "OBJECT",
+                "required": ["cards"],
+                "properties": {
+                    "cards": {
+                        "type": "ARRAY",
+                        "items": {
+                            "type": "OBJECT",
+                            "required": ["front", "back"],
+                            "properties": {
+                                "front": { "type": "STRING" },
+                                "back": { "type": "STRING" },
+                                "difficulty": { "type": "STRING", "enum": ["easy", "hard"] }
+                            }
+                        }
+                    }
+                }
+            })
+        }
+
+        fn violation(raw: &str) -> (String, String) {
+            match parse_structured(raw, &flashcard_schema(), true) {
+                Err(NoteError::SchemaViolation { path, message }) => (path, message),
+                other => panic!("expected a schema violation, got {:?}", other),
+            }
+        }
+
+        #[test]
+        fn conforming_structured_output_is_returned_parsed() {
+            let raw = r#" {"cards": [{"front": "Capital of France?", "back": "Paris", "difficulty": "easy"}]} "#;
+            let value = parse_structured(raw, &flashcard_schema(), true).unwrap();
+            assert_eq!(value["cards"][0]["back"], "Paris");
+        }
+
+        #[test]
+        fn nonconforming_structured_output_names_the_bad_value() {
+            assert_eq!(violation(r#"{"cards": [{"front": "Q"}]}"#), ("$.cards[0]".to_string(), "is missing required property 'back'".to_string()));
+            assert_eq!(violation(r#"{"cards": [{"front": "Q", "back": 4}]}"#).0, "$.cards[0].back");
+            assert_eq!(violation(r#"{"cards": [{"front": "Q", "back": "A", "difficulty": "medium"}]}"#).0, "$.cards[0].difficulty");
+            assert_eq!(violation(r#"{"cards": {}}"#).0, "$.cards");
+            let (path, message) = violation(r#"{"cards": ["#);
+            assert_eq!(path, "$");
+            assert!(message.starts_with("malformed JSON"));
+        }
+
+        #[test]
+        fn unvalidated_structured_output_only_has_to_parse() {
+            let value = parse_structured(r#"{"cards": 4}"#, &flashcard_schema(), false).unwrap();
+            assert_eq!(value["cards"], 4);
+            assert!(parse_structured("not json", &flashcard_schema(), false).is_err());
+        }
+    }
 }
 
 // Main run function
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
-    // Launch local LLM server in the background
-    {
-
+    // Surface an unwritable vault up front rather than on the first failed save
+    if let Err(e) = check_vault_writable() {
+        eprintln!("{}", e);
+        let _ = STARTUP_VAULT_ERROR.set(e.to_string());
+    }
+    
+    if let Some(days) = config::load_config().auto_trash_empty_after_days {
+        let trashed = commands::trash_stale_empty_notes(days);
+        if !trashed.is_empty() {
+            println!("Moved {} empty notes to the trash", trashed.len());
+        }
     }
+    
+    // Load the saved vector index and bring it in line with the notes on disk
+    if let Err(e) = commands::load_index() {
+        eprintln!("Error building embedding index: {}", e);
+    }
+    
+    // Build the full-text index off the main thread; save and delete keep it current after that
+    std::thread::spawn(|| {
+        text::rebuild(|index| commands::list_all_notes().iter().for_each(|note| index.update(note)));
+    });
+
+    completion::start_keep_alive();
 
     tauri::Builder::default()
         .plugin(tauri_plugin_opener::init())
-        .invoke_handler(tauri::generate_handler![
+        .setup(|app| {
+            if let Some(error) = STARTUP_VAULT_ERROR.get() {
+                let _ = app.handle().emit("vault-not-writable", error.clone());
+            }
+            reminders::start_scheduler(app.handle().clone());
+            Ok(())
+        })
+        .invoke_handler(timing::timed(tauri::generate_handler![
             commands::list_notes,
+            commands::set_note_state,
+            commands::list_recovered_notes,
+            commands::migrate_note_states,
+            commands::migrate_note_schema,
             commands::create_note,
             commands::save_note,
+            commands::append_to_note,
             commands::delete_note,
+            commands::list_empty_notes,
+            commands::delete_empty_notes,
+            commands::empty_notes,
+            commands::exact_duplicates,
+            commands::notes_on_this_day,
+            commands::set_auto_trash_empty_after_days,
             commands::search_notes,
             commands::semantic_search,
+            commands::count_matches,
+            commands::search_notes_detailed,
+            commands::search_notes_streaming,
+            commands::cancel_search,
+            commands::get_note,
+            commands::render_note_html,
+            commands::note_outline,
+            commands::note_changelog,
+            commands::list_notes_by_language,
+            commands::note_content_hash,
+            commands::note_path,
+            commands::export_note_pdf,
+            commands::export_by_tag,
+            commands::toggle_tag,
+            commands::build_index_note,
+            commands::notes_linking_to,
+            commands::title_autocomplete,
+            commands::benchmark_search,
+            commands::list_notes_by_length,
+            commands::export_graph,
+            commands::related_notes,
+            commands::list_notes_clustered,
+            commands::semantic_hits,
+            commands::list_notes_sorted,
+            commands::list_notes_stream,
+            commands::get_default_sort,
+            commands::set_default_sort,
+            commands::vault_config,
+            commands::set_vault_config,
+            commands::set_note_order,
+            commands::get_note_order,
+            commands::batch,
+            commands::init_index,
+            commands::index_status,
+            commands::index_stats,
+            commands::compact_index,
+            commands::import_path,
+            commands::import_markdown_split,
+            commands::set_import_allowed_tags,
+            commands::reconcile_index,
+            commands::refresh_index,
+            commands::verify_index,
+            commands::list_unindexed_notes,
+            commands::reindex_note,
+            commands::precompute_embeddings,
+            commands::cancel_precompute_embeddings,
+            commands::rebuild_text_index,
+            commands::set_stop_words,
+            commands::note_similarity,
+            commands::embed_text,
+            commands::embed_note,
+            commands::embedding_dimension,
+            commands::embedding_backend_status,
+            commands::set_note_format,
+            commands::set_max_note_bytes,
+            commands::get_embedding_strategy,
+            commands::set_embedding_strategy,
+            commands::get_hnsw_params,
+            commands::set_hnsw_params,
+            commands::set_note_cursor,
+            commands::set_note_completion_temperature,
+            commands::detect_language,
+            commands::set_note_metadata,
+            commands::get_note_metadata,
+            commands::effective_config,
+            commands::set_reminder,
+            commands::due_reminders,
+            commands::clear_reminder,
+            commands::list_due_reminders,
+            commands::check_vault,
+            commands::startup_vault_error,
+            commands::set_language_detection,
+            commands::set_auto_title,
             completion::get_completion,
+            completion::retry_last_completion,
             completion::chat_completion,
+            completion::save_chat_as_note,
             completion::check_server_status,
-        ])
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+            completion::diagnose_connectivity,
+            completion::warm_completion,
+            completion::set_keep_alive_interval,
+            completion::get_generation_defaults,
+            completion::set_generation_defaults,
+            completion::list_models,
+            completion::set_frozen_context_enabled,
+            completion::set_strip_code_from_context,
+            completion::set_context_format,
+            completion::set_context_budget,
+            completion::ask_notes,
+            completion::set_chat_grounding,
+            completion::get_autocomplete_settings,
+            completion::set_autocomplete_settings,
+            completion::set_prompt_template,
+            completion::get_global_prompt,
+            completion::set_global_prompt,
+            completion::set_language_matched_prompt,
+            completion::set_thinking_enabled,
+            completion::rewrite_selection,
+            completion::suggest_tag_clusters,
+            completion::clear_completion_cache,
+            completion::set_completion_cache_settings,
+            completion::autocomplete_at,
+            completion::debug_build_request,
+            completion::preview_completion_context,
+            completion::get_structured_completion,
+            commands::get_command_timings,
+        ]))
+        .build(tauri::generate_context!())
+        .expect("error while running tauri application")
+        .run(|_app, event| {
+            if let tauri::RunEvent::Exit = event {
+                if let Err(e) = commands::persist_index() {
+                    eprintln!("Error saving embedding index: {}", e);
+                }
+            }
+        });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn note(title: &str, content: &str) -> Note {
+        Note { title: title.to_string(), content: content.to_string(), ..Default::default() }
+    }
+
+    #[test]
+    fn content_hash_ignores_invisible_differences() {
+        let original = note("Plan", "first line\nsecond line");
+        assert_eq!(content_hash(&original), content_hash(&note("Plan", "first line\r\nsecond line")));
+        assert_eq!(content_hash(&original), content_hash(&note("Plan ", "first line  \nsecond line\n\n")));
+        // "é" precomposed and as "e" plus a combining accent
+        assert_eq!(content_hash(&note("Caf\u{e9}", "")), content_hash(&note("Cafe\u{301}", "")));
+    }
+
+    #[test]
+    fn content_hash_sees_real_changes() {
+        let original = note("Plan", "first line\nsecond line");
+        assert_ne!(content_hash(&original), content_hash(&note("Plan", "first line\nsecond  line")));
+        assert_ne!(content_hash(&original), content_hash(&note("Plans", "first line\nsecond line")));
+        // Moving text between the title and the content is a change
+        assert_ne!(content_hash(&note("ab", "c")), content_hash(&note("a", "bc")));
+        assert_eq!(content_hash(&original).len(), 16);
+    }
+
+    #[test]
+    fn exact_hash_counts_every_byte() {
+        let original = note("Plan", "first line\nsecond line");
+        assert_eq!(exact_content_hash(&original), exact_content_hash(&original.clone()));
+        assert_ne!(exact_content_hash(&original), exact_content_hash(&note("Plan", "first line\r\nsecond line")));
+    }
+
+    #[test]
+    fn cursor_offsets_are_clamped_onto_char_boundaries() {
+        let content = "h\u{e9}llo";
+        assert_eq!(clamp_cursor(content, 0), 0);
+        assert_eq!(clamp_cursor(content, 3), 3);
+        // Inside the two-byte "é"
+        assert_eq!(clamp_cursor(content, 2), 1);
+        assert_eq!(clamp_cursor(content, content.len()), content.len());
+        assert_eq!(clamp_cursor(content, 1000), content.len());
+        assert_eq!(clamp_cursor("", 5), 0);
+    }
+
+    #[test]
+    fn auto_title_replaces_only_the_placeholder() {
+        let placeholder = || NEW_NOTE_TITLE.to_string();
+        assert_eq!(auto_title(placeholder(), "\n  ## Groceries \nmilk", true), "Groceries");
+        assert_eq!(auto_title("Shopping".to_string(), "Groceries", true), "Shopping");
+        assert_eq!(auto_title(placeholder(), "Groceries", false), NEW_NOTE_TITLE);
+        // Nothing to take a title from yet
+        assert_eq!(auto_title(placeholder(), "", true), NEW_NOTE_TITLE);
+        assert_eq!(auto_title(placeholder(), " \n###\n", true), NEW_NOTE_TITLE);
+    }
+
+    #[test]
+    fn auto_title_is_cut_to_the_first_line_and_limit() {
+        let long = "word ".repeat(40);
+        let title = auto_title(NEW_NOTE_TITLE.to_string(), &long, true);
+        assert!(title.chars().count() <= AUTO_TITLE_MAX_CHARS);
+        assert!(!title.ends_with(' '));
+    }
+
+    #[test]
+    fn note_size_limit_allows_exactly_the_limit() {
+        let config = config::AppConfig { max_note_bytes: Some(4), ..Default::default() };
+        assert!(check_note_size("abcd", &config).is_ok());
+        assert!(matches!(check_note_size("abcde", &config), Err(NoteError::TooLarge { size: 5, limit: 4 })));
+        // The limit is in bytes, not characters
+        assert!(matches!(check_note_size("\u{e9}\u{e9}\u{e9}", &config), Err(NoteError::TooLarge { size: 6, limit: 4 })));
+    }
+    #[test]
+    fn vault_under_a_file_is_not_writable() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("not-a-folder");
+        std::fs::write(&file, "").unwrap();
+        // Creating a folder inside a regular file fails even for root
+        let vault = file.join("notes");
+        assert!(matches!(check_dir_writable(&vault), Err(NoteError::VaultNotWritable { path, .. }) if path == vault.display().to_string()));
+        assert!(check_dir_writable(&dir.path().join("notes")).is_ok());
+        assert!(!dir.path().join("notes").join(".write-test").exists());
+    }
 }
@@ -0,0 +1,431 @@
+use pulldown_cmark::{html, CowStr, Event, Options, Parser, Tag, TagEnd};
+use serde::Serialize;
+
+// URL schemes that are allowed to survive into rendered links and images
+const SAFE_SCHEMES: [&str; 4] = ["http:", "https:", "mailto:", "#"];
+
+// A single `[[target|label]]` occurrence located within a larger string
+struct WikiLink<'a> {
+    start: usize,
+    end: usize,
+    target: &'a str,
+    label: &'a str,
+}
+
+// Find the first well-formed wiki-link in `text`
+fn find_wiki_link(text: &str) -> Option<WikiLink<'_>> {
+    let mut offset = 0;
+    loop {
+        let start = offset + text[offset..].find("[[")?;
+        let len = text[start + 2..].find("]]")?;
+        let inner = &text[start + 2..start + 2 + len];
+        // A nested `[[` means the first opener was never closed
+        if let Some(nested) = inner.rfind("[[") {
+            offset = start + 2 + nested;
+            continue;
+        }
+        let mut parts = inner.splitn(2, '|');
+        let target = parts.next().unwrap_or("").trim();
+        let label = parts.next().map(str::trim).unwrap_or(target);
+        if target.is_empty() || target.contains('\n') {
+            offset = start + 2;
+            continue;
+        }
+        return Some(WikiLink { start, end: start + 2 + len + 2, target, label });
+    }
+}
+
+// Extract the targets of all `[[wiki-links]]` in a note, in order of appearance.
+// `[[Target|label]]` yields `Target`.
+pub fn wiki_links(content: &str) -> Vec<String> {
+    let mut links = Vec::new();
+    let mut rest = content;
+    while let Some(link) = find_wiki_link(rest) {
+        links.push(link.target.to_string());
+        rest = &rest[link.end..];
+    }
+    links
+}
+
+// `text` with each `[[wiki-link]]` replaced by its label and `#tags` removed, for snippets
+// quoted in other notes, where they'd otherwise add links and tags that aren't really there
+pub fn strip_links_and_tags(text: &str) -> String {
+    let mut unlinked = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(link) = find_wiki_link(rest) {
+        unlinked.push_str(&rest[..link.start]);
+        unlinked.push_str(link.label);
+        rest = &rest[link.end..];
+    }
+    unlinked.push_str(rest);
+
+    let mut stripped = String::with_capacity(unlinked.len());
+    let mut previous = ' ';
+    let mut chars = unlinked.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        let starts_tag = c == '#'
+            && (previous.is_whitespace() || previous == '(')
+            && unlinked[i + 1..].starts_with(char::is_alphabetic);
+        if starts_tag {
+            while chars.next_if(|&(_, c)| is_tag_char(c)).is_some() {}
+            previous = '#';
+            continue;
+        }
+        stripped.push(c);
+        previous = c;
+    }
+    stripped
+}
+
+// Extract the `#tags` in a note, lowercased and deduped, in order of first appearance.
+// A tag starts with a letter and may contain letters, digits, '-', '_' and '/'. Code spans
+// and blocks are skipped, and headings never match since their `#` isn't part of the text.
+pub fn tags(content: &str) -> Vec<String> {
+    let mut tags: Vec<String> = Vec::new();
+    let mut in_code_block = false;
+    for event in Parser::new(content) {
+        let text = match event {
+            Event::Start(Tag::CodeBlock(_)) => {
+                in_code_block = true;
+                continue;
+            }
+            Event::End(TagEnd::CodeBlock) => {
+                in_code_block = false;
+                continue;
+            }
+            Event::Text(text) if !in_code_block => text,
+            _ => continue,
+        };
+        let mut previous = ' ';
+        for (i, c) in text.char_indices() {
+            if c == '#' && (previous.is_whitespace() || previous == '(') {
+                let rest = &text[i + 1..];
+                let len = rest.find(|c: char| !is_tag_char(c)).unwrap_or(rest.len());
+                let tag = rest[..len].trim_end_matches('/').to_lowercase();
+                if tag.starts_with(char::is_alphabetic) && !tags.contains(&tag) {
+                    tags.push(tag);
+                }
+            }
+            previous = c;
+        }
+    }
+    tags
+}
+
+fn is_tag_char(c: char) -> bool {
+    c.is_alphanumeric() || matches!(c, '-' | '_' | '/')
+}
+
+// Normalize user input to the form `tags` returns ("#Food/" -> "food"), or None if it
+// isn't a valid tag
+pub fn normalize_tag(tag: &str) -> Option<String> {
+    let tag = tag.trim().trim_start_matches('#').trim_end_matches('/').to_lowercase();
+    (tag.starts_with(char::is_alphabetic) && tag.chars().all(is_tag_char)).then_some(tag)
+}
+
+// Remove `tag` (normalized, see normalize_tag) from `content` if it has it, else add it
+pub fn toggle_tag(content: &str, tag: &str) -> String {
+    if tags(content).iter().any(|existing| existing == tag) {
+        remove_tag(content, tag)
+    } else {
+        add_tag(content, tag)
+    }
+}
+
+// Append `#tag` to the note, on its trailing line of tags if it ends with one
+pub fn add_tag(content: &str, tag: &str) -> String {
+    let trimmed = content.trim_end();
+    let last_line = trimmed.lines().next_back().unwrap_or("");
+    let is_tag_line = !last_line.trim().is_empty()
+        && last_line.split_whitespace().all(|word| word.len() > 1 && word.starts_with('#') && !word.starts_with("##"));
+    if trimmed.is_empty() {
+        format!("#{}\n", tag)
+    } else if is_tag_line {
+        format!("{} #{}\n", trimmed, tag)
+    } else {
+        format!("{}\n\n#{}\n", trimmed, tag)
+    }
+}
+
+// Remove every `#tag` occurrence (case-insensitively) outside fenced code. Lines left with
+// nothing but whitespace by the removal are dropped.
+pub fn remove_tag(content: &str, tag: &str) -> String {
+    let mut kept = String::with_capacity(content.len());
+    let mut in_fence = false;
+    for line in content.split_inclusive('\n') {
+        let trimmed = line.trim_start();
+        if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            in_fence = !in_fence;
+        }
+        if in_fence || !line.contains('#') {
+            kept.push_str(line);
+            continue;
+        }
+        let (body, newline) = match line.strip_suffix('\n') {
+            Some(body) => (body, "\n"),
+            None => (line, ""),
+        };
+        let stripped = remove_tag_from_line(body, tag);
+        if stripped.trim().is_empty() && !body.trim().is_empty() {
+            continue;
+        }
+        kept.push_str(&stripped);
+        kept.push_str(newline);
+    }
+    kept
+}
+
+fn remove_tag_from_line(line: &str, tag: &str) -> String {
+    let mut kept = String::with_capacity(line.len());
+    let mut previous = ' ';
+    let mut skip_to = 0;
+    for (i, c) in line.char_indices() {
+        if i < skip_to {
+            continue;
+        }
+        if c == '#' && (previous.is_whitespace() || previous == '(') {
+            let rest = &line[i + 1..];
+            let len = rest.find(|c: char| !is_tag_char(c)).unwrap_or(rest.len());
+            if rest[..len].trim_end_matches('/').to_lowercase() == tag {
+                // Take the space before the tag with it
+                if kept.ends_with(' ') {
+                    kept.pop();
+                }
+                skip_to = i + 1 + len;
+                previous = '#';
+                continue;
+            }
+        }
+        kept.push(c);
+        previous = c;
+    }
+    if line.starts_with(char::is_whitespace) {
+        kept
+    } else {
+        kept.trim_start().to_string()
+    }
+}
+
+// One heading in a note's outline, with the headings nested under it
+#[derive(Serialize, Debug)]
+pub struct OutlineEntry {
+    pub text: String,
+    pub level: u8,
+    // Character (not byte) offset of the heading line in the content
+    pub offset: usize,
+    pub children: Vec<OutlineEntry>,
+}
+
+// Build a nested table of contents from the markdown headings in `content`. A heading is
+// nested under the closest preceding heading of a lower level, so skipped levels are fine.
+pub fn outline(content: &str) -> Vec<OutlineEntry> {
+    let mut headings = Vec::new();
+    let mut current: Option<(u8, usize, String)> = None;
+    for (event, range) in Parser::new(content).into_offset_iter() {
+        match event {
+            Event::Start(Tag::Heading { level, .. }) => {
+                let offset = content[..range.start].chars().count();
+                current = Some((level as u8, offset, String::new()));
+            }
+            Event::Text(text) | Event::Code(text) => {
+                if let Some((_, _, heading)) = current.as_mut() {
+                    heading.push_str(&text);
+                }
+            }
+            Event::End(TagEnd::Heading(_)) => {
+                if let Some((level, offset, text)) = current.take() {
+                    headings.push(OutlineEntry { text: text.trim().to_string(), level, offset, children: Vec::new() });
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // Fold the flat list into a tree, keeping a stack of the currently open headings
+    let mut roots: Vec<OutlineEntry> = Vec::new();
+    let mut open: Vec<OutlineEntry> = Vec::new();
+    for heading in headings {
+        while open.last().is_some_and(|parent| parent.level >= heading.level) {
+            close_heading(&mut open, &mut roots);
+        }
+        open.push(heading);
+    }
+    while !open.is_empty() {
+        close_heading(&mut open, &mut roots);
+    }
+    roots
+}
+
+// Pop the innermost open heading and attach it to its parent, or to the roots
+fn close_heading(open: &mut Vec<OutlineEntry>, roots: &mut Vec<OutlineEntry>) {
+    if let Some(heading) = open.pop() {
+        match open.last_mut() {
+            Some(parent) => parent.children.push(heading),
+            None => roots.push(heading),
+        }
+    }
+}
+
+// Render markdown to HTML. Raw HTML in the source is escaped rather than passed through,
+// unsafe link schemes are neutralised, and `[[wiki-links]]` become anchors to the note id
+// returned by `resolve` (or an inert span when the target doesn't exist).
+pub fn render_html<F>(content: &str, resolve: F) -> String
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let mut options = Options::empty();
+    options.insert(Options::ENABLE_TABLES);
+    options.insert(Options::ENABLE_STRIKETHROUGH);
+    options.insert(Options::ENABLE_TASKLISTS);
+
+    let mut events: Vec<Event> = Vec::new();
+    let mut pending_text = String::new();
+    let mut in_code_block = false;
+
+    for event in Parser::new_ext(content, options) {
+        let event = sanitize_event(event);
+        match event {
+            Event::Text(text) if !in_code_block => {
+                pending_text.push_str(&text);
+                continue;
+            }
+            Event::Start(Tag::CodeBlock(_)) => in_code_block = true,
+            Event::End(pulldown_cmark::TagEnd::CodeBlock) => in_code_block = false,
+            _ => {}
+        }
+        flush_text(&mut pending_text, &mut events, &resolve);
+        events.push(event);
+    }
+    flush_text(&mut pending_text, &mut events, &resolve);
+
+    let mut output = String::new();
+    html::push_html(&mut output, events.into_iter());
+    output
+}
+
+fn sanitize_event(event: Event) -> Event {
+    match event {
+        // Source HTML is shown literally so `<script>` and friends can never execute
+        Event::Html(raw) | Event::InlineHtml(raw) => Event::Text(raw),
+        Event::Start(Tag::Link { link_type, dest_url, title, id }) => Event::Start(Tag::Link {
+            link_type,
+            dest_url: sanitize_url(dest_url),
+            title,
+            id,
+        }),
+        Event::Start(Tag::Image { link_type, dest_url, title, id }) => Event::Start(Tag::Image {
+            link_type,
+            dest_url: sanitize_url(dest_url),
+            title,
+            id,
+        }),
+        other => other,
+    }
+}
+
+fn sanitize_url(url: CowStr) -> CowStr {
+    let lowered = url.trim().to_lowercase();
+    // Relative paths have no scheme and are fine; anything with a scheme must be allow-listed
+    let has_scheme = lowered
+        .find(':')
+        .map(|i| !lowered[..i].contains('/'))
+        .unwrap_or(false);
+    if !has_scheme || SAFE_SCHEMES.iter().any(|s| lowered.starts_with(s)) {
+        url
+    } else {
+        CowStr::Borrowed("#")
+    }
+}
+
+// Emit accumulated text, turning any `[[wiki-links]]` into anchors
+fn flush_text<F>(pending: &mut String, events: &mut Vec<Event>, resolve: &F)
+where
+    F: Fn(&str) -> Option<String>,
+{
+    if pending.is_empty() {
+        return;
+    }
+    let text = std::mem::take(pending);
+    let mut rest = text.as_str();
+    while let Some(link) = find_wiki_link(rest) {
+        if link.start > 0 {
+            events.push(Event::Text(CowStr::from(rest[..link.start].to_string())));
+        }
+        let (open, close) = match resolve(link.target) {
+            Some(id) => (
+                format!(
+                    "<a class=\"wiki-link\" href=\"#note-{id}\" data-note-id=\"{id}\">",
+                    id = escape_attr(&id)
+                ),
+                "</a>",
+            ),
+            None => ("<span class=\"wiki-link unresolved\">".to_string(), "</span>"),
+        };
+        events.push(Event::InlineHtml(CowStr::from(open)));
+        events.push(Event::Text(CowStr::from(link.label.to_string())));
+        events.push(Event::InlineHtml(CowStr::Borrowed(close)));
+        rest = &rest[link.end..];
+    }
+    if !rest.is_empty() {
+        events.push(Event::Text(CowStr::from(rest.to_string())));
+    }
+}
+
+fn escape_attr(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('"', "&quot;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn toggling_twice_restores_the_note() {
+        let content = "Buy milk\n";
+        let tagged = toggle_tag(content, "errand");
+        assert_eq!(tagged, "Buy milk\n\n#errand\n");
+        assert_eq!(tags(&tagged), ["errand"]);
+        let untagged = toggle_tag(&tagged, "errand");
+        assert!(tags(&untagged).is_empty());
+        assert_eq!(untagged.trim_end(), "Buy milk");
+    }
+
+    #[test]
+    fn toggling_matches_tags_case_insensitively() {
+        let untagged = toggle_tag("Plan #Work and #work/later\n#WORK #home\n", "work");
+        assert_eq!(tags(&untagged), ["work/later", "home"]);
+        // Added to an existing trailing line of tags rather than on a new one
+        assert_eq!(toggle_tag("Text\n#home", "work"), "Text\n#home #work\n");
+    }
+
+    #[test]
+    fn tags_are_normalized_or_rejected() {
+        assert_eq!(normalize_tag(" #Food/ ").as_deref(), Some("food"));
+        assert_eq!(normalize_tag("Work/Q3-plan_2").as_deref(), Some("work/q3-plan_2"));
+        assert_eq!(normalize_tag("#42"), None);
+        assert_eq!(normalize_tag("two words"), None);
+        assert_eq!(normalize_tag("#"), None);
+    }
+
+    #[test]
+    fn links_become_their_labels() {
+        assert_eq!(strip_links_and_tags("See [[Plans]] and [[Plans 2024|this year]]."), "See Plans and this year.");
+        assert_eq!(strip_links_and_tags("Not a link: [[ ]] or [[open"), "Not a link: [[ ]] or [[open");
+    }
+
+    #[test]
+    fn tags_are_removed() {
+        assert_eq!(strip_links_and_tags("Buy milk #shopping/weekly today"), "Buy milk  today");
+        assert_eq!(strip_links_and_tags("(#todo) done"), "() done");
+        assert!(tags(&strip_links_and_tags("#a #b-c text #d")).is_empty());
+    }
+
+    #[test]
+    fn hashes_that_are_not_tags_stay() {
+        assert_eq!(strip_links_and_tags("Issue #42, C# and a#b"), "Issue #42, C# and a#b");
+    }
+}
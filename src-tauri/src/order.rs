@@ -0,0 +1,97 @@
+use crate::Note;
+use std::collections::{HashMap, HashSet};
+use std::fs::create_dir_all;
+use std::path::PathBuf;
+
+// The manual ordering used by SortOrder::Manual, as a list of note ids
+fn order_path() -> PathBuf {
+    let dir = crate::config::load_config().vault_data_dir();
+    create_dir_all(&dir).ok();
+    dir.join("order.json")
+}
+
+// Load the saved order, or an empty one if there isn't one yet
+pub fn load_order() -> Vec<String> {
+    std::fs::read_to_string(order_path())
+        .ok()
+        .and_then(|contents| serde_json::from_str(&contents).ok())
+        .unwrap_or_default()
+}
+
+pub fn save_order(ids: &[String]) -> Result<(), String> {
+    let contents = serde_json::to_string(ids).map_err(|e| e.to_string())?;
+    std::fs::write(order_path(), contents).map_err(|e| e.to_string())
+}
+
+// Drop a deleted note from the saved order
+pub fn forget(id: &str) -> Result<(), String> {
+    let mut ids = load_order();
+    let before = ids.len();
+    ids.retain(|other| other != id);
+    if ids.len() == before {
+        return Ok(());
+    }
+    save_order(&ids)
+}
+
+// Put `notes` in the order of `ids`. Notes missing from `ids` keep their relative
+// order and go at the end; ids with no matching note are ignored.
+pub fn apply(notes: &mut [Note], ids: &[String]) {
+    let positions: HashMap<&str, usize> = ids.iter().enumerate().map(|(i, id)| (id.as_str(), i)).collect();
+    notes.sort_by_key(|note| positions.get(note.id.as_str()).copied().unwrap_or(usize::MAX));
+}
+
+// Dedupe `ids`, keeping the first occurrence, and drop ids that don't name an existing note
+pub fn normalize(ids: Vec<String>, existing: &HashSet<String>) -> Vec<String> {
+    let mut seen = HashSet::new();
+    ids.into_iter()
+        .filter(|id| existing.contains(id) && seen.insert(id.clone()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn notes(ids: &[&str]) -> Vec<Note> {
+        ids.iter().map(|id| Note { id: id.to_string(), ..Default::default() }).collect()
+    }
+
+    fn ids(notes: &[Note]) -> Vec<&str> {
+        notes.iter().map(|note| note.id.as_str()).collect()
+    }
+
+    fn strings(ids: &[&str]) -> Vec<String> {
+        ids.iter().map(|id| id.to_string()).collect()
+    }
+
+    #[test]
+    fn full_order_is_followed() {
+        let mut listed = notes(&["a", "b", "c"]);
+        apply(&mut listed, &strings(&["c", "a", "b"]));
+        assert_eq!(ids(&listed), ["c", "a", "b"]);
+    }
+
+    #[test]
+    fn unordered_notes_follow_in_their_listed_order() {
+        let mut listed = notes(&["a", "b", "c", "d"]);
+        apply(&mut listed, &strings(&["d", "b"]));
+        assert_eq!(ids(&listed), ["d", "b", "a", "c"]);
+    }
+
+    #[test]
+    fn ids_of_missing_notes_are_ignored() {
+        let mut listed = notes(&["a", "b"]);
+        apply(&mut listed, &strings(&["gone", "b"]));
+        assert_eq!(ids(&listed), ["b", "a"]);
+        let mut listed = notes(&["a", "b"]);
+        apply(&mut listed, &[]);
+        assert_eq!(ids(&listed), ["a", "b"]);
+    }
+
+    #[test]
+    fn normalize_drops_unknown_and_repeated_ids() {
+        let existing: HashSet<String> = strings(&["a", "b", "c"]).into_iter().collect();
+        assert_eq!(normalize(strings(&["b", "gone", "a", "b"]), &existing), ["b", "a"]);
+    }
+}
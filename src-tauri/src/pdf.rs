@@ -0,0 +1,369 @@
+use crate::Note;
+use printpdf::{BuiltinFont, Image, ImageTransform, IndirectFontRef, Mm, PdfDocument, PdfDocumentReference, PdfLayerReference};
+use pulldown_cmark::{Event, Options, Parser, Tag, TagEnd};
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+// A4 page with uniform margins, in millimetres
+const PAGE_WIDTH: f32 = 210.0;
+const PAGE_HEIGHT: f32 = 297.0;
+const MARGIN: f32 = 20.0;
+
+const PT_TO_MM: f32 = 0.3528;
+// Line height relative to font size
+const LEADING: f32 = 1.4;
+// Indent per list level
+const LIST_INDENT: f32 = 6.0;
+// Resolution images are laid out at before being shrunk to fit the page
+const IMAGE_DPI: f32 = 150.0;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Style {
+    Body,
+    Heading(u8),
+    Code,
+}
+
+impl Style {
+    fn font_size(self) -> f32 {
+        match self {
+            Style::Heading(1) => 20.0,
+            Style::Heading(2) => 16.0,
+            Style::Heading(_) => 13.0,
+            Style::Body => 11.0,
+            Style::Code => 9.5,
+        }
+    }
+
+    // Average glyph width as a fraction of the font size, used for line wrapping
+    fn char_width(self) -> f32 {
+        match self {
+            Style::Code => 0.6,
+            Style::Heading(_) => 0.55,
+            Style::Body => 0.5,
+        }
+    }
+}
+
+// One laid-out unit of the note: a heading, paragraph, list item, code block or image.
+// For images `image` is the source as written in the note and `text` its alt text.
+struct Block {
+    style: Style,
+    text: String,
+    depth: usize,
+    bullet: Option<String>,
+    image: Option<String>,
+}
+
+// Flatten markdown into blocks. Inline formatting is dropped; images get blocks of their own.
+fn blocks(content: &str) -> Vec<Block> {
+    let mut options = Options::empty();
+    options.insert(Options::ENABLE_TABLES);
+    options.insert(Options::ENABLE_STRIKETHROUGH);
+    options.insert(Options::ENABLE_TASKLISTS);
+
+    let mut blocks = Vec::new();
+    let mut current: Option<Block> = None;
+    // Next number for each open list; None for bullet lists
+    let mut lists: Vec<Option<u64>> = Vec::new();
+
+    let flush = |current: &mut Option<Block>, blocks: &mut Vec<Block>| {
+        if let Some(block) = current.take() {
+            if !block.text.trim().is_empty() || block.bullet.is_some() || block.image.is_some() {
+                blocks.push(block);
+            }
+        }
+    };
+    let start = |style: Style, bullet: Option<String>, depth: usize| Some(Block { style, text: String::new(), depth, bullet, image: None });
+
+    for event in Parser::new_ext(content, options) {
+        match event {
+            Event::Start(Tag::Heading { level, .. }) => {
+                flush(&mut current, &mut blocks);
+                current = start(Style::Heading(level as u8), None, 0);
+            }
+            Event::Start(Tag::CodeBlock(_)) => {
+                flush(&mut current, &mut blocks);
+                current = start(Style::Code, None, lists.len());
+            }
+            // A paragraph inside a list item continues the item's block
+            Event::Start(Tag::Paragraph)
+                if current.as_ref().is_none_or(|block| block.bullet.is_none() || !block.text.is_empty()) =>
+            {
+                flush(&mut current, &mut blocks);
+                current = start(Style::Body, None, lists.len());
+            }
+            Event::Start(Tag::List(first)) => {
+                flush(&mut current, &mut blocks);
+                lists.push(first);
+            }
+            Event::End(TagEnd::List(_)) => {
+                flush(&mut current, &mut blocks);
+                lists.pop();
+            }
+            Event::Start(Tag::Item) => {
+                flush(&mut current, &mut blocks);
+                let bullet = match lists.last_mut() {
+                    Some(Some(n)) => {
+                        *n += 1;
+                        format!("{}.", *n - 1)
+                    }
+                    _ => "-".to_string(),
+                };
+                current = start(Style::Body, Some(bullet), lists.len().saturating_sub(1));
+            }
+            Event::End(TagEnd::Heading(_) | TagEnd::Paragraph | TagEnd::CodeBlock | TagEnd::Item | TagEnd::Image) => {
+                flush(&mut current, &mut blocks);
+            }
+            // The text that follows is the image's alt text, up to its end
+            Event::Start(Tag::Image { dest_url, .. }) => {
+                flush(&mut current, &mut blocks);
+                current = Some(Block { style: Style::Body, text: String::new(), depth: lists.len(), bullet: None, image: Some(dest_url.to_string()) });
+            }
+            Event::Text(text) | Event::Code(text) => {
+                current
+                    .get_or_insert_with(|| Block { style: Style::Body, text: String::new(), depth: lists.len(), bullet: None, image: None })
+                    .text
+                    .push_str(&text);
+            }
+            Event::TaskListMarker(done) => {
+                if let Some(block) = current.as_mut() {
+                    block.text.push_str(if done { "[x] " } else { "[ ] " });
+                }
+            }
+            Event::SoftBreak => {
+                if let Some(block) = current.as_mut() {
+                    block.text.push(' ');
+                }
+            }
+            Event::HardBreak => {
+                if let Some(block) = current.as_mut() {
+                    block.text.push('\n');
+                }
+            }
+            Event::End(TagEnd::TableCell) => {
+                if let Some(block) = current.as_mut() {
+                    block.text.push_str(" | ");
+                }
+            }
+            Event::End(TagEnd::TableRow | TagEnd::TableHead) => flush(&mut current, &mut blocks),
+            _ => {}
+        }
+    }
+    flush(&mut current, &mut blocks);
+    blocks
+}
+
+// Break `text` into lines of at most `max_chars` characters, on spaces where possible
+fn wrap(text: &str, max_chars: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+    for paragraph in text.split('\n') {
+        let mut line = String::new();
+        for word in paragraph.split_whitespace() {
+            let mut word = word.to_string();
+            // Hard-split words that can't fit on a line of their own
+            while word.chars().count() > max_chars {
+                if !line.is_empty() {
+                    lines.push(std::mem::take(&mut line));
+                }
+                let split = word.char_indices().nth(max_chars).map(|(i, _)| i).unwrap_or(word.len());
+                lines.push(word[..split].to_string());
+                word = word[split..].to_string();
+            }
+            if !line.is_empty() && line.chars().count() + 1 + word.chars().count() > max_chars {
+                lines.push(std::mem::take(&mut line));
+            }
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line.push_str(&word);
+        }
+        lines.push(line);
+    }
+    lines
+}
+
+// Tracks the write position and starts new pages as needed
+struct Writer {
+    doc: PdfDocumentReference,
+    layer: PdfLayerReference,
+    y: f32,
+    regular: IndirectFontRef,
+    bold: IndirectFontRef,
+    mono: IndirectFontRef,
+}
+
+impl Writer {
+    // Move down by `height`, first starting a new page if it doesn't fit on this one
+    fn advance(&mut self, height: f32) {
+        if self.y - height < MARGIN {
+            let (page, layer) = self.doc.add_page(Mm(PAGE_WIDTH), Mm(PAGE_HEIGHT), "Layer 1");
+            self.layer = self.doc.get_page(page).get_layer(layer);
+            self.y = PAGE_HEIGHT - MARGIN;
+        }
+        self.y -= height;
+    }
+
+    fn line(&mut self, text: &str, style: Style, x: f32) {
+        self.advance(style.font_size() * PT_TO_MM * LEADING);
+        let font = match style {
+            Style::Body => &self.regular,
+            Style::Heading(_) => &self.bold,
+            Style::Code => &self.mono,
+        };
+        self.layer.use_text(text, style.font_size(), Mm(x), Mm(self.y), font);
+    }
+
+    // Draw the image file at `path` at `x`, shrunk to `max_width` and to the page height
+    fn image(&mut self, path: &Path, x: f32, max_width: f32) -> Result<(), String> {
+        let decoded = printpdf::image_crate::open(path).map_err(|e| e.to_string())?;
+        let image = Image::from_dynamic_image(&decoded);
+        let to_mm = |px: usize| px as f32 * 25.4 / IMAGE_DPI;
+        let (width, height) = (to_mm(image.image.width.0), to_mm(image.image.height.0));
+        if width <= 0.0 || height <= 0.0 {
+            return Err("image is empty".to_string());
+        }
+        let scale = (max_width / width).min((PAGE_HEIGHT - 2.0 * MARGIN) / height).min(1.0);
+        self.advance(height * scale);
+        let transform = ImageTransform {
+            translate_x: Some(Mm(x)),
+            translate_y: Some(Mm(self.y)),
+            scale_x: Some(scale),
+            scale_y: Some(scale),
+            dpi: Some(IMAGE_DPI),
+            ..Default::default()
+        };
+        image.add_to_layer(self.layer.clone(), transform);
+        Ok(())
+    }
+
+    fn gap(&mut self, mm: f32) {
+        self.y -= mm;
+    }
+}
+
+// The file an image source refers to: absolute paths and file:// URLs as they are, other
+// paths relative to `base`. None for remote images, which aren't fetched.
+fn local_image(src: &str, base: &Path) -> Option<PathBuf> {
+    let src = src.strip_prefix("file://").unwrap_or(src);
+    if src.is_empty() || src.contains("://") || src.starts_with("data:") {
+        return None;
+    }
+    Some(base.join(src))
+}
+
+// Render a note to a PDF at `dest`. Uses the built-in PDF fonts, which only cover
+// Windows-1252, so characters outside it are left out. Images stored locally (relative
+// paths are taken from `image_dir`) are embedded; remote or unreadable ones are replaced
+// by an "[image: ...]" line.
+pub fn export_note(note: &Note, dest: &Path, image_dir: &Path) -> Result<(), String> {
+    let (doc, page, layer) = PdfDocument::new(note.title.as_str(), Mm(PAGE_WIDTH), Mm(PAGE_HEIGHT), "Layer 1");
+    let font = |f| doc.add_builtin_font(f).map_err(|e| e.to_string());
+    let (regular, bold, mono) = (font(BuiltinFont::Helvetica)?, font(BuiltinFont::HelveticaBold)?, font(BuiltinFont::Courier)?);
+    let layer = doc.get_page(page).get_layer(layer);
+    let mut writer = Writer { doc, layer, y: PAGE_HEIGHT - MARGIN, regular, bold, mono };
+
+    let mut all = vec![Block { style: Style::Heading(1), text: note.title.clone(), depth: 0, bullet: None, image: None }];
+    all.extend(blocks(&note.content));
+    for mut block in all {
+        let indent = block.depth as f32 * LIST_INDENT + if block.bullet.is_some() { LIST_INDENT } else { 0.0 };
+        let x = MARGIN + indent;
+        let width = PAGE_WIDTH - MARGIN - x;
+        if let Some(src) = &block.image {
+            let embedded = match local_image(src, image_dir).map(|path| (writer.image(&path, x, width), path)) {
+                Some((Ok(()), _)) => true,
+                Some((Err(e), path)) => {
+                    eprintln!("Can't embed image {}: {}", path.display(), e);
+                    false
+                }
+                None => false,
+            };
+            if embedded {
+                writer.gap(2.0);
+                continue;
+            }
+            let label = if block.text.trim().is_empty() { src.clone() } else { block.text.trim().to_string() };
+            block.text = format!("[image: {}]", label);
+        }
+        let max_chars = (width / (block.style.font_size() * PT_TO_MM * block.style.char_width())) as usize;
+        let lines = if block.style == Style::Code {
+            block.text.trim_end_matches('\n').lines().flat_map(|line| wrap_code(line, max_chars)).collect()
+        } else {
+            wrap(block.text.trim(), max_chars.max(1))
+        };
+        if let Style::Heading(_) = block.style {
+            writer.gap(2.0);
+        }
+        for (i, line) in lines.iter().enumerate() {
+            if i == 0 {
+                if let Some(bullet) = &block.bullet {
+                    writer.line(line, block.style, x);
+                    // Bullet sits in the indent, on the same baseline as the first line
+                    writer.layer.use_text(bullet.as_str(), block.style.font_size(), Mm(x - LIST_INDENT), Mm(writer.y), &writer.regular);
+                    continue;
+                }
+            }
+            writer.line(line, block.style, x);
+        }
+        writer.gap(2.0);
+    }
+
+    let file = File::create(dest).map_err(|e| e.to_string())?;
+    writer.doc.save(&mut BufWriter::new(file)).map_err(|e| e.to_string())
+}
+
+// Code keeps its own line breaks and spacing; long lines are cut rather than re-flowed
+fn wrap_code(line: &str, max_chars: usize) -> Vec<String> {
+    let chars: Vec<char> = line.chars().collect();
+    if chars.is_empty() {
+        return vec![String::new()];
+    }
+    chars.chunks(max_chars.max(1)).map(|chunk| chunk.iter().collect()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn note(content: &str) -> Note {
+        Note { title: "Trip".to_string(), content: content.to_string(), ..Default::default() }
+    }
+
+    fn export(content: &str, image_dir: &Path) -> String {
+        let dest = image_dir.join("out.pdf");
+        export_note(&note(content), &dest, image_dir).unwrap();
+        String::from_utf8_lossy(&std::fs::read(dest).unwrap()).into_owned()
+    }
+
+    #[test]
+    fn images_get_blocks_of_their_own() {
+        let blocks = blocks("Before ![the view](view.png) after");
+        let shown: Vec<(Option<&str>, &str)> = blocks.iter().map(|block| (block.image.as_deref(), block.text.trim())).collect();
+        assert_eq!(shown, [(None, "Before"), (Some("view.png"), "the view"), (None, "after")]);
+    }
+
+    #[test]
+    fn only_local_images_are_resolved() {
+        let base = Path::new("/vault/notes");
+        assert_eq!(local_image("img/view.png", base), Some(PathBuf::from("/vault/notes/img/view.png")));
+        assert_eq!(local_image("/pics/view.png", base), Some(PathBuf::from("/pics/view.png")));
+        assert_eq!(local_image("file:///pics/view.png", base), Some(PathBuf::from("/pics/view.png")));
+        assert_eq!(local_image("https://example.com/view.png", base), None);
+        assert_eq!(local_image("data:image/png;base64,AAAA", base), None);
+    }
+
+    #[test]
+    fn local_images_are_embedded_and_missing_ones_replaced() {
+        let dir = tempfile::tempdir().unwrap();
+        printpdf::image_crate::RgbImage::from_pixel(40, 20, printpdf::image_crate::Rgb([200, 30, 30]))
+            .save(dir.path().join("view.png"))
+            .unwrap();
+        let pdf = export("![the view](view.png)", dir.path());
+        assert!(pdf.contains("/Subtype /Image") || pdf.contains("/Subtype/Image"));
+        assert!(!pdf.contains("[image:"));
+
+        let pdf = export("![the view](missing.png)", dir.path());
+        assert!(!pdf.contains("/Subtype /Image") && !pdf.contains("/Subtype/Image"));
+    }
+}
@@ -0,0 +1,225 @@
+use crate::commands::{get_note, list_notes};
+use crate::{update_note_locked, Note, NoteError, NoteState, NoteSummary};
+use chrono::{DateTime, Duration, Utc};
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use tauri::{AppHandle, Emitter};
+
+// How often the background check looks for due reminders
+const CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(30);
+
+// Shortest and longest repeat a reminder may have. Anything shorter than the check interval
+// would fire on every check.
+const MIN_INTERVAL_SECS: u64 = 60;
+const MAX_INTERVAL_SECS: u64 = 10 * 366 * 24 * 60 * 60;
+
+type DueIndex = HashMap<String, DateTime<Utc>>;
+
+// Reminder time of every note that has one, so the scheduler doesn't parse the whole vault on
+// each check. Built from the notes on first use and kept current by every save and delete.
+static DUE_INDEX: Lazy<Mutex<Option<DueIndex>>> = Lazy::new(|| Mutex::new(None));
+
+// Parse a reminder time, rejecting anything that isn't RFC 3339
+pub fn parse_time(when: &str) -> Result<DateTime<Utc>, String> {
+    DateTime::parse_from_rfc3339(when)
+        .map(|t| t.with_timezone(&Utc))
+        .map_err(|e| format!("Invalid reminder time {:?}: {}", when, e))
+}
+
+// Reject repeat intervals the scheduler can't honour
+pub fn check_interval(secs: u64) -> Result<(), String> {
+    if (MIN_INTERVAL_SECS..=MAX_INTERVAL_SECS).contains(&secs) {
+        Ok(())
+    } else {
+        Err(format!(
+            "Reminder interval must be between {} and {} seconds",
+            MIN_INTERVAL_SECS, MAX_INTERVAL_SECS
+        ))
+    }
+}
+
+// A note's reminder time. Reminders on trashed notes never fire.
+fn reminder_time(note: &Note) -> Option<DateTime<Utc>> {
+    if note.state == NoteState::Trashed {
+        return None;
+    }
+    note.reminder_at.as_deref().and_then(|when| parse_time(when).ok())
+}
+
+// Record a saved note's reminder time (or its lack of one) in the due index
+pub fn track(note: &Note) {
+    let mut index = DUE_INDEX.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(index) = index.as_mut() {
+        match reminder_time(note) {
+            Some(at) => index.insert(note.id.clone(), at),
+            None => index.remove(&note.id),
+        };
+    }
+}
+
+// Drop a deleted note from the due index
+pub fn untrack(id: &str) {
+    let mut index = DUE_INDEX.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(index) = index.as_mut() {
+        index.remove(id);
+    }
+}
+
+// Forget the due index so it's rebuilt from the notes on the next check
+pub fn reset_index() {
+    *DUE_INDEX.lock().unwrap_or_else(|e| e.into_inner()) = None;
+}
+
+// Ids of notes whose indexed reminder time is at or before `now`
+fn due_ids(now: DateTime<Utc>) -> Vec<String> {
+    let mut index = DUE_INDEX.lock().unwrap_or_else(|e| e.into_inner());
+    let index = index.get_or_insert_with(|| {
+        list_notes(None)
+            .iter()
+            .filter_map(|note| Some((note.id.clone(), reminder_time(note)?)))
+            .collect()
+    });
+    index.iter().filter(|(_, &at)| at <= now).map(|(id, _)| id.clone()).collect()
+}
+
+// When a note's reminder is due, if it has one that has come due by `now`
+fn due_at(note: &Note, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    reminder_time(note).filter(|&at| at <= now)
+}
+
+// The first occurrence after `now` of a reminder repeating every `interval_secs` from `at`.
+// Occurrences missed while the app was closed are skipped rather than fired one by one.
+// None for one-off reminders and for times too far out to represent.
+fn next_occurrence(at: DateTime<Utc>, interval_secs: Option<u64>, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    let secs = i64::try_from(interval_secs?).ok().filter(|&secs| secs > 0)?;
+    let occurrences = now.signed_duration_since(at).num_seconds().max(0) / secs + 1;
+    let step = Duration::try_seconds(secs.checked_mul(occurrences)?)?;
+    at.checked_add_signed(step)
+}
+
+// Notes whose reminder time has passed but that haven't fired yet, without firing them
+pub fn list_due() -> Vec<Note> {
+    let now = Utc::now();
+    due_ids(now)
+        .into_iter()
+        .filter_map(|id| get_note(id).ok())
+        .filter(|note| due_at(note, now).is_some())
+        .collect()
+}
+
+// Fire every reminder whose time has passed and return the notes they belong to.
+// One-off reminders are cleared; recurring ones move to their next future occurrence.
+// Each note is re-read under its lock and only its reminder fields are changed, so an edit
+// saved since the index was read isn't lost.
+pub fn fire_due() -> Vec<Note> {
+    let now = Utc::now();
+    let mut due = Vec::new();
+    for id in due_ids(now) {
+        let mut fired = false;
+        let result = update_note_locked(&id, |note| {
+            // The reminder may have been moved or cleared since the index was read
+            let Some(at) = due_at(note, now) else {
+                return Ok(false);
+            };
+            note.reminder_at = next_occurrence(at, note.reminder_interval_secs, now).map(|next| next.to_rfc3339());
+            fired = true;
+            Ok(true)
+        });
+        match result {
+            Ok(note) if fired => due.push(note),
+            Ok(_) => {}
+            Err(NoteError::NotFound(_)) => untrack(&id),
+            Err(e) => eprintln!("Error updating reminder on note {}: {}", id, e),
+        }
+    }
+    due
+}
+
+// Check for due reminders periodically, emitting `reminder-due` with the notes that fired
+pub fn start_scheduler(app: AppHandle) {
+    std::thread::spawn(move || loop {
+        let due = fire_due();
+        if !due.is_empty() {
+            let summaries: Vec<NoteSummary> = due.iter().map(NoteSummary::from).collect();
+            if let Err(e) = app.emit("reminder-due", summaries) {
+                eprintln!("Error emitting reminder-due: {}", e);
+            }
+        }
+        std::thread::sleep(CHECK_INTERVAL);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time(s: &str) -> DateTime<Utc> {
+        parse_time(s).unwrap()
+    }
+
+    #[test]
+    fn one_off_reminders_have_no_next_occurrence() {
+        let at = time("2024-01-01T09:00:00Z");
+        assert_eq!(next_occurrence(at, None, time("2024-01-01T09:00:30Z")), None);
+    }
+
+    #[test]
+    fn recurring_reminders_skip_missed_occurrences() {
+        let at = time("2024-01-01T09:00:00Z");
+        let now = time("2024-01-03T10:00:00Z");
+        let next = next_occurrence(at, Some(24 * 60 * 60), now);
+        assert_eq!(next, Some(time("2024-01-04T09:00:00Z")));
+    }
+
+    #[test]
+    fn next_occurrence_is_always_after_now() {
+        let at = time("2024-01-01T09:00:00Z");
+        assert_eq!(next_occurrence(at, Some(60), at), Some(time("2024-01-01T09:01:00Z")));
+    }
+
+    #[test]
+    fn overflowing_intervals_clear_the_reminder() {
+        let at = time("2024-01-01T09:00:00Z");
+        assert_eq!(next_occurrence(at, Some(u64::MAX), time("2024-01-02T09:00:00Z")), None);
+        assert_eq!(next_occurrence(at, Some(i64::MAX as u64), at), None);
+    }
+
+    #[test]
+    fn intervals_are_bounded() {
+        assert!(check_interval(0).is_err());
+        assert!(check_interval(MIN_INTERVAL_SECS - 1).is_err());
+        assert!(check_interval(MIN_INTERVAL_SECS).is_ok());
+        assert!(check_interval(MAX_INTERVAL_SECS).is_ok());
+        assert!(check_interval(MAX_INTERVAL_SECS + 1).is_err());
+    }
+
+    #[test]
+    fn only_past_reminders_are_due() {
+        let now = time("2024-01-01T09:00:00Z");
+        let note = |when: &str| Note { reminder_at: Some(when.to_string()), ..Default::default() };
+        assert!(due_at(&note("2024-01-01T08:59:59Z"), now).is_some());
+        assert!(due_at(&note("2024-01-01T09:00:01Z"), now).is_none());
+        assert!(due_at(&note("not a time"), now).is_none());
+    }
+    #[test]
+    fn cleared_and_trashed_reminders_are_never_due() {
+        let now = time("2024-01-01T09:00:00Z");
+        let reminded = |id: &str, when: Option<&str>, state: NoteState| Note {
+            id: id.to_string(),
+            reminder_at: when.map(str::to_string),
+            state,
+            ..Default::default()
+        };
+        let notes = [
+            reminded("exactly-now", Some("2024-01-01T09:00:00Z"), NoteState::Active),
+            // 08:30 UTC, so due even though its local time is later than now
+            reminded("other-zone", Some("2024-01-01T10:30:00+02:00"), NoteState::Pinned),
+            reminded("cleared", None, NoteState::Active),
+            reminded("trashed", Some("2024-01-01T08:00:00Z"), NoteState::Trashed),
+            reminded("tomorrow", Some("2024-01-02T09:00:00Z"), NoteState::Active),
+        ];
+        let due: Vec<&str> = notes.iter().filter(|note| due_at(note, now).is_some()).map(|note| note.id.as_str()).collect();
+        assert_eq!(due, ["exactly-now", "other-zone"]);
+    }
+}
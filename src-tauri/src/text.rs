@@ -0,0 +1,546 @@
+use crate::Note;
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+use unicode_segmentation::UnicodeSegmentation;
+
+// Inverted full-text index over every note's title and content, kept up to date by
+// save/delete and rebuilt in full by rebuild_text_index
+pub static TEXT_INDEX: Lazy<Mutex<TextIndex>> = Lazy::new(|| Mutex::new(TextIndex::new(stop_words())));
+
+// Held for the whole of a rebuild, so rebuilds run one at a time and the last one started
+// (with the latest stop words) is the one left in place
+static REBUILD: Lazy<Mutex<()>> = Lazy::new(|| Mutex::new(()));
+
+// Common English words left out of the index unless the user configures their own list
+pub const DEFAULT_STOP_WORDS: [&str; 35] = [
+    "a", "an", "and", "are", "as", "at", "be", "but", "by", "for", "from", "has", "have", "i",
+    "if", "in", "into", "is", "it", "its", "of", "on", "or", "so", "that", "the", "their",
+    "then", "there", "this", "to", "was", "were", "will", "with",
+];
+
+// The configured stop words, or the defaults, in the form tokenize produces
+pub fn stop_words() -> HashSet<String> {
+    stop_words_from(crate::config::load_config().stop_words.as_deref())
+}
+
+fn stop_words_from(configured: Option<&[String]>) -> HashSet<String> {
+    match configured {
+        Some(words) => words.iter().flat_map(|word| tokenize(word)).collect(),
+        None => DEFAULT_STOP_WORDS.iter().map(|word| word.to_string()).collect(),
+    }
+}
+
+// The stop words TEXT_INDEX was built with, so everything else that skips stop words skips
+// the same ones as search does
+pub fn active_stop_words() -> HashSet<String> {
+    TEXT_INDEX.lock().unwrap_or_else(|e| e.into_inner()).stop_words.clone()
+}
+
+// Split text into lowercase, accent-folded words at Unicode word boundaries. Numbers are
+// words too, and scripts written without spaces (CJK) come out one character per word.
+pub fn tokenize(text: &str) -> Vec<String> {
+    crate::language::fold(text).unicode_words().map(str::to_string).collect()
+}
+
+// tokenize, minus any word in `stop_words`
+pub fn tokenize_without(text: &str, stop_words: &HashSet<String>) -> Vec<String> {
+    tokenize(text).into_iter().filter(|token| !stop_words.contains(token)).collect()
+}
+
+// The `n` words that best set `group` apart from the rest of `all`: frequent in the group's
+// notes, rare elsewhere (document frequency weighted by inverse document frequency). Words
+// shorter than three characters, pure numbers and `stop_words` are skipped.
+pub fn distinctive_terms(group: &[&Note], all: &[Note], n: usize, stop_words: &HashSet<String>) -> Vec<String> {
+    let document_terms = |note: &Note| -> HashSet<String> {
+        tokenize_without(&format!("{} {}", note.title, note.content), stop_words)
+            .into_iter()
+            .filter(|term| term.chars().count() >= 3 && !term.chars().all(|c| c.is_numeric()))
+            .collect()
+    };
+    let mut vault_frequency: HashMap<String, usize> = HashMap::new();
+    for note in all {
+        for term in document_terms(note) {
+            *vault_frequency.entry(term).or_default() += 1;
+        }
+    }
+    let mut group_frequency: HashMap<String, usize> = HashMap::new();
+    for note in group {
+        for term in document_terms(note) {
+            *group_frequency.entry(term).or_default() += 1;
+        }
+    }
+    let total = all.len().max(1) as f32;
+    let mut scored: Vec<(String, f32)> = group_frequency
+        .into_iter()
+        .map(|(term, count)| {
+            let vault_count = vault_frequency.get(&term).copied().unwrap_or(count).max(1) as f32;
+            let score = count as f32 * (1.0 + (total / vault_count).ln());
+            (term, score)
+        })
+        .collect();
+    // Ties break alphabetically so labels don't change from run to run
+    scored.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    scored.into_iter().take(n).map(|(term, _)| term).collect()
+}
+
+#[derive(Serialize, Clone, Copy, Debug)]
+pub struct TextIndexStats {
+    // Distinct tokens
+    pub tokens: usize,
+    pub documents: usize,
+}
+
+// What the index holds for one note, so its postings can be dropped when it changes
+struct IndexedNote {
+    tokens: HashSet<String>,
+    stems: HashSet<String>,
+}
+
+pub struct TextIndex {
+    stop_words: HashSet<String>,
+    // Token -> ids of the notes containing it
+    postings: HashMap<String, HashSet<String>>,
+    // "{language}:{stem}" -> ids of the notes in that language with a word of that stem
+    stem_postings: HashMap<String, HashSet<String>>,
+    // Every language a note in the index has been stemmed in
+    languages: HashSet<String>,
+    documents: HashMap<String, IndexedNote>,
+    // Whether every note has been added, so searches can rely on the index
+    complete: bool,
+    // Saves and deletes (None) made while a rebuild is running, to replay onto its result
+    changed_during_rebuild: Option<HashMap<String, Option<Note>>>,
+}
+
+impl TextIndex {
+    pub fn new(stop_words: HashSet<String>) -> Self {
+        TextIndex {
+            stop_words,
+            postings: HashMap::new(),
+            stem_postings: HashMap::new(),
+            languages: HashSet::new(),
+            documents: HashMap::new(),
+            complete: false,
+            changed_during_rebuild: None,
+        }
+    }
+
+    // Index a note, replacing whatever was indexed for it before
+    pub fn update(&mut self, note: &Note) {
+        if let Some(changed) = self.changed_during_rebuild.as_mut() {
+            changed.insert(note.id.clone(), Some(note.clone()));
+        }
+        self.unindex(&note.id);
+        let tokens: HashSet<String> = tokenize_without(&note.title, &self.stop_words)
+            .into_iter()
+            .chain(tokenize_without(&note.content, &self.stop_words))
+            .collect();
+        for token in &tokens {
+            self.postings.entry(token.clone()).or_default().insert(note.id.clone());
+        }
+        // Stems come from every word, stop words included, the way matches_query takes them
+        let mut stems = HashSet::new();
+        if let Some((language, stemmer)) = note
+            .language
+            .as_deref()
+            .and_then(|language| Some((language, crate::language::stemmer_for(language)?)))
+        {
+            self.languages.insert(language.to_string());
+            for word in tokenize(&note.title).into_iter().chain(tokenize(&note.content)) {
+                stems.insert(format!("{}:{}", language, stemmer.stem(&word)));
+            }
+        }
+        for stem in &stems {
+            self.stem_postings.entry(stem.clone()).or_default().insert(note.id.clone());
+        }
+        self.documents.insert(note.id.clone(), IndexedNote { tokens, stems });
+    }
+
+    pub fn remove(&mut self, id: &str) {
+        if let Some(changed) = self.changed_during_rebuild.as_mut() {
+            changed.insert(id.to_string(), None);
+        }
+        self.unindex(id);
+    }
+
+    fn unindex(&mut self, id: &str) {
+        let Some(indexed) = self.documents.remove(id) else {
+            return;
+        };
+        for (keys, postings) in [(indexed.tokens, &mut self.postings), (indexed.stems, &mut self.stem_postings)] {
+            for key in keys {
+                if let Some(ids) = postings.get_mut(&key) {
+                    ids.remove(id);
+                    if ids.is_empty() {
+                        postings.remove(&key);
+                    }
+                }
+            }
+        }
+    }
+
+    // Ids of the notes search_notes could match for `query`: each of the query's words appears
+    // inside one of the note's words, or shares a stem with one in the note's language. This
+    // is a superset; candidates still need matching in full. None when the index can't narrow
+    // things down and every note has to be checked: it's still being built, or no query word
+    // is usable (words inside a stop word are unusable, since stop words aren't indexed).
+    pub fn candidates(&self, query: &str) -> Option<HashSet<String>> {
+        if !self.complete {
+            return None;
+        }
+        let words: Vec<String> = tokenize_without(query, &self.stop_words)
+            .into_iter()
+            .filter(|word| !self.stop_words.iter().any(|stop_word| stop_word.contains(word.as_str())))
+            .collect();
+        let mut candidates: Option<HashSet<String>> = None;
+        for word in words {
+            let mut ids: HashSet<String> = self
+                .postings
+                .iter()
+                .filter(|(token, _)| token.contains(word.as_str()))
+                .flat_map(|(_, ids)| ids.iter().cloned())
+                .collect();
+            for language in &self.languages {
+                let Some(stemmer) = crate::language::stemmer_for(language) else {
+                    continue;
+                };
+                if let Some(stemmed) = self.stem_postings.get(&format!("{}:{}", language, stemmer.stem(&word))) {
+                    ids.extend(stemmed.iter().cloned());
+                }
+            }
+            candidates = Some(match candidates {
+                Some(candidates) => candidates.intersection(&ids).cloned().collect(),
+                None => ids,
+            });
+        }
+        candidates
+    }
+
+    pub fn stats(&self) -> TextIndexStats {
+        TextIndexStats { tokens: self.postings.len(), documents: self.documents.len() }
+    }
+
+    // Start recording saves and deletes for finish_rebuild to replay
+    fn begin_rebuild(&mut self) {
+        self.changed_during_rebuild = Some(HashMap::new());
+    }
+
+    // Replace this index with `rebuilt`, first replaying the changes made since begin_rebuild
+    fn finish_rebuild(&mut self, mut rebuilt: TextIndex) {
+        for (id, note) in self.changed_during_rebuild.take().unwrap_or_default() {
+            match note {
+                Some(note) => rebuilt.update(&note),
+                None => rebuilt.remove(&id),
+            }
+        }
+        rebuilt.complete = true;
+        *self = rebuilt;
+    }
+}
+
+// Empty TEXT_INDEX, e.g. when the notes directory changes. Searches scan every note until
+// the next rebuild completes it.
+pub fn reset() {
+    *TEXT_INDEX.lock().unwrap_or_else(|e| e.into_inner()) = TextIndex::new(stop_words());
+}
+
+// Rebuild TEXT_INDEX with the current stop words. `fill` adds every note to the new index
+// without TEXT_INDEX locked, so saves and deletes carry on meanwhile; they're replayed onto
+// the new index before it's swapped in, so none are lost.
+pub fn rebuild(fill: impl FnOnce(&mut TextIndex)) -> TextIndexStats {
+    let _rebuilding = REBUILD.lock().unwrap_or_else(|e| e.into_inner());
+    TEXT_INDEX.lock().unwrap_or_else(|e| e.into_inner()).begin_rebuild();
+    let mut rebuilt = TextIndex::new(stop_words());
+    fill(&mut rebuilt);
+    let mut current = TEXT_INDEX.lock().unwrap_or_else(|e| e.into_inner());
+    current.finish_rebuild(rebuilt);
+    current.stats()
+}
+
+// Excerpt of `content` around the first case-insensitive match of `query`, about `width`
+// characters long with the match centred. The edges are widened to whole words and never
+// fall inside a markdown link or code span; a cut edge is marked with an ellipsis.
+// Content without a literal match (e.g. matched by stem) is excerpted from the start.
+pub fn snippet(content: &str, query: &str, width: usize) -> String {
+    let (match_start, match_end) = find_case_insensitive(content, query).unwrap_or((0, 0));
+    let match_chars = content[match_start..match_end].chars().count();
+    let before = width.saturating_sub(match_chars) / 2;
+
+    // Step back `before` characters from the match, then forward `width` from there
+    let mut start = content[..match_start]
+        .char_indices()
+        .rev()
+        .nth(before.saturating_sub(1))
+        .map(|(i, _)| i)
+        .unwrap_or(0);
+    if before == 0 {
+        start = match_start;
+    }
+    let mut end = content[start..].char_indices().nth(width).map(|(i, _)| start + i).unwrap_or(content.len());
+    // A match near the end gets more context before it instead
+    let shown = content[start..end].chars().count();
+    if shown < width {
+        start = content[..start].char_indices().rev().nth(width - shown - 1).map(|(i, _)| i).unwrap_or(0);
+    }
+
+    // Widen to word boundaries
+    while start > 0 && !content[..start].ends_with(char::is_whitespace) {
+        start = content[..start].char_indices().next_back().map(|(i, _)| i).unwrap_or(0);
+    }
+    while end < content.len() && !content[end..].starts_with(char::is_whitespace) {
+        end += content[end..].chars().next().map(char::len_utf8).unwrap_or(1);
+    }
+    // ...and past any link or code span an edge would split
+    for (range_start, range_end) in protected_ranges(content) {
+        if range_start < start && start < range_end {
+            start = range_start;
+        }
+        if range_start < end && end < range_end {
+            end = range_end;
+        }
+    }
+
+    let mut excerpt = content[start..end].split_whitespace().collect::<Vec<_>>().join(" ");
+    if start > 0 {
+        excerpt.insert_str(0, "… ");
+    }
+    if end < content.len() && !content[end..].trim().is_empty() {
+        excerpt.push_str(" …");
+    }
+    excerpt
+}
+
+// Byte range of the first case-insensitive occurrence of `query` in `text`
+fn find_case_insensitive(text: &str, query: &str) -> Option<(usize, usize)> {
+    let query: Vec<char> = query.trim().chars().flat_map(char::to_lowercase).collect();
+    if query.is_empty() {
+        return None;
+    }
+    text.char_indices().find_map(|(i, _)| {
+        let mut wanted = query.iter();
+        let mut end = i;
+        let mut lowered = text[i..].char_indices().flat_map(|(j, c)| c.to_lowercase().map(move |l| (j, c, l)));
+        loop {
+            let Some(want) = wanted.next() else {
+                return Some((i, end));
+            };
+            let (j, c, l) = lowered.next()?;
+            if l != *want {
+                return None;
+            }
+            end = i + j + c.len_utf8();
+        }
+    })
+}
+
+// Byte ranges of `code spans` and [links](targets), which snippets shouldn't cut through.
+// Only spans within a single line are considered.
+fn protected_ranges(content: &str) -> Vec<(usize, usize)> {
+    let mut ranges = Vec::new();
+    let mut line_start = 0;
+    for line in content.split_inclusive('\n') {
+        let mut i = 0;
+        while i < line.len() {
+            let rest = &line[i..];
+            if let Some(code) = rest.strip_prefix('`') {
+                if let Some(close) = code.find('`') {
+                    ranges.push((line_start + i, line_start + i + close + 2));
+                    i += close + 2;
+                    continue;
+                }
+            } else if let Some(text) = rest.strip_prefix('[') {
+                // Link text has no brackets of its own, so a stray `[` earlier on the line
+                // doesn't stretch the range up to a later link
+                let link = text
+                    .find(['[', ']'])
+                    .filter(|&text_end| text[text_end..].starts_with("]("))
+                    .and_then(|text_end| text[text_end..].find(')').map(|close| 1 + text_end + close + 1));
+                if let Some(len) = link {
+                    ranges.push((line_start + i, line_start + i + len));
+                    i += len;
+                    continue;
+                }
+            }
+            i += rest.chars().next().map(char::len_utf8).unwrap_or(1);
+        }
+        line_start += line.len();
+    }
+    ranges
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn note(id: &str, content: &str, language: Option<&str>) -> Note {
+        Note {
+            id: id.to_string(),
+            title: String::new(),
+            content: content.to_string(),
+            language: language.map(str::to_string),
+            ..Default::default()
+        }
+    }
+
+    fn index(notes: &[Note]) -> TextIndex {
+        let mut index = TextIndex::new(DEFAULT_STOP_WORDS.iter().map(|word| word.to_string()).collect());
+        index.begin_rebuild();
+        let mut rebuilt = TextIndex::new(index.stop_words.clone());
+        notes.iter().for_each(|note| rebuilt.update(note));
+        index.finish_rebuild(rebuilt);
+        index
+    }
+
+    fn ids(candidates: Option<HashSet<String>>) -> Vec<String> {
+        let mut ids: Vec<String> = candidates.expect("index should narrow the search").into_iter().collect();
+        ids.sort();
+        ids
+    }
+
+    #[test]
+    fn tokenize_splits_on_punctuation_and_folds_case_and_accents() {
+        assert_eq!(tokenize("Hello, world! Caf\u{e9}-au-lait (it's)"), ["hello", "world", "cafe", "au", "lait", "it's"]);
+    }
+
+    #[test]
+    fn tokenize_keeps_numbers_whole() {
+        assert_eq!(tokenize("Pay 1,000 by 3.14 or 42"), ["pay", "1,000", "by", "3.14", "or", "42"]);
+    }
+
+    #[test]
+    fn tokenize_splits_cjk_per_character() {
+        assert_eq!(tokenize("\u{6771}\u{4eac}\u{3067}"), ["\u{6771}", "\u{4eac}", "\u{3067}"]);
+    }
+
+    #[test]
+    fn configured_stop_words_are_tokenized_like_text() {
+        let configured = ["The".to_string(), "Caf\u{e9}".to_string()];
+        let stop_words = stop_words_from(Some(&configured));
+        assert_eq!(stop_words, HashSet::from(["the".to_string(), "cafe".to_string()]));
+        assert_eq!(tokenize_without("The caf\u{e9} opens", &stop_words), ["opens"]);
+        assert!(stop_words_from(None).contains("the"));
+    }
+
+    #[test]
+    fn distinctive_terms_skip_stop_words() {
+        let all = [note("1", "recipe oven flour recipe", None), note("2", "budget taxes", None)];
+        let group = [&all[0]];
+        let stop_words = HashSet::from(["recipe".to_string()]);
+        let terms = distinctive_terms(&group, &all, 2, &stop_words);
+        assert_eq!(terms, ["flour", "oven"]);
+    }
+
+    #[test]
+    fn candidates_need_every_query_word() {
+        let index = index(&[note("a", "Grocery list: apples", None), note("b", "apples and pears", None)]);
+        assert_eq!(ids(index.candidates("apples pears")), ["b"]);
+        assert_eq!(ids(index.candidates("APPLES")), ["a", "b"]);
+    }
+
+    #[test]
+    fn candidates_match_inside_words() {
+        let index = index(&[note("a", "The deployment failed", None)]);
+        assert_eq!(ids(index.candidates("ploy")), ["a"]);
+        assert_eq!(ids(index.candidates("deployment fail")), ["a"]);
+    }
+
+    #[test]
+    fn candidates_match_across_accents_and_stems() {
+        let index = index(&[note("a", "Il pleut au café", Some("fra")), note("b", "She runs daily", Some("eng"))]);
+        assert_eq!(ids(index.candidates("cafe")), ["a"]);
+        assert_eq!(ids(index.candidates("running")), ["b"]);
+    }
+
+    #[test]
+    fn queries_inside_stop_words_check_every_note() {
+        let index = index(&[note("a", "the end", None)]);
+        assert!(index.candidates("th").is_none());
+        assert!(index.candidates("the").is_none());
+        assert!(index.candidates("!!").is_none());
+    }
+
+    #[test]
+    fn incomplete_index_checks_every_note() {
+        let mut index = TextIndex::new(HashSet::new());
+        index.update(&note("a", "apples", None));
+        assert!(index.candidates("apples").is_none());
+    }
+
+    #[test]
+    fn rebuild_replays_changes_made_meanwhile() {
+        let mut live = index(&[note("a", "old text", None), note("b", "doomed", None)]);
+        live.begin_rebuild();
+        // The rebuild read the notes before these changes reached the live index
+        let mut rebuilt = TextIndex::new(live.stop_words.clone());
+        rebuilt.update(&note("a", "old text", None));
+        rebuilt.update(&note("b", "doomed", None));
+        live.update(&note("a", "new text", None));
+        live.remove("b");
+        live.update(&note("c", "created during rebuild", None));
+        live.finish_rebuild(rebuilt);
+
+        assert_eq!(ids(live.candidates("new")), ["a"]);
+        assert!(ids(live.candidates("old")).is_empty());
+        assert!(ids(live.candidates("doomed")).is_empty());
+        assert_eq!(ids(live.candidates("created")), ["c"]);
+        assert_eq!(live.stats().documents, 2);
+        assert!(live.changed_during_rebuild.is_none());
+    }
+
+    #[test]
+    fn updates_replace_old_postings() {
+        let mut index = index(&[note("a", "first draft", Some("eng"))]);
+        index.update(&note("a", "second version", Some("eng")));
+        assert!(ids(index.candidates("draft")).is_empty());
+        assert_eq!(ids(index.candidates("version")), ["a"]);
+        index.remove("a");
+        assert_eq!(index.stats().tokens, 0);
+        assert!(index.stem_postings.is_empty());
+    }
+
+    #[test]
+    fn snippet_at_the_start_has_no_leading_ellipsis() {
+        let content = "Needle first, then a long run of words that goes on well past the width of the snippet";
+        let excerpt = snippet(content, "needle", 30);
+        assert!(excerpt.starts_with("Needle first"));
+        assert!(excerpt.ends_with(" …"));
+    }
+
+    #[test]
+    fn snippet_in_the_middle_is_cut_on_both_sides() {
+        let content = "one two three four five six seven eight needle nine ten eleven twelve thirteen fourteen";
+        let excerpt = snippet(content, "needle", 20);
+        assert!(excerpt.starts_with("… "));
+        assert!(excerpt.ends_with(" …"));
+        assert!(excerpt.contains("needle"));
+        // Whole words only
+        assert!(excerpt.trim_matches(|c| c == '…' || c == ' ').split(' ').all(|word| content.split(' ').any(|w| w == word)));
+    }
+
+    #[test]
+    fn snippet_at_the_end_takes_context_from_before() {
+        let content = "a long run of words that comes before the match at the very end: needle";
+        let excerpt = snippet(content, "needle", 30);
+        assert!(excerpt.starts_with("… "));
+        assert!(excerpt.ends_with("needle"));
+        assert!(excerpt.chars().count() >= 30);
+    }
+
+    #[test]
+    fn snippet_edges_skip_over_links_and_code() {
+        let content = "intro [a link with several words](https://example.com/page) needle `some code here` outro";
+        let excerpt = snippet(content, "needle", 16);
+        assert!(excerpt.contains("[a link with several words](https://example.com/page)"));
+        assert!(excerpt.contains("`some code here`"));
+    }
+
+    #[test]
+    fn stray_bracket_does_not_protect_up_to_a_later_link() {
+        let content = "a [stray bracket, then [link](x) and `code`";
+        let ranges = protected_ranges(content);
+        let link = content.find("[link").unwrap();
+        let code = content.find('`').unwrap();
+        assert_eq!(ranges, [(link, link + "[link](x)".len()), (code, code + "`code`".len())]);
+    }
+}
@@ -0,0 +1,96 @@
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::Instant;
+use tauri::ipc::Invoke;
+
+// How many recent command timings are kept for get_command_timings
+const MAX_TIMINGS: usize = 500;
+
+// One measured command invocation
+#[derive(Serialize, Clone)]
+pub struct CommandTiming {
+    pub command: String,
+    pub duration_ms: f64,
+    pub at: String,
+}
+
+static TIMINGS: Lazy<Mutex<VecDeque<CommandTiming>>> = Lazy::new(|| Mutex::new(VecDeque::with_capacity(MAX_TIMINGS)));
+
+// Commands declared with #[tauri::command(async)]. The handler returns as soon as one is
+// dispatched to a worker thread, so timed leaves them alone and they time themselves with
+// CommandTimer instead.
+const ASYNC_COMMANDS: &[&str] = &["search_notes_streaming", "benchmark_search", "rebuild_text_index"];
+
+// Wrap an invoke handler so every command is timed. For synchronous commands the handler
+// returning means the command has finished.
+pub fn timed<F>(handler: F) -> impl Fn(Invoke) -> bool + Send + Sync + 'static
+where
+    F: Fn(Invoke) -> bool + Send + Sync + 'static,
+{
+    move |invoke: Invoke| {
+        let command = invoke.message.command().to_string();
+        if ASYNC_COMMANDS.contains(&command.as_str()) {
+            return handler(invoke);
+        }
+        let start = Instant::now();
+        let handled = handler(invoke);
+        record(command, start.elapsed().as_secs_f64() * 1000.0);
+        handled
+    }
+}
+
+// Times an async command from inside its body; the duration is recorded when it's dropped
+pub struct CommandTimer {
+    command: &'static str,
+    start: Instant,
+}
+
+impl CommandTimer {
+    pub fn start(command: &'static str) -> Self {
+        CommandTimer { command, start: Instant::now() }
+    }
+}
+
+impl Drop for CommandTimer {
+    fn drop(&mut self) {
+        record(self.command.to_string(), self.start.elapsed().as_secs_f64() * 1000.0);
+    }
+}
+
+fn record(command: String, duration_ms: f64) {
+    if log::log_enabled!(log::Level::Debug) {
+        log::debug!("Command {} took {:.2}ms", command, duration_ms);
+    }
+    if let Ok(mut timings) = TIMINGS.lock() {
+        if timings.len() == MAX_TIMINGS {
+            timings.pop_front();
+        }
+        timings.push_back(CommandTiming {
+            command,
+            duration_ms,
+            at: crate::now_rfc3339(),
+        });
+    }
+}
+
+// Recent command timings, oldest first
+pub fn recent() -> Vec<CommandTiming> {
+    TIMINGS.lock().map(|timings| timings.iter().cloned().collect()).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timer_records_when_dropped() {
+        {
+            let _timer = CommandTimer::start("timer_records_when_dropped");
+            std::thread::sleep(std::time::Duration::from_millis(5));
+        }
+        let timing = recent().into_iter().rev().find(|timing| timing.command == "timer_records_when_dropped").unwrap();
+        assert!(timing.duration_ms >= 5.0);
+    }
+}